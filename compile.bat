@@ -1,3 +1,9 @@
 C:\VulkanSDK\1.3.290.0\Bin\glslc.exe vertexshader.vert -o vertexshader.spv
 C:\VulkanSDK\1.3.290.0\Bin\glslc.exe fragmentshader.frag -o fragmentshader.spv
+C:\VulkanSDK\1.3.290.0\Bin\glslc.exe fragmentshader_bindless.frag -o fragmentshader_bindless.spv
+C:\VulkanSDK\1.3.290.0\Bin\glslc.exe particles_simulate.comp -o particles_simulate.spv
+C:\VulkanSDK\1.3.290.0\Bin\glslc.exe particles_render.comp -o particles_render.spv
+C:\VulkanSDK\1.3.290.0\Bin\glslc.exe equirect_to_cube.comp -o equirect_to_cube.spv
+C:\VulkanSDK\1.3.290.0\Bin\glslc.exe skybox.vert -o skybox_vertex.spv
+C:\VulkanSDK\1.3.290.0\Bin\glslc.exe skybox.frag -o skybox_fragment.spv
 pause
\ No newline at end of file
@@ -0,0 +1,13 @@
+#version 450
+
+layout(binding = 0) uniform textureCube cubemap;
+layout(binding = 1) uniform sampler cubemapSampler;
+
+layout(location = 0) in vec3 fragDirection;
+
+layout(location = 0) out vec4 outColor;
+
+void main() {
+    // Cubemaps are sampled by direction so it doesn't need normalising
+    outColor = vec4(texture(samplerCube(cubemap, cubemapSampler), fragDirection).rgb, 1.0);
+}
@@ -0,0 +1,16 @@
+#version 450
+
+layout(push_constant) uniform SkyboxPushConstants {
+    vec2 viewScale;
+} push;
+
+layout(location = 0) out vec3 fragDirection;
+
+void main() {
+    // A triangle covering the whole screen, made from the vertex index so no vertex buffer is needed
+    vec2 position = vec2((gl_VertexIndex << 1) & 2, gl_VertexIndex & 2) * 2.0 - 1.0;
+    gl_Position = vec4(position, 0.0, 1.0);
+
+    // The direction the camera looks through the vertex, clip space y points down the screen but the sky's +Y is up
+    fragDirection = vec3(position.x * push.viewScale.x, -position.y * push.viewScale.y, 1.0);
+}
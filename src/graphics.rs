@@ -3,6 +3,25 @@ mod presentation;
 mod pipeline;
 pub(crate) mod vk_app;
 mod commands;
+mod allocator;
 mod buffers;
+mod barriers;
 mod textures;
+mod samplers;
+mod block_compression;
+mod texture_containers;
+mod image_loaders;
+mod cubemaps;
+mod skybox;
+mod streaming;
+mod texture_manager;
+mod upload;
+mod mesh;
+mod descriptors;
+mod bindless;
+mod compute;
+mod particles;
 mod errors;
+mod renderer;
+mod debug;
+pub(crate) mod report;
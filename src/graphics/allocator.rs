@@ -0,0 +1,796 @@
+use crate::graphics::debug::DebugUtils;
+use crate::graphics::device::SupportedPhysicalDevice;
+use crate::graphics::errors::VkAppError;
+use crate::graphics::vk_app::Result;
+use crate::warn;
+use ash::vk;
+use std::collections::BTreeMap;
+use std::ffi;
+use std::fmt::{Display, Formatter};
+
+/// Size of the device memory blocks that resources are sub-allocated from
+///
+/// Resources larger than a block get a dedicated block of their own
+const BLOCK_SIZE: vk::DeviceSize = 64 * 1024 * 1024;
+
+/// Buffers and linear images are sub-allocated from separate blocks to optimal images
+///
+/// Linear and optimal resources next to each other in the same memory must be bufferImageGranularity apart, which
+/// can be as large as a page. Keeping them in separate blocks means no padding is ever needed between neighbours
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ResourceKind
+{
+    Linear,
+    Optimal,
+}
+
+/// What an allocation is used for, so memory use can be broken down when looking for leaks or over-budget scenes
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum MemoryPurpose
+{
+    Vertex,
+    Index,
+    Uniform,
+    Texture,
+    Staging,
+    /// Buffers read and written by compute shaders
+    Storage,
+}
+
+/// A region of device memory sub-allocated from a block
+///
+/// Must be returned to the allocator with Allocator::free once the resource bound to it is destroyed
+pub struct Allocation
+{
+    pub memory:        vk::DeviceMemory,
+    pub offset:        vk::DeviceSize,
+    pub size:          vk::DeviceSize,
+    pub purpose:       MemoryPurpose,
+    memory_type_index: u32,
+    block_id:          u64,
+    /// Null if the memory is not host visible
+    mapped_ptr:        *mut ffi::c_void,
+}
+
+impl Allocation
+{
+    /// Host visible memory stays mapped for the lifetime of its block, so this is valid until the allocation is freed
+    pub fn mapped_ptr(&self) -> Option<*mut ffi::c_void>
+    {
+        if self.mapped_ptr.is_null() {
+            None
+        } else {
+            Some(self.mapped_ptr)
+        }
+    }
+}
+
+/// A range of unused memory in a block
+#[derive(Copy, Clone)]
+struct FreeRange
+{
+    offset: vk::DeviceSize,
+    size:   vk::DeviceSize,
+}
+
+/// One vkAllocateMemory allocation which resources of one kind are sub-allocated from
+struct MemoryBlock
+{
+    id:                u64,
+    memory:            vk::DeviceMemory,
+    memory_type_index: u32,
+    kind:              ResourceKind,
+    size:              vk::DeviceSize,
+    /// Created for a single resource larger than BLOCK_SIZE, freed as soon as that resource is
+    dedicated:         bool,
+    mapped_ptr:        *mut ffi::c_void,
+    /// Sorted by offset, neighbouring ranges are always merged
+    free_ranges:       Vec<FreeRange>,
+    allocation_count:  usize,
+}
+
+impl MemoryBlock
+{
+    /// First fit search for a free range that can hold size bytes at the given alignment, returns the offset if found
+    fn allocate(&mut self, size: vk::DeviceSize, alignment: vk::DeviceSize) -> Option<vk::DeviceSize>
+    {
+        let (index, offset) = self.free_ranges.iter().enumerate().find_map(|(index, range)| {
+            let offset = range.offset.next_multiple_of(alignment);
+            (offset + size <= range.offset + range.size).then_some((index, offset))
+        })?;
+
+        // Split the free range around the allocation, the padding before an aligned offset stays free
+        let range = self.free_ranges.remove(index);
+        let end = offset + size;
+        let range_end = range.offset + range.size;
+        if end < range_end {
+            self.free_ranges
+                .insert(index, FreeRange { offset: end, size: range_end - end });
+        }
+        if offset > range.offset {
+            self.free_ranges
+                .insert(index, FreeRange { offset: range.offset, size: offset - range.offset });
+        }
+
+        self.allocation_count += 1;
+        Some(offset)
+    }
+
+    /// Return a range to the block, merging it with any free neighbours
+    fn free(&mut self, offset: vk::DeviceSize, size: vk::DeviceSize)
+    {
+        let index = self.free_ranges.partition_point(|range| range.offset < offset);
+        self.free_ranges.insert(index, FreeRange { offset, size });
+
+        // Merge with the next range then the previous range
+        if index + 1 < self.free_ranges.len() && offset + size == self.free_ranges[index + 1].offset {
+            self.free_ranges[index].size += self.free_ranges.remove(index + 1).size;
+        }
+        if index > 0 {
+            let previous = self.free_ranges[index - 1];
+            if previous.offset + previous.size == offset {
+                self.free_ranges[index - 1].size += self.free_ranges.remove(index).size;
+            }
+        }
+
+        self.allocation_count -= 1;
+    }
+
+    /// Whether resources of the kind from the memory type can be sub-allocated from this block
+    fn accepts(&self, memory_type_index: u32, kind: ResourceKind) -> bool
+    {
+        self.memory_type_index == memory_type_index && self.kind == kind && !self.dedicated
+    }
+
+    fn used_bytes(&self) -> vk::DeviceSize
+    {
+        self.size - self.free_ranges.iter().map(|range| range.size).sum::<vk::DeviceSize>()
+    }
+}
+
+/// Totals over every block owned by the allocator
+#[derive(Copy, Clone, Default)]
+pub struct AllocatorStats
+{
+    pub block_count:      usize,
+    pub allocation_count: usize,
+    /// Memory allocated from the device for blocks
+    pub allocated_bytes:  vk::DeviceSize,
+    /// Memory in use by allocations, including alignment padding
+    pub used_bytes:       vk::DeviceSize,
+}
+
+impl Display for AllocatorStats
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result
+    {
+        write!(
+            f,
+            "{} allocations using {:.2} MiB of {:.2} MiB in {} device memory blocks",
+            self.allocation_count,
+            self.used_bytes as f64 / (1024.0 * 1024.0),
+            self.allocated_bytes as f64 / (1024.0 * 1024.0),
+            self.block_count
+        )
+    }
+}
+
+/// Allocations of one purpose from one memory type
+#[derive(Copy, Clone, Default)]
+struct PurposeUsage
+{
+    allocation_count: usize,
+    bytes:            vk::DeviceSize,
+}
+
+/// Memory use of a heap, along with the driver's budget for it when VK_EXT_memory_budget is available
+pub struct HeapSnapshot
+{
+    pub index:           u32,
+    pub size:            vk::DeviceSize,
+    pub flags:           vk::MemoryHeapFlags,
+    /// Memory allocated by us for blocks in this heap
+    pub allocated_bytes: vk::DeviceSize,
+    /// How much the process can allocate from the heap before allocations may fail or perform poorly
+    pub budget:          Option<vk::DeviceSize>,
+    /// The driver's view of the process' usage of the heap, including memory allocated outside of our allocator
+    pub driver_usage:    Option<vk::DeviceSize>,
+}
+
+pub struct MemoryTypeSnapshot
+{
+    pub index:           u32,
+    pub heap_index:      u32,
+    pub property_flags:  vk::MemoryPropertyFlags,
+    pub block_count:     usize,
+    pub allocated_bytes: vk::DeviceSize,
+    /// Memory in use by allocations, including alignment padding
+    pub used_bytes:      vk::DeviceSize,
+}
+
+pub struct PurposeSnapshot
+{
+    pub purpose:           MemoryPurpose,
+    pub memory_type_index: u32,
+    pub allocation_count:  usize,
+    pub bytes:             vk::DeviceSize,
+}
+
+/// Memory use broken down by heap, memory type and purpose at the time it was taken
+///
+/// Only memory types with blocks and purposes with allocations are included
+pub struct MemorySnapshot
+{
+    pub heaps:        Vec<HeapSnapshot>,
+    pub memory_types: Vec<MemoryTypeSnapshot>,
+    pub purposes:     Vec<PurposeSnapshot>,
+}
+
+impl Display for MemorySnapshot
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result
+    {
+        let mib = |bytes: vk::DeviceSize| bytes as f64 / (1024.0 * 1024.0);
+
+        for heap in &self.heaps {
+            write!(
+                f,
+                "Heap {} ({:?}, {:.2} MiB): {:.2} MiB allocated",
+                heap.index,
+                heap.flags,
+                mib(heap.size),
+                mib(heap.allocated_bytes)
+            )?;
+            if let (Some(budget), Some(driver_usage)) = (heap.budget, heap.driver_usage) {
+                write!(
+                    f,
+                    ", driver reports {:.2} MiB used of {:.2} MiB budget",
+                    mib(driver_usage),
+                    mib(budget)
+                )?;
+                if driver_usage > budget {
+                    write!(f, " (over budget)")?;
+                }
+            }
+            writeln!(f)?;
+        }
+        for memory_type in &self.memory_types {
+            writeln!(
+                f,
+                "  Memory type {} (heap {}, {:?}): {:.2} MiB used of {:.2} MiB in {} blocks",
+                memory_type.index,
+                memory_type.heap_index,
+                memory_type.property_flags,
+                mib(memory_type.used_bytes),
+                mib(memory_type.allocated_bytes),
+                memory_type.block_count
+            )?;
+            for purpose in self
+                .purposes
+                .iter()
+                .filter(|purpose| purpose.memory_type_index == memory_type.index)
+            {
+                writeln!(
+                    f,
+                    "    {:?}: {} allocations, {:.2} MiB",
+                    purpose.purpose,
+                    purpose.allocation_count,
+                    mib(purpose.bytes)
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Allocates large blocks of device memory per memory type and sub-allocates buffers and images from them
+///
+/// Devices limit the number of simultaneous vkAllocateMemory allocations (maxMemoryAllocationCount), which can be as low as 4096
+pub struct Allocator
+{
+    instance: ash::Instance,
+    physical_device: vk::PhysicalDevice,
+    device: ash::Device,
+    debug_utils: DebugUtils,
+    memory_properties: vk::PhysicalDeviceMemoryProperties,
+    max_memory_allocation_count: u32,
+    /// VK_EXT_memory_budget is enabled so the driver can be asked for each heap's budget
+    memory_budget: bool,
+    blocks: Vec<MemoryBlock>,
+    next_block_id: u64,
+    /// Live allocations by memory type and purpose
+    purpose_usage: BTreeMap<(u32, MemoryPurpose), PurposeUsage>,
+}
+
+impl Allocator
+{
+    pub fn new(
+        instance: &ash::Instance, physical_device: &SupportedPhysicalDevice, device: &ash::Device, debug_utils: &DebugUtils,
+    ) -> Self
+    {
+        let vk_physical_device = physical_device.vk_physical_device;
+        // memory_properties contains the memory heaps from which GPU memory can be allocated (e.g dedicated VRAM, swap space in RAM)
+        let memory_properties = unsafe { instance.get_physical_device_memory_properties(vk_physical_device) };
+        let properties = unsafe { instance.get_physical_device_properties(vk_physical_device) };
+        Self {
+            instance: instance.clone(),
+            physical_device: vk_physical_device,
+            device: device.clone(),
+            debug_utils: debug_utils.clone(),
+            memory_properties,
+            max_memory_allocation_count: properties.limits.max_memory_allocation_count,
+            memory_budget: physical_device.memory_budget,
+            blocks: Vec::new(),
+            next_block_id: 0,
+            purpose_usage: BTreeMap::new(),
+        }
+    }
+
+    /// Graphics cards have different types of memory to allocate from
+    ///
+    /// Each type varies in allowed operations and performance
+    pub fn find_memory_type(&self, type_filter: u32, properties: vk::MemoryPropertyFlags) -> Result<u32>
+    {
+        self.memory_properties
+            .memory_types_as_slice()
+            .iter()
+            .zip(0u32..)
+            // Check if memory type is allowed for the resource and has the properties we want
+            .find(|(memory_type, index)| type_filter & (1 << index) != 0 && memory_type.property_flags.contains(properties))
+            .map(|(_, index)| index)
+            .ok_or_else(|| VkAppError::DeviceError(String::from("Failed to find suitable memory type")))
+    }
+
+    /// Allocate memory for a buffer and bind the buffer to it
+    pub fn allocate_buffer(
+        &mut self, buffer: vk::Buffer, properties: vk::MemoryPropertyFlags, purpose: MemoryPurpose,
+    ) -> Result<Allocation>
+    {
+        let memory_requirements = unsafe { self.device.get_buffer_memory_requirements(buffer) };
+        let allocation = self.allocate(memory_requirements, properties, ResourceKind::Linear, purpose)?;
+        if let Err(err) = unsafe { self.device.bind_buffer_memory(buffer, allocation.memory, allocation.offset) } {
+            self.free(&allocation);
+            return Err(err.into());
+        }
+        Ok(allocation)
+    }
+
+    /// Allocate memory for an image with optimal tiling and bind the image to it
+    pub fn allocate_image(
+        &mut self, image: vk::Image, properties: vk::MemoryPropertyFlags, purpose: MemoryPurpose,
+    ) -> Result<Allocation>
+    {
+        let memory_requirements = unsafe { self.device.get_image_memory_requirements(image) };
+        let allocation = self.allocate(memory_requirements, properties, ResourceKind::Optimal, purpose)?;
+        if let Err(err) = unsafe { self.device.bind_image_memory(image, allocation.memory, allocation.offset) } {
+            self.free(&allocation);
+            return Err(err.into());
+        }
+        Ok(allocation)
+    }
+
+    /// Sub-allocate memory meeting the requirements from an existing block, or a new block if none have space
+    pub fn allocate(
+        &mut self, memory_requirements: vk::MemoryRequirements, properties: vk::MemoryPropertyFlags, kind: ResourceKind,
+        purpose: MemoryPurpose,
+    ) -> Result<Allocation>
+    {
+        let memory_type_index = self.find_memory_type(memory_requirements.memory_type_bits, properties)?;
+        let size = memory_requirements.size;
+        let alignment = memory_requirements.alignment.max(1);
+
+        let existing = self
+            .blocks
+            .iter_mut()
+            .filter(|block| block.accepts(memory_type_index, kind))
+            .find_map(|block| {
+                let offset = block.allocate(size, alignment)?;
+                Some(Allocation {
+                    memory: block.memory,
+                    offset,
+                    size,
+                    purpose,
+                    memory_type_index,
+                    block_id: block.id,
+                    mapped_ptr: mapped_ptr_at(block.mapped_ptr, offset),
+                })
+            });
+        if let Some(allocation) = existing {
+            self.track(&allocation);
+            return Ok(allocation);
+        }
+
+        // Small heaps, such as the host visible part of VRAM, would be used up by a few blocks
+        let heap_index = self.memory_properties.memory_types[memory_type_index as usize].heap_index;
+        let block_size = BLOCK_SIZE.min(self.memory_properties.memory_heaps[heap_index as usize].size / 8);
+        let dedicated = size > block_size;
+
+        let mut block = self.create_block(memory_type_index, kind, if dedicated { size } else { block_size }, dedicated)?;
+        let offset = block
+            .allocate(size, alignment)
+            .expect("A new block must have space for the allocation it was created for");
+        let allocation = Allocation {
+            memory: block.memory,
+            offset,
+            size,
+            purpose,
+            memory_type_index,
+            block_id: block.id,
+            mapped_ptr: mapped_ptr_at(block.mapped_ptr, offset),
+        };
+        self.blocks.push(block);
+        self.track(&allocation);
+
+        Ok(allocation)
+    }
+
+    /// Return an allocation to its block, the resource bound to it must already be destroyed
+    ///
+    /// Empty blocks are freed, except one per memory type and resource kind which is kept to avoid reallocating
+    pub fn free(&mut self, allocation: &Allocation)
+    {
+        let Some(index) = self.blocks.iter().position(|block| block.id == allocation.block_id) else {
+            warn!("Freeing allocation from unknown memory block {}", allocation.block_id);
+            return;
+        };
+
+        if let Some(usage) = self
+            .purpose_usage
+            .get_mut(&(allocation.memory_type_index, allocation.purpose))
+        {
+            usage.allocation_count -= 1;
+            usage.bytes -= allocation.size;
+        }
+
+        let block = &mut self.blocks[index];
+        block.free(allocation.offset, allocation.size);
+        if block.allocation_count > 0 {
+            return;
+        }
+
+        let (memory_type_index, kind) = (block.memory_type_index, block.kind);
+        let other_empty_block = self.blocks.iter().any(|other| {
+            other.id != allocation.block_id
+                && !other.dedicated
+                && other.allocation_count == 0
+                && other.memory_type_index == memory_type_index
+                && other.kind == kind
+        });
+        if self.blocks[index].dedicated || other_empty_block {
+            let block = self.blocks.swap_remove(index);
+            unsafe { self.device.free_memory(block.memory, None) };
+        }
+    }
+
+    pub fn stats(&self) -> AllocatorStats
+    {
+        self.blocks
+            .iter()
+            .fold(AllocatorStats::default(), |stats, block| AllocatorStats {
+                block_count:      stats.block_count + 1,
+                allocation_count: stats.allocation_count + block.allocation_count,
+                allocated_bytes:  stats.allocated_bytes + block.size,
+                used_bytes:       stats.used_bytes + block.used_bytes(),
+            })
+    }
+
+    /// Memory use by heap, memory type and purpose, including the driver's budget for each heap if available
+    pub fn snapshot(&self) -> MemorySnapshot
+    {
+        let budget = self.query_budget();
+
+        let memory_types: Vec<MemoryTypeSnapshot> = self
+            .memory_properties
+            .memory_types_as_slice()
+            .iter()
+            .zip(0u32..)
+            .filter_map(|(memory_type, index)| {
+                let blocks = self.blocks.iter().filter(|block| block.memory_type_index == index);
+                let (block_count, allocated_bytes, used_bytes) = blocks.fold((0, 0, 0), |totals, block| {
+                    (totals.0 + 1, totals.1 + block.size, totals.2 + block.used_bytes())
+                });
+                (block_count > 0).then_some(MemoryTypeSnapshot {
+                    index,
+                    heap_index: memory_type.heap_index,
+                    property_flags: memory_type.property_flags,
+                    block_count,
+                    allocated_bytes,
+                    used_bytes,
+                })
+            })
+            .collect();
+
+        let heaps = self
+            .memory_properties
+            .memory_heaps_as_slice()
+            .iter()
+            .zip(0u32..)
+            .map(|(heap, index)| HeapSnapshot {
+                index,
+                size: heap.size,
+                flags: heap.flags,
+                allocated_bytes: memory_types
+                    .iter()
+                    .filter(|memory_type| memory_type.heap_index == index)
+                    .map(|memory_type| memory_type.allocated_bytes)
+                    .sum(),
+                budget: budget.map(|budget| budget.heap_budget[index as usize]),
+                driver_usage: budget.map(|budget| budget.heap_usage[index as usize]),
+            })
+            .collect();
+
+        let purposes = self
+            .purpose_usage
+            .iter()
+            .filter(|(_, usage)| usage.allocation_count > 0)
+            .map(|(&(memory_type_index, purpose), usage)| PurposeSnapshot {
+                purpose,
+                memory_type_index,
+                allocation_count: usage.allocation_count,
+                bytes: usage.bytes,
+            })
+            .collect();
+
+        MemorySnapshot { heaps, memory_types, purposes }
+    }
+
+    /// Free every block, all allocations must have been freed first
+    pub fn cleanup(&mut self)
+    {
+        let stats = self.stats();
+        if stats.allocation_count > 0 {
+            warn!(
+                "Destroying allocator with {} allocations still in use",
+                stats.allocation_count
+            );
+            for (&(memory_type_index, purpose), usage) in &self.purpose_usage {
+                if usage.allocation_count > 0 {
+                    warn!(
+                        "Leaked {} {:?} allocations of {} bytes from memory type {}",
+                        usage.allocation_count, purpose, usage.bytes, memory_type_index
+                    );
+                }
+            }
+        }
+        for block in self.blocks.drain(..) {
+            // Freeing memory implicitly unmaps it
+            unsafe { self.device.free_memory(block.memory, None) };
+        }
+    }
+
+    fn create_block(
+        &mut self, memory_type_index: u32, kind: ResourceKind, size: vk::DeviceSize, dedicated: bool,
+    ) -> Result<MemoryBlock>
+    {
+        if self.blocks.len() as u32 >= self.max_memory_allocation_count {
+            return Err(VkAppError::DeviceError(format!(
+                "Reached the device's limit of {} memory allocations",
+                self.max_memory_allocation_count
+            )));
+        }
+
+        let heap_index = self.memory_properties.memory_types[memory_type_index as usize].heap_index as usize;
+        if let Some(budget) = self.query_budget() {
+            if budget.heap_usage[heap_index] + size > budget.heap_budget[heap_index] {
+                warn!(
+                    "Allocating {} bytes puts heap {} over its budget, {} of {} bytes are already in use",
+                    size, heap_index, budget.heap_usage[heap_index], budget.heap_budget[heap_index]
+                );
+            }
+        }
+
+        let memory_allocate_info = vk::MemoryAllocateInfo::default()
+            .allocation_size(size)
+            .memory_type_index(memory_type_index);
+        let memory = unsafe { self.device.allocate_memory(&memory_allocate_info, None) }?;
+
+        // Host visible blocks stay mapped for their whole lifetime so allocations from them can be written to at any time
+        let host_visible = self.memory_properties.memory_types[memory_type_index as usize]
+            .property_flags
+            .contains(vk::MemoryPropertyFlags::HOST_VISIBLE);
+        let mapped_ptr = if host_visible {
+            match unsafe { self.device.map_memory(memory, 0, vk::WHOLE_SIZE, vk::MemoryMapFlags::empty()) } {
+                Ok(mapped_ptr) => mapped_ptr,
+                Err(err) => {
+                    unsafe { self.device.free_memory(memory, None) };
+                    return Err(err.into());
+                }
+            }
+        } else {
+            std::ptr::null_mut()
+        };
+
+        let id = self.next_block_id;
+        self.next_block_id += 1;
+        self.debug_utils.set_name(
+            memory,
+            format!("Memory block {} ({:?}, memory type {})", id, kind, memory_type_index).as_str(),
+        );
+
+        Ok(MemoryBlock {
+            id,
+            memory,
+            memory_type_index,
+            kind,
+            size,
+            dedicated,
+            mapped_ptr,
+            free_ranges: vec![FreeRange { offset: 0, size }],
+            allocation_count: 0,
+        })
+    }
+
+    fn track(&mut self, allocation: &Allocation)
+    {
+        let usage = self
+            .purpose_usage
+            .entry((allocation.memory_type_index, allocation.purpose))
+            .or_default();
+        usage.allocation_count += 1;
+        usage.bytes += allocation.size;
+    }
+
+    /// Ask the driver for each heap's budget and the process' current usage of it, if VK_EXT_memory_budget is enabled
+    ///
+    /// The values change as other processes allocate memory so they are queried each time rather than cached
+    fn query_budget(&self) -> Option<vk::PhysicalDeviceMemoryBudgetPropertiesEXT<'static>>
+    {
+        if !self.memory_budget {
+            return None;
+        }
+        let mut budget = vk::PhysicalDeviceMemoryBudgetPropertiesEXT::default();
+        let mut memory_properties = vk::PhysicalDeviceMemoryProperties2::default().push_next(&mut budget);
+        unsafe {
+            self.instance
+                .get_physical_device_memory_properties2(self.physical_device, &mut memory_properties)
+        };
+        Some(budget)
+    }
+}
+
+fn mapped_ptr_at(block_mapped_ptr: *mut ffi::c_void, offset: vk::DeviceSize) -> *mut ffi::c_void
+{
+    if block_mapped_ptr.is_null() {
+        block_mapped_ptr
+    } else {
+        unsafe { block_mapped_ptr.add(offset as usize) }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn block(size: vk::DeviceSize, kind: ResourceKind) -> MemoryBlock
+    {
+        MemoryBlock {
+            id: 0,
+            memory: vk::DeviceMemory::null(),
+            memory_type_index: 0,
+            kind,
+            size,
+            dedicated: false,
+            mapped_ptr: std::ptr::null_mut(),
+            free_ranges: vec![FreeRange { offset: 0, size }],
+            allocation_count: 0,
+        }
+    }
+
+    fn free_ranges(block: &MemoryBlock) -> Vec<(vk::DeviceSize, vk::DeviceSize)>
+    {
+        block.free_ranges.iter().map(|range| (range.offset, range.size)).collect()
+    }
+
+    #[test]
+    fn allocations_are_packed_first_fit()
+    {
+        let mut block = block(1024, ResourceKind::Linear);
+        assert_eq!(block.allocate(256, 1), Some(0));
+        assert_eq!(block.allocate(256, 1), Some(256));
+        assert_eq!(free_ranges(&block), vec![(512, 512)]);
+        assert_eq!(block.allocation_count, 2);
+        assert_eq!(block.used_bytes(), 512);
+    }
+
+    #[test]
+    fn allocations_that_dont_fit_fail()
+    {
+        let mut block = block(1024, ResourceKind::Linear);
+        assert_eq!(block.allocate(1025, 1), None);
+        assert_eq!(block.allocate(1024, 1), Some(0));
+        assert_eq!(block.allocate(1, 1), None);
+        assert!(block.free_ranges.is_empty());
+    }
+
+    #[test]
+    fn alignment_padding_stays_free()
+    {
+        let mut block = block(1024, ResourceKind::Linear);
+        assert_eq!(block.allocate(100, 1), Some(0));
+        assert_eq!(block.allocate(100, 256), Some(256));
+        assert_eq!(free_ranges(&block), vec![(100, 156), (356, 668)]);
+
+        // The padding is used by a later allocation small enough to fit in it
+        assert_eq!(block.allocate(64, 4), Some(100));
+        assert_eq!(free_ranges(&block), vec![(164, 92), (356, 668)]);
+    }
+
+    #[test]
+    fn aligned_allocations_skip_ranges_too_small_once_padded()
+    {
+        let mut block = block(1024, ResourceKind::Linear);
+        assert_eq!(block.allocate(8, 1), Some(0));
+        assert_eq!(block.allocate(120, 1), Some(8));
+        assert_eq!(block.allocate(100, 1), Some(128));
+        block.free(8, 120);
+
+        // 120 bytes are free from offset 8, but only 64 once aligned to 64
+        assert_eq!(block.allocate(100, 64), Some(256));
+        assert_eq!(free_ranges(&block), vec![(8, 120), (228, 28), (356, 668)]);
+    }
+
+    #[test]
+    fn freed_holes_are_reused()
+    {
+        let mut block = block(1024, ResourceKind::Linear);
+        let offsets: Vec<_> = (0..4).map(|_| block.allocate(256, 1).unwrap()).collect();
+        block.free(offsets[1], 256);
+        assert_eq!(free_ranges(&block), vec![(256, 256)]);
+        assert_eq!(block.allocate(128, 1), Some(256));
+        assert_eq!(block.allocate(128, 1), Some(384));
+        assert_eq!(block.allocate(1, 1), None);
+    }
+
+    #[test]
+    fn freed_ranges_merge_with_free_neighbours()
+    {
+        let mut block = block(1024, ResourceKind::Linear);
+        let offsets: Vec<_> = (0..4).map(|_| block.allocate(256, 1).unwrap()).collect();
+
+        block.free(offsets[0], 256);
+        block.free(offsets[2], 256);
+        assert_eq!(free_ranges(&block), vec![(0, 256), (512, 256)]);
+
+        // Merges with both the previous and next ranges
+        block.free(offsets[1], 256);
+        assert_eq!(free_ranges(&block), vec![(0, 768)]);
+
+        block.free(offsets[3], 256);
+        assert_eq!(free_ranges(&block), vec![(0, 1024)]);
+        assert_eq!(block.allocation_count, 0);
+        assert_eq!(block.used_bytes(), 0);
+        assert_eq!(block.allocate(1024, 1), Some(0));
+    }
+
+    #[test]
+    fn freed_ranges_merge_with_the_next_range()
+    {
+        let mut block = block(1024, ResourceKind::Linear);
+        let first = block.allocate(256, 1).unwrap();
+        let second = block.allocate(256, 1).unwrap();
+        block.free(second, 256);
+        assert_eq!(free_ranges(&block), vec![(256, 768)]);
+        block.free(first, 256);
+        assert_eq!(free_ranges(&block), vec![(0, 1024)]);
+    }
+
+    #[test]
+    fn linear_and_optimal_resources_use_separate_blocks()
+    {
+        let linear = block(1024, ResourceKind::Linear);
+        let optimal = block(1024, ResourceKind::Optimal);
+        assert!(linear.accepts(0, ResourceKind::Linear));
+        assert!(!linear.accepts(0, ResourceKind::Optimal));
+        assert!(optimal.accepts(0, ResourceKind::Optimal));
+        assert!(!optimal.accepts(0, ResourceKind::Linear));
+        assert!(!linear.accepts(1, ResourceKind::Linear));
+    }
+
+    #[test]
+    fn dedicated_blocks_are_not_shared()
+    {
+        let dedicated = MemoryBlock { dedicated: true, ..block(1024, ResourceKind::Linear) };
+        assert!(!dedicated.accepts(0, ResourceKind::Linear));
+    }
+}
@@ -0,0 +1,201 @@
+use crate::graphics::errors::VkAppError;
+use crate::graphics::vk_app::Result;
+use ash::vk;
+use std::ops::Range;
+
+/// A transition of some of an image's mip levels and layers, of some of its aspects, from one layout to another
+///
+/// The stages and accesses waited on and made to wait are derived from the layouts, see cmd_transition_images
+#[derive(Copy, Clone, Debug)]
+pub struct ImageTransition
+{
+    pub image:      vk::Image,
+    pub old_layout: vk::ImageLayout,
+    pub new_layout: vk::ImageLayout,
+    pub range:      vk::ImageSubresourceRange,
+}
+
+impl ImageTransition
+{
+    /// Transition every mip level and layer of the aspects, narrowed with mips and layers
+    pub fn new(
+        image: vk::Image, aspect_mask: vk::ImageAspectFlags, old_layout: vk::ImageLayout, new_layout: vk::ImageLayout,
+    ) -> Self
+    {
+        Self {
+            image,
+            old_layout,
+            new_layout,
+            range: vk::ImageSubresourceRange::default()
+                .aspect_mask(aspect_mask)
+                .base_mip_level(0)
+                .level_count(vk::REMAINING_MIP_LEVELS)
+                .base_array_layer(0)
+                .layer_count(vk::REMAINING_ARRAY_LAYERS),
+        }
+    }
+
+    /// Transition every mip level and layer of a colour image
+    pub fn colour(image: vk::Image, old_layout: vk::ImageLayout, new_layout: vk::ImageLayout) -> Self
+    {
+        Self::new(image, vk::ImageAspectFlags::COLOR, old_layout, new_layout)
+    }
+
+    /// Only transition these mip levels
+    pub fn mips(mut self, mips: Range<u32>) -> Self
+    {
+        self.range.base_mip_level = mips.start;
+        self.range.level_count = mips.len() as u32;
+        self
+    }
+
+    /// Only transition these array layers, or cubemap faces
+    pub fn layers(mut self, layers: Range<u32>) -> Self
+    {
+        self.range.base_array_layer = layers.start;
+        self.range.layer_count = layers.len() as u32;
+        self
+    }
+}
+
+/// The stages that access an image in a layout, and the accesses which must finish before leaving the layout and which
+/// must wait for entering it
+struct LayoutUsage
+{
+    stages:     vk::PipelineStageFlags,
+    src_access: vk::AccessFlags,
+    dst_access: vk::AccessFlags,
+}
+
+fn layout_usage(layout: vk::ImageLayout) -> Option<LayoutUsage>
+{
+    let (stages, src_access, dst_access) = match layout {
+        // The contents are discarded so there's nothing to wait for, an image can't be transitioned to these
+        vk::ImageLayout::UNDEFINED => (
+            vk::PipelineStageFlags::TOP_OF_PIPE,
+            vk::AccessFlags::empty(),
+            vk::AccessFlags::empty(),
+        ),
+        vk::ImageLayout::PREINITIALIZED => (
+            vk::PipelineStageFlags::HOST,
+            vk::AccessFlags::HOST_WRITE,
+            vk::AccessFlags::empty(),
+        ),
+        // Used for storage images and for anything else, so any shader or transfer could read or write the image
+        vk::ImageLayout::GENERAL => (
+            vk::PipelineStageFlags::FRAGMENT_SHADER
+                | vk::PipelineStageFlags::COMPUTE_SHADER
+                | vk::PipelineStageFlags::TRANSFER,
+            vk::AccessFlags::SHADER_WRITE | vk::AccessFlags::TRANSFER_WRITE,
+            vk::AccessFlags::SHADER_READ
+                | vk::AccessFlags::SHADER_WRITE
+                | vk::AccessFlags::TRANSFER_READ
+                | vk::AccessFlags::TRANSFER_WRITE,
+        ),
+        vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL => (
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            vk::AccessFlags::COLOR_ATTACHMENT_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+        ),
+        vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL | vk::ImageLayout::DEPTH_ATTACHMENT_OPTIMAL => (
+            vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+            vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+        ),
+        // Tested against and sampled, e.g. a shadow map, but not written
+        vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL | vk::ImageLayout::DEPTH_READ_ONLY_OPTIMAL => (
+            vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS
+                | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS
+                | vk::PipelineStageFlags::FRAGMENT_SHADER,
+            vk::AccessFlags::empty(),
+            vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ | vk::AccessFlags::SHADER_READ,
+        ),
+        // Textures are read by fragment shaders when drawing and by compute shaders when converting them
+        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL => (
+            vk::PipelineStageFlags::FRAGMENT_SHADER | vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::AccessFlags::empty(),
+            vk::AccessFlags::SHADER_READ,
+        ),
+        vk::ImageLayout::TRANSFER_SRC_OPTIMAL => (
+            vk::PipelineStageFlags::TRANSFER,
+            vk::AccessFlags::empty(),
+            vk::AccessFlags::TRANSFER_READ,
+        ),
+        vk::ImageLayout::TRANSFER_DST_OPTIMAL => (
+            vk::PipelineStageFlags::TRANSFER,
+            vk::AccessFlags::TRANSFER_WRITE,
+            vk::AccessFlags::TRANSFER_WRITE,
+        ),
+        // An acquired image is waited for by a semaphore at the colour attachment stage, so leaving the layout waits on
+        // that stage. Presentation is synchronised by the render finished semaphore, so nothing waits on entering it
+        vk::ImageLayout::PRESENT_SRC_KHR => (
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            vk::AccessFlags::empty(),
+            vk::AccessFlags::empty(),
+        ),
+        _ => return None,
+    };
+
+    Some(LayoutUsage { stages, src_access, dst_access })
+}
+
+/// Record a barrier transitioning each image's subresources into command_buffer, which can be any command buffer
+/// being recorded, e.g. a frame's or the upload context's
+///
+/// Each transition waits for the accesses the old layout is used for to finish, and makes the accesses the new layout is
+/// used for wait for the transition. Reads don't have to finish being made visible, only writes. Transitions from
+/// UNDEFINED discard the contents. The source and destination stages of every transition are combined into one barrier
+pub fn cmd_transition_images(
+    device: &ash::Device, command_buffer: vk::CommandBuffer, transitions: &[ImageTransition],
+) -> Result<()>
+{
+    let mut src_stages = vk::PipelineStageFlags::empty();
+    let mut dst_stages = vk::PipelineStageFlags::empty();
+    let mut barriers = Vec::with_capacity(transitions.len());
+
+    for transition in transitions {
+        let unsupported = || {
+            VkAppError::DeviceError(format!(
+                "Unsupported layout transition from {:?} to {:?}",
+                transition.old_layout, transition.new_layout
+            ))
+        };
+        if transition.new_layout == vk::ImageLayout::UNDEFINED || transition.new_layout == vk::ImageLayout::PREINITIALIZED {
+            return Err(unsupported());
+        }
+        let old_usage = layout_usage(transition.old_layout).ok_or_else(unsupported)?;
+        let new_usage = layout_usage(transition.new_layout).ok_or_else(unsupported)?;
+
+        src_stages |= old_usage.stages;
+        dst_stages |= new_usage.stages;
+        barriers.push(
+            vk::ImageMemoryBarrier::default()
+                .old_layout(transition.old_layout)
+                .new_layout(transition.new_layout)
+                .src_access_mask(old_usage.src_access)
+                .dst_access_mask(new_usage.dst_access)
+                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .image(transition.image)
+                .subresource_range(transition.range),
+        );
+    }
+
+    if barriers.is_empty() {
+        return Ok(());
+    }
+
+    unsafe {
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            src_stages,
+            dst_stages,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &barriers,
+        )
+    };
+
+    Ok(())
+}
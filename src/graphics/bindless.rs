@@ -0,0 +1,217 @@
+use crate::graphics::debug::DebugUtils;
+use crate::graphics::descriptors::DescriptorLayoutCache;
+use crate::graphics::device::{LogicalDevice, SupportedPhysicalDevice};
+use crate::graphics::errors::VkAppError;
+use crate::graphics::vk_app::Result;
+use crate::log;
+use ash::vk;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// The most textures the bindless array holds, lowered to the device's update after bind limits
+const MAX_BINDLESS_TEXTURES: u32 = 4096;
+/// The most samplers the bindless sampler array holds, few are needed as a sampler can be used with any texture
+const MAX_BINDLESS_SAMPLERS: u32 = 16;
+
+/// The set index the bindless set is bound at, after the frame's set
+pub const BINDLESS_SET: u32 = 1;
+
+/// One descriptor set holding every texture and sampler, which draws select by index rather than binding a set each
+///
+/// The arrays are partially bound so only the slots that have been filled may be read, and update after bind so new
+/// slots can be filled while frames using the set are in flight. A texture's slot is only refilled once it has been
+/// freed, which must wait until no pending frame could be reading it
+pub struct BindlessTextures
+{
+    device:             Rc<LogicalDevice>,
+    pool:               vk::DescriptorPool,
+    /// Owned by the layout cache the set was created with
+    pub set_layout:     vk::DescriptorSetLayout,
+    pub set:            vk::DescriptorSet,
+    texture_capacity:   u32,
+    /// Slots from texture_count up have never been filled
+    texture_count:      u32,
+    /// Slots below texture_count whose textures have been freed, reused before new slots
+    free_texture_slots: Vec<u32>,
+    sampler_capacity:   u32,
+    /// The slot of each sampler added, samplers keep their slots as they live as long as the sampler cache
+    sampler_slots:      HashMap<vk::Sampler, u32>,
+}
+
+impl Drop for BindlessTextures
+{
+    fn drop(&mut self)
+    {
+        // Destroying the pool frees the set
+        unsafe { self.device.destroy_descriptor_pool(self.pool, None) };
+    }
+}
+
+impl BindlessTextures
+{
+    /// Create the bindless set with as many slots as the device's update after bind limits allow
+    ///
+    /// The device must have been created with descriptor indexing
+    pub fn new(
+        instance: &ash::Instance, physical_device: &SupportedPhysicalDevice, device: &Rc<LogicalDevice>,
+        debug_utils: &DebugUtils, layout_cache: &mut DescriptorLayoutCache,
+    ) -> Result<Self>
+    {
+        // The extension's properties struct has the same members as the descriptor indexing part of the 1.2 properties
+        let mut descriptor_indexing_properties = vk::PhysicalDeviceDescriptorIndexingProperties::default();
+        {
+            let mut properties = vk::PhysicalDeviceProperties2::default().push_next(&mut descriptor_indexing_properties);
+            unsafe { instance.get_physical_device_properties2(physical_device.vk_physical_device, &mut properties) };
+        }
+        let texture_capacity = MAX_BINDLESS_TEXTURES
+            .min(descriptor_indexing_properties.max_per_stage_descriptor_update_after_bind_sampled_images)
+            .min(descriptor_indexing_properties.max_descriptor_set_update_after_bind_sampled_images);
+        let sampler_capacity = MAX_BINDLESS_SAMPLERS
+            .min(descriptor_indexing_properties.max_per_stage_descriptor_update_after_bind_samplers)
+            .min(descriptor_indexing_properties.max_descriptor_set_update_after_bind_samplers);
+        log!(
+            "Bindless textures with {} texture slots and {} sampler slots",
+            texture_capacity,
+            sampler_capacity
+        );
+
+        let bindings = [
+            vk::DescriptorSetLayoutBinding::default()
+                .binding(0)
+                .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+                .descriptor_count(texture_capacity)
+                .stage_flags(vk::ShaderStageFlags::FRAGMENT),
+            vk::DescriptorSetLayoutBinding::default()
+                .binding(1)
+                .descriptor_type(vk::DescriptorType::SAMPLER)
+                .descriptor_count(sampler_capacity)
+                .stage_flags(vk::ShaderStageFlags::FRAGMENT),
+        ];
+        // UPDATE_UNUSED_WHILE_PENDING allows filling slots that frames in flight don't read
+        let binding_flags = [vk::DescriptorBindingFlags::PARTIALLY_BOUND
+            | vk::DescriptorBindingFlags::UPDATE_AFTER_BIND
+            | vk::DescriptorBindingFlags::UPDATE_UNUSED_WHILE_PENDING; 2];
+        let set_layout = layout_cache.get_or_create_with_flags(&bindings, &binding_flags)?;
+
+        let pool_sizes = [
+            vk::DescriptorPoolSize {
+                ty:               vk::DescriptorType::SAMPLED_IMAGE,
+                descriptor_count: texture_capacity,
+            },
+            vk::DescriptorPoolSize {
+                ty:               vk::DescriptorType::SAMPLER,
+                descriptor_count: sampler_capacity,
+            },
+        ];
+        let pool_create_info = vk::DescriptorPoolCreateInfo::default()
+            .flags(vk::DescriptorPoolCreateFlags::UPDATE_AFTER_BIND)
+            .pool_sizes(&pool_sizes)
+            .max_sets(1);
+        let pool = unsafe { device.create_descriptor_pool(&pool_create_info, None) }?;
+        debug_utils.set_name(pool, "Bindless descriptor pool");
+
+        let layouts = [set_layout];
+        let set_allocate_info = vk::DescriptorSetAllocateInfo::default()
+            .descriptor_pool(pool)
+            .set_layouts(&layouts);
+        let set = match unsafe { device.allocate_descriptor_sets(&set_allocate_info) } {
+            Ok(sets) => sets[0],
+            Err(err) => {
+                unsafe { device.destroy_descriptor_pool(pool, None) };
+                return Err(err.into());
+            }
+        };
+        debug_utils.set_name(set, "Bindless descriptor set");
+
+        Ok(Self {
+            device: device.clone(),
+            pool,
+            set_layout,
+            set,
+            texture_capacity,
+            texture_count: 0,
+            free_texture_slots: Vec::new(),
+            sampler_capacity,
+            sampler_slots: HashMap::new(),
+        })
+    }
+
+    /// Write a texture's view to a freed slot, or the next slot that has never been filled, returning the index shaders
+    /// select it with
+    ///
+    /// The view must be in SHADER_READ_ONLY_OPTIMAL layout when drawn with and outlive every frame that draws with it
+    pub fn add_texture(&mut self, image_view: vk::ImageView) -> Result<u32>
+    {
+        let slot = match self.free_texture_slots.pop() {
+            Some(slot) => slot,
+            None if self.texture_count < self.texture_capacity => {
+                self.texture_count += 1;
+                self.texture_count - 1
+            }
+            None => {
+                return Err(VkAppError::DeviceError(format!(
+                    "Cannot add a texture, all {} bindless texture slots are used",
+                    self.texture_capacity
+                )))
+            }
+        };
+
+        let image_info = [vk::DescriptorImageInfo::default()
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .image_view(image_view)];
+        let descriptor_write = vk::WriteDescriptorSet::default()
+            .dst_set(self.set)
+            .dst_binding(0)
+            .dst_array_element(slot)
+            .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+            .image_info(&image_info);
+        unsafe { self.device.update_descriptor_sets(&[descriptor_write], &[]) };
+
+        Ok(slot)
+    }
+
+    /// Let add_texture reuse a texture's slot
+    ///
+    /// The slot may be refilled straight away, so it must only be freed once no frame in flight could be drawing with it
+    pub fn free_texture(&mut self, slot: u32) -> Result<()>
+    {
+        if slot >= self.texture_count || self.free_texture_slots.contains(&slot) {
+            return Err(VkAppError::DeviceError(format!(
+                "Cannot free bindless texture slot {} as it isn't filled",
+                slot
+            )));
+        }
+        self.free_texture_slots.push(slot);
+        Ok(())
+    }
+
+    /// Write a sampler to the next free slot, returning the index shaders select it with, or return the slot it was
+    /// already written to
+    ///
+    /// The sampler must outlive every frame that draws with it
+    pub fn add_sampler(&mut self, sampler: vk::Sampler) -> Result<u32>
+    {
+        if let Some(&slot) = self.sampler_slots.get(&sampler) {
+            return Ok(slot);
+        }
+        let slot = self.sampler_slots.len() as u32;
+        if slot == self.sampler_capacity {
+            return Err(VkAppError::DeviceError(format!(
+                "Cannot add a sampler, all {} bindless sampler slots are used",
+                self.sampler_capacity
+            )));
+        }
+
+        let image_info = [vk::DescriptorImageInfo::default().sampler(sampler)];
+        let descriptor_write = vk::WriteDescriptorSet::default()
+            .dst_set(self.set)
+            .dst_binding(1)
+            .dst_array_element(slot)
+            .descriptor_type(vk::DescriptorType::SAMPLER)
+            .image_info(&image_info);
+        unsafe { self.device.update_descriptor_sets(&[descriptor_write], &[]) };
+
+        self.sampler_slots.insert(sampler, slot);
+        Ok(slot)
+    }
+}
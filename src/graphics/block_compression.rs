@@ -0,0 +1,677 @@
+use ash::vk;
+
+/// Bytes in each 4x4 block of a BC format, or None for formats that aren't block compressed
+pub fn block_size(format: vk::Format) -> Option<usize>
+{
+    match format {
+        vk::Format::BC1_RGB_UNORM_BLOCK
+        | vk::Format::BC1_RGB_SRGB_BLOCK
+        | vk::Format::BC1_RGBA_UNORM_BLOCK
+        | vk::Format::BC1_RGBA_SRGB_BLOCK => Some(8),
+        vk::Format::BC3_UNORM_BLOCK
+        | vk::Format::BC3_SRGB_BLOCK
+        | vk::Format::BC5_UNORM_BLOCK
+        | vk::Format::BC7_UNORM_BLOCK
+        | vk::Format::BC7_SRGB_BLOCK => Some(16),
+        _ => None,
+    }
+}
+
+/// Bytes of a level of the format, blocks cover 4x4 texels so partial blocks are rounded up
+///
+/// None when the size doesn't fit in a usize, which only a malformed file's extent would do
+pub fn level_size(format: vk::Format, width: u32, height: u32) -> Option<usize>
+{
+    let (columns, rows, unit_size) = match block_size(format) {
+        Some(block_size) => (width.div_ceil(4), height.div_ceil(4), block_size),
+        None => (width, height, 4),
+    };
+    (columns as usize).checked_mul(rows as usize)?.checked_mul(unit_size)
+}
+
+/// The RGBA format a BC format decompresses to, keeping whether it is sRGB
+pub fn decompressed_format(format: vk::Format) -> vk::Format
+{
+    match format {
+        vk::Format::BC1_RGB_SRGB_BLOCK
+        | vk::Format::BC1_RGBA_SRGB_BLOCK
+        | vk::Format::BC3_SRGB_BLOCK
+        | vk::Format::BC7_SRGB_BLOCK => vk::Format::R8G8B8A8_SRGB,
+        _ => vk::Format::R8G8B8A8_UNORM,
+    }
+}
+
+/// Decompress a level of BC blocks into tightly packed RGBA pixels
+///
+/// The blocks must be in row order and there must be level_size of them. BC5's two channels become red and green,
+/// with blue 0 and alpha opaque. Levels that aren't block compressed are returned as they are
+pub fn decompress(format: vk::Format, blocks: &[u8], width: u32, height: u32) -> Vec<u8>
+{
+    let Some(block_size) = block_size(format) else {
+        return blocks.to_vec();
+    };
+    let blocks_wide = width.div_ceil(4);
+
+    let mut pixels = vec![0; width as usize * height as usize * 4];
+    for (block, index) in blocks.chunks_exact(block_size).zip(0u32..) {
+        let texels = match format {
+            vk::Format::BC1_RGB_UNORM_BLOCK | vk::Format::BC1_RGB_SRGB_BLOCK => decode_bc1(block, Bc1Mode::Opaque),
+            vk::Format::BC1_RGBA_UNORM_BLOCK | vk::Format::BC1_RGBA_SRGB_BLOCK => decode_bc1(block, Bc1Mode::PunchThrough),
+            vk::Format::BC3_UNORM_BLOCK | vk::Format::BC3_SRGB_BLOCK => decode_bc3(block),
+            vk::Format::BC5_UNORM_BLOCK => decode_bc5(block),
+            _ => decode_bc7(block),
+        };
+
+        // Blocks at the right and bottom edges of levels that aren't a multiple of 4 are only partly used
+        let (block_x, block_y) = (index % blocks_wide * 4, index / blocks_wide * 4);
+        for (texel, offset) in texels.iter().zip(0u32..) {
+            let (x, y) = (block_x + offset % 4, block_y + offset / 4);
+            if x < width && y < height {
+                let start = (y * width + x) as usize * 4;
+                pixels[start..start + 4].copy_from_slice(texel);
+            }
+        }
+    }
+    pixels
+}
+
+/// How a BC1 block whose first endpoint isn't larger than its second uses its fourth palette entry
+#[derive(Copy, Clone, PartialEq)]
+enum Bc1Mode
+{
+    /// Black, for the RGB formats
+    Opaque,
+    /// Transparent black, for the RGBA formats
+    PunchThrough,
+    /// The colour half of a BC3 block always has four interpolated colours
+    FourColour,
+}
+
+/// Expand an RGB565 colour to 8 bits per channel by repeating the high bits in the low bits
+fn rgb565(colour: u16) -> [u8; 4]
+{
+    let (r, g, b) = ((colour >> 11) & 0x1f, (colour >> 5) & 0x3f, colour & 0x1f);
+    [
+        ((r << 3) | (r >> 2)) as u8,
+        ((g << 2) | (g >> 4)) as u8,
+        ((b << 3) | (b >> 2)) as u8,
+        u8::MAX,
+    ]
+}
+
+/// Weighted average of two colours, weight_0 parts of a and weight_1 parts of b
+fn mix(a: [u8; 4], b: [u8; 4], weight_0: u32, weight_1: u32) -> [u8; 4]
+{
+    let channel = |index: usize| ((a[index] as u32 * weight_0 + b[index] as u32 * weight_1) / (weight_0 + weight_1)) as u8;
+    [channel(0), channel(1), channel(2), u8::MAX]
+}
+
+/// Two RGB565 endpoints and 2-bit indices into a palette of them and colours between them
+fn decode_bc1(block: &[u8], mode: Bc1Mode) -> [[u8; 4]; 16]
+{
+    let colour_0 = u16::from_le_bytes([block[0], block[1]]);
+    let colour_1 = u16::from_le_bytes([block[2], block[3]]);
+    let (endpoint_0, endpoint_1) = (rgb565(colour_0), rgb565(colour_1));
+
+    let palette = if colour_0 > colour_1 || mode == Bc1Mode::FourColour {
+        [
+            endpoint_0,
+            endpoint_1,
+            mix(endpoint_0, endpoint_1, 2, 1),
+            mix(endpoint_0, endpoint_1, 1, 2),
+        ]
+    } else {
+        let alpha = if mode == Bc1Mode::PunchThrough { 0 } else { u8::MAX };
+        [endpoint_0, endpoint_1, mix(endpoint_0, endpoint_1, 1, 1), [0, 0, 0, alpha]]
+    };
+
+    let indices = u32::from_le_bytes([block[4], block[5], block[6], block[7]]);
+    std::array::from_fn(|texel| palette[(indices >> (texel * 2)) as usize & 3])
+}
+
+/// Two 8-bit endpoints and 3-bit indices into a palette of them and values between them, a single channel
+fn decode_bc4(block: &[u8]) -> [u8; 16]
+{
+    let (value_0, value_1) = (block[0] as u32, block[1] as u32);
+    let mut palette = [value_0, value_1, 0, 0, 0, 0, 0, u8::MAX as u32];
+    if value_0 > value_1 {
+        for step in 1..7 {
+            palette[step as usize + 1] = ((7 - step) * value_0 + step * value_1) / 7;
+        }
+    } else {
+        // Six values with 0 and 255 as the last two
+        for step in 1..5 {
+            palette[step as usize + 1] = ((5 - step) * value_0 + step * value_1) / 5;
+        }
+        palette[6] = 0;
+    }
+
+    let indices = u64::from_le_bytes([block[2], block[3], block[4], block[5], block[6], block[7], 0, 0]);
+    std::array::from_fn(|texel| palette[(indices >> (texel * 3)) as usize & 7] as u8)
+}
+
+/// A BC4 block of alpha followed by a BC1 block of colour
+fn decode_bc3(block: &[u8]) -> [[u8; 4]; 16]
+{
+    let alpha = decode_bc4(&block[..8]);
+    let mut texels = decode_bc1(&block[8..], Bc1Mode::FourColour);
+    for (texel, alpha) in texels.iter_mut().zip(alpha) {
+        texel[3] = alpha;
+    }
+    texels
+}
+
+/// Two BC4 blocks, red then green
+fn decode_bc5(block: &[u8]) -> [[u8; 4]; 16]
+{
+    let (red, green) = (decode_bc4(&block[..8]), decode_bc4(&block[8..]));
+    std::array::from_fn(|texel| [red[texel], green[texel], 0, u8::MAX])
+}
+
+/// The layout of a BC7 block in one of the eight modes
+struct Bc7Mode
+{
+    subsets:              usize,
+    partition_bits:       u32,
+    rotation_bits:        u32,
+    index_selection_bits: u32,
+    colour_bits:          u32,
+    alpha_bits:           u32,
+    /// A p-bit for each endpoint, appended as the lowest bit of each of its channels
+    endpoint_p_bits:      bool,
+    /// A p-bit for each subset, shared by both of its endpoints
+    shared_p_bits:        bool,
+    index_bits:           u32,
+    /// Bits of the second set of indices, used for alpha or colour as chosen by the index selection bit
+    secondary_index_bits: u32,
+}
+
+#[rustfmt::skip]
+const BC7_MODES: [Bc7Mode; 8] = [
+    Bc7Mode { subsets: 3, partition_bits: 4, rotation_bits: 0, index_selection_bits: 0, colour_bits: 4, alpha_bits: 0, endpoint_p_bits: true,  shared_p_bits: false, index_bits: 3, secondary_index_bits: 0 },
+    Bc7Mode { subsets: 2, partition_bits: 6, rotation_bits: 0, index_selection_bits: 0, colour_bits: 6, alpha_bits: 0, endpoint_p_bits: false, shared_p_bits: true,  index_bits: 3, secondary_index_bits: 0 },
+    Bc7Mode { subsets: 3, partition_bits: 6, rotation_bits: 0, index_selection_bits: 0, colour_bits: 5, alpha_bits: 0, endpoint_p_bits: false, shared_p_bits: false, index_bits: 2, secondary_index_bits: 0 },
+    Bc7Mode { subsets: 2, partition_bits: 6, rotation_bits: 0, index_selection_bits: 0, colour_bits: 7, alpha_bits: 0, endpoint_p_bits: true,  shared_p_bits: false, index_bits: 2, secondary_index_bits: 0 },
+    Bc7Mode { subsets: 1, partition_bits: 0, rotation_bits: 2, index_selection_bits: 1, colour_bits: 5, alpha_bits: 6, endpoint_p_bits: false, shared_p_bits: false, index_bits: 2, secondary_index_bits: 3 },
+    Bc7Mode { subsets: 1, partition_bits: 0, rotation_bits: 2, index_selection_bits: 0, colour_bits: 7, alpha_bits: 8, endpoint_p_bits: false, shared_p_bits: false, index_bits: 2, secondary_index_bits: 2 },
+    Bc7Mode { subsets: 1, partition_bits: 0, rotation_bits: 0, index_selection_bits: 0, colour_bits: 7, alpha_bits: 7, endpoint_p_bits: true,  shared_p_bits: false, index_bits: 4, secondary_index_bits: 0 },
+    Bc7Mode { subsets: 2, partition_bits: 6, rotation_bits: 0, index_selection_bits: 0, colour_bits: 5, alpha_bits: 5, endpoint_p_bits: true,  shared_p_bits: false, index_bits: 2, secondary_index_bits: 0 },
+];
+
+/// The subset of each texel for each 2 subset partition, a bit per texel
+#[rustfmt::skip]
+const BC7_PARTITIONS_2: [u16; 64] = [
+    0xcccc, 0x8888, 0xeeee, 0xecc8, 0xc880, 0xfeec, 0xfec8, 0xec80, 0xc800, 0xffec, 0xfe80, 0xe800, 0xffe8, 0xff00, 0xfff0, 0xf000,
+    0xf710, 0x008e, 0x7100, 0x08ce, 0x008c, 0x7310, 0x3100, 0x8cce, 0x088c, 0x3110, 0x6666, 0x366c, 0x17e8, 0x0ff0, 0x718e, 0x399c,
+    0xaaaa, 0xf0f0, 0x5a5a, 0x33cc, 0x3c3c, 0x55aa, 0x9696, 0xa55a, 0x73ce, 0x13c8, 0x324c, 0x3bdc, 0x6996, 0xc33c, 0x9966, 0x0660,
+    0x0272, 0x04e4, 0x4e40, 0x2720, 0xc936, 0x936c, 0x39c6, 0x639c, 0x9336, 0x9cc6, 0x817e, 0xe718, 0xccf0, 0x0fcc, 0x7744, 0xee22,
+];
+
+/// The subset of each texel for each 3 subset partition, two bits per texel
+#[rustfmt::skip]
+const BC7_PARTITIONS_3: [u32; 64] = [
+    0xaa685050, 0x6a5a5040, 0x5a5a4200, 0x5450a0a8, 0xa5a50000, 0xa0a05050, 0x5555a0a0, 0x5a5a5050,
+    0xaa550000, 0xaa555500, 0xaaaa5500, 0x90909090, 0x94949494, 0xa4a4a4a4, 0xa9a59450, 0x2a0a4250,
+    0xa5945040, 0x0a425054, 0xa5a5a500, 0x55a0a0a0, 0xa8a85454, 0x6a6a4040, 0xa4a45000, 0x1a1a0500,
+    0x0050a4a4, 0xaaa59090, 0x14696914, 0x69691400, 0xa08585a0, 0xaa821414, 0x50a4a450, 0x6a5a0200,
+    0xa9a58000, 0x5090a0a8, 0xa8a09050, 0x24242424, 0x00aa5500, 0x24924924, 0x24499224, 0x50a50a50,
+    0x500aa550, 0xaaaa4444, 0x66660000, 0xa5a0a5a0, 0x50a050a0, 0x69286928, 0x44aaaa44, 0x66666600,
+    0xaa444444, 0x54a854a8, 0x95809580, 0x96969600, 0xa85454a8, 0x80959580, 0xaa141414, 0x96960000,
+    0xaaaa1414, 0xa05050a0, 0xa0a5a5a0, 0x96000000, 0x40804080, 0xa9a8a9a8, 0xaaaaaa44, 0x2a4a5254,
+];
+
+/// The anchor texel of the second subset of each 2 subset partition, the first subset's is always texel 0
+#[rustfmt::skip]
+const BC7_ANCHORS_2: [u32; 64] = [
+    15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15,
+    15,  2,  8,  2,  2,  8,  8, 15,  2,  8,  2,  2,  8,  8,  2,  2,
+    15, 15,  6,  8,  2,  8, 15, 15,  2,  8,  2,  2,  2, 15, 15,  6,
+     6,  2,  6,  8, 15, 15,  2,  2, 15, 15, 15, 15, 15,  2,  2, 15,
+];
+
+/// The anchor texels of the second and third subsets of each 3 subset partition
+#[rustfmt::skip]
+const BC7_ANCHORS_3: [[u32; 2]; 64] = [
+    [3, 15], [3, 8], [15, 8], [15, 3], [8, 15], [3, 15], [15, 3], [15, 8],
+    [8, 15], [8, 15], [6, 15], [6, 15], [6, 15], [5, 15], [3, 15], [3, 8],
+    [3, 15], [3, 8], [8, 15], [15, 3], [3, 15], [3, 8], [6, 15], [10, 8],
+    [5, 3], [8, 15], [8, 6], [6, 10], [8, 15], [5, 15], [15, 10], [15, 8],
+    [8, 15], [15, 3], [3, 15], [5, 10], [6, 10], [10, 8], [8, 9], [15, 10],
+    [15, 6], [3, 15], [15, 8], [5, 15], [15, 3], [15, 6], [15, 6], [15, 8],
+    [3, 15], [15, 3], [5, 15], [5, 15], [5, 15], [8, 15], [5, 15], [10, 15],
+    [5, 15], [10, 15], [8, 15], [13, 15], [15, 3], [12, 15], [3, 15], [3, 8],
+];
+
+/// Interpolation weights out of 64 for 2, 3 and 4-bit indices
+const BC7_WEIGHTS_2: [u32; 4] = [0, 21, 43, 64];
+const BC7_WEIGHTS_3: [u32; 8] = [0, 9, 18, 27, 37, 46, 55, 64];
+const BC7_WEIGHTS_4: [u32; 16] = [0, 4, 9, 13, 17, 21, 26, 30, 34, 38, 43, 47, 51, 55, 60, 64];
+
+/// Reads fields from the bits of a block, starting at the lowest bit of the first byte
+struct BitReader
+{
+    bits:     u128,
+    position: u32,
+}
+
+impl BitReader
+{
+    fn read(&mut self, count: u32) -> u32
+    {
+        let value = (self.bits >> self.position) as u32 & ((1u64 << count) - 1) as u32;
+        self.position += count;
+        value
+    }
+}
+
+/// One to three subsets each with their own pair of RGB or RGBA endpoints, and indices into the colours between them
+fn decode_bc7(block: &[u8]) -> [[u8; 4]; 16]
+{
+    let mut reader = BitReader {
+        bits:     u128::from_le_bytes(block.try_into().unwrap_or_default()),
+        position: 0,
+    };
+
+    // The mode is the number of zero bits before the first set bit, a block with no set bit is reserved and decodes
+    // to transparent black
+    let mode_index = block[0].trailing_zeros();
+    let Some(mode) = BC7_MODES.get(mode_index as usize) else {
+        return [[0; 4]; 16];
+    };
+    reader.read(mode_index + 1);
+    let partition = reader.read(mode.partition_bits) as usize;
+    let rotation = reader.read(mode.rotation_bits);
+    let index_selection = reader.read(mode.index_selection_bits);
+
+    // Each channel of every endpoint is stored before the next channel
+    let endpoint_count = mode.subsets * 2;
+    let mut endpoints = [[0u32; 4]; 6];
+    for channel in 0..3 {
+        for endpoint in &mut endpoints[..endpoint_count] {
+            endpoint[channel] = reader.read(mode.colour_bits);
+        }
+    }
+    if mode.alpha_bits > 0 {
+        for endpoint in &mut endpoints[..endpoint_count] {
+            endpoint[3] = reader.read(mode.alpha_bits);
+        }
+    }
+
+    let mut colour_bits = mode.colour_bits;
+    let mut alpha_bits = mode.alpha_bits;
+    if mode.endpoint_p_bits || mode.shared_p_bits {
+        let p_bits: Vec<u32> = if mode.endpoint_p_bits {
+            (0..endpoint_count).map(|_| reader.read(1)).collect()
+        } else {
+            (0..mode.subsets).flat_map(|_| [reader.read(1); 2]).collect()
+        };
+        for (endpoint, p_bit) in endpoints.iter_mut().zip(p_bits) {
+            for channel in endpoint.iter_mut() {
+                *channel = (*channel << 1) | p_bit;
+            }
+        }
+        colour_bits += 1;
+        if alpha_bits > 0 {
+            alpha_bits += 1;
+        }
+    }
+
+    // Expand to 8 bits by repeating the high bits in the low bits, endpoints without alpha are opaque
+    let expand = |value: u32, bits: u32| (value << (8 - bits)) | (value >> (2 * bits - 8));
+    let endpoints = endpoints.map(|endpoint| {
+        [
+            expand(endpoint[0], colour_bits),
+            expand(endpoint[1], colour_bits),
+            expand(endpoint[2], colour_bits),
+            if alpha_bits > 0 {
+                expand(endpoint[3], alpha_bits)
+            } else {
+                u8::MAX as u32
+            },
+        ]
+    });
+
+    let subset_of = |texel: u32| match mode.subsets {
+        1 => 0,
+        2 => (BC7_PARTITIONS_2[partition] >> texel) as usize & 1,
+        _ => (BC7_PARTITIONS_3[partition] >> (texel * 2)) as usize & 3,
+    };
+    // Each subset's anchor texel has one less index bit, as its index's highest bit is always 0
+    let is_anchor = |texel: u32| match mode.subsets {
+        1 => texel == 0,
+        2 => texel == 0 || texel == BC7_ANCHORS_2[partition],
+        _ => texel == 0 || BC7_ANCHORS_3[partition].contains(&texel),
+    };
+
+    let indices: [u32; 16] = std::array::from_fn(|texel| reader.read(mode.index_bits - is_anchor(texel as u32) as u32));
+    let secondary_indices: [u32; 16] = std::array::from_fn(|texel| match mode.secondary_index_bits {
+        0 => 0,
+        bits => reader.read(bits - (texel == 0) as u32),
+    });
+
+    // Modes 4 and 5 have separate indices for colour and alpha, which mode 4 can swap
+    let weights = |bits: u32| match bits {
+        2 => &BC7_WEIGHTS_2[..],
+        3 => &BC7_WEIGHTS_3[..],
+        _ => &BC7_WEIGHTS_4[..],
+    };
+    let (colour_weights, colour_indices, alpha_weights, alpha_indices) = match (mode.secondary_index_bits, index_selection) {
+        (0, _) => (weights(mode.index_bits), &indices, weights(mode.index_bits), &indices),
+        (bits, 0) => (weights(mode.index_bits), &indices, weights(bits), &secondary_indices),
+        (bits, _) => (weights(bits), &secondary_indices, weights(mode.index_bits), &indices),
+    };
+
+    let interpolate = |value_0: u32, value_1: u32, weight: u32| ((64 - weight) * value_0 + weight * value_1 + 32) >> 6;
+    std::array::from_fn(|texel| {
+        let subset = subset_of(texel as u32);
+        let (endpoint_0, endpoint_1) = (endpoints[subset * 2], endpoints[subset * 2 + 1]);
+        let colour_weight = colour_weights[colour_indices[texel] as usize];
+        let alpha_weight = alpha_weights[alpha_indices[texel] as usize];
+
+        let mut colour = [0u8; 4];
+        for channel in 0..3 {
+            colour[channel] = interpolate(endpoint_0[channel], endpoint_1[channel], colour_weight) as u8;
+        }
+        colour[3] = interpolate(endpoint_0[3], endpoint_1[3], alpha_weight) as u8;
+
+        // The rotation swaps alpha with one of the colour channels
+        if rotation > 0 {
+            colour.swap(rotation as usize - 1, 3);
+        }
+        colour
+    })
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    const RED_565: u16 = 0xf800;
+    const BLUE_565: u16 = 0x001f;
+    const RED: [u8; 4] = [255, 0, 0, 255];
+    const BLUE: [u8; 4] = [0, 0, 255, 255];
+
+    /// A BC1 block whose texels use palette entries 0, 1, 2, 3 in turn
+    fn bc1_block(colour_0: u16, colour_1: u16) -> [u8; 8]
+    {
+        let [c0_low, c0_high] = colour_0.to_le_bytes();
+        let [c1_low, c1_high] = colour_1.to_le_bytes();
+        [c0_low, c0_high, c1_low, c1_high, 0xe4, 0xe4, 0xe4, 0xe4]
+    }
+
+    /// A BC4 block whose texels use palette entries 0 to 7 in turn
+    fn bc4_block(value_0: u8, value_1: u8) -> [u8; 8]
+    {
+        let indices = (0..16u64).fold(0, |indices, texel| indices | (texel % 8) << (texel * 3));
+        let mut block = [value_0, value_1, 0, 0, 0, 0, 0, 0];
+        block[2..].copy_from_slice(&indices.to_le_bytes()[..6]);
+        block
+    }
+
+    #[test]
+    fn bc1_four_colour_blocks_interpolate_thirds()
+    {
+        let palette = [RED, BLUE, [170, 0, 85, 255], [85, 0, 170, 255]];
+        let texels = decode_bc1(&bc1_block(RED_565, BLUE_565), Bc1Mode::PunchThrough);
+        for (texel, colour) in texels.iter().enumerate() {
+            assert_eq!(*colour, palette[texel % 4], "texel {}", texel);
+        }
+    }
+
+    #[test]
+    fn bc1_three_colour_blocks_have_black_or_transparent_fourth_entries()
+    {
+        let block = bc1_block(BLUE_565, RED_565);
+        let opaque = decode_bc1(&block, Bc1Mode::Opaque);
+        assert_eq!(opaque[..4], [BLUE, RED, [127, 0, 127, 255], [0, 0, 0, 255]]);
+
+        let punch_through = decode_bc1(&block, Bc1Mode::PunchThrough);
+        assert_eq!(punch_through[..4], [BLUE, RED, [127, 0, 127, 255], [0, 0, 0, 0]]);
+
+        // Equal endpoints are also three colour blocks
+        let equal = decode_bc1(&bc1_block(RED_565, RED_565), Bc1Mode::PunchThrough);
+        assert_eq!(equal[3], [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn bc1_endpoints_expand_to_the_full_range()
+    {
+        assert_eq!(rgb565(0xffff), [255, 255, 255, 255]);
+        assert_eq!(rgb565(0x0000), [0, 0, 0, 255]);
+        // 5-bit 16 and 6-bit 32 repeat their high bits in the low bits
+        assert_eq!(rgb565((16 << 11) | (32 << 5) | 16), [132, 130, 132, 255]);
+    }
+
+    #[test]
+    fn bc4_blocks_interpolate_eight_or_six_values()
+    {
+        assert_eq!(
+            decode_bc4(&bc4_block(200, 100))[..8],
+            [200, 100, 185, 171, 157, 142, 128, 114]
+        );
+        assert_eq!(decode_bc4(&bc4_block(100, 200))[..8], [100, 200, 120, 140, 160, 180, 0, 255]);
+    }
+
+    #[test]
+    fn bc3_blocks_always_have_four_colours_and_bc4_alpha()
+    {
+        let mut block = [0; 16];
+        block[..8].copy_from_slice(&bc4_block(100, 200));
+        block[8..].copy_from_slice(&bc1_block(BLUE_565, RED_565));
+        let texels = decode_bc3(&block);
+        assert_eq!(texels[0], [0, 0, 255, 100]);
+        assert_eq!(texels[1], [255, 0, 0, 200]);
+        assert_eq!(texels[2], [85, 0, 170, 120]);
+        assert_eq!(texels[3], [170, 0, 85, 140]);
+    }
+
+    #[test]
+    fn bc5_blocks_decode_to_red_and_green()
+    {
+        let mut block = [0; 16];
+        block[..8].copy_from_slice(&bc4_block(200, 100));
+        block[8..].copy_from_slice(&bc4_block(100, 200));
+        let texels = decode_bc5(&block);
+        assert_eq!(texels[0], [200, 100, 0, 255]);
+        assert_eq!(texels[6], [128, 0, 0, 255]);
+        assert_eq!(texels[7], [114, 255, 0, 255]);
+    }
+
+    /// Writes the fields of a block from the lowest bit of the first byte
+    struct BitWriter
+    {
+        bits:     u128,
+        position: u32,
+    }
+
+    impl BitWriter
+    {
+        fn new() -> Self { Self { bits: 0, position: 0 } }
+
+        fn write(&mut self, value: u32, count: u32)
+        {
+            self.bits |= (value as u128) << self.position;
+            self.position += count;
+        }
+
+        fn block(&self) -> [u8; 16] { self.bits.to_le_bytes() }
+    }
+
+    /// The fields of a BC7 mode before its indices, written out from the BC7 specification rather than BC7_MODES
+    struct Bc7Layout
+    {
+        partition_bits:       u32,
+        rotation_bits:        u32,
+        index_selection_bits: u32,
+        endpoints:            u32,
+        colour_bits:          u32,
+        alpha_bits:           u32,
+        p_bits:               u32,
+    }
+
+    #[rustfmt::skip]
+    const BC7_LAYOUTS: [Bc7Layout; 8] = [
+        Bc7Layout { partition_bits: 4, rotation_bits: 0, index_selection_bits: 0, endpoints: 6, colour_bits: 4, alpha_bits: 0, p_bits: 6 },
+        Bc7Layout { partition_bits: 6, rotation_bits: 0, index_selection_bits: 0, endpoints: 4, colour_bits: 6, alpha_bits: 0, p_bits: 2 },
+        Bc7Layout { partition_bits: 6, rotation_bits: 0, index_selection_bits: 0, endpoints: 6, colour_bits: 5, alpha_bits: 0, p_bits: 0 },
+        Bc7Layout { partition_bits: 6, rotation_bits: 0, index_selection_bits: 0, endpoints: 4, colour_bits: 7, alpha_bits: 0, p_bits: 4 },
+        Bc7Layout { partition_bits: 0, rotation_bits: 2, index_selection_bits: 1, endpoints: 2, colour_bits: 5, alpha_bits: 6, p_bits: 0 },
+        Bc7Layout { partition_bits: 0, rotation_bits: 2, index_selection_bits: 0, endpoints: 2, colour_bits: 7, alpha_bits: 8, p_bits: 0 },
+        Bc7Layout { partition_bits: 0, rotation_bits: 0, index_selection_bits: 0, endpoints: 2, colour_bits: 7, alpha_bits: 7, p_bits: 2 },
+        Bc7Layout { partition_bits: 6, rotation_bits: 0, index_selection_bits: 0, endpoints: 4, colour_bits: 5, alpha_bits: 5, p_bits: 4 },
+    ];
+
+    /// Write a BC7 block's fields up to its indices, every endpoint with the same channels and p-bit
+    fn bc7_header(mode: u32, partition: u32, rotation: u32, channels: [u32; 4], p_bit: u32) -> BitWriter
+    {
+        let layout = &BC7_LAYOUTS[mode as usize];
+        let mut writer = BitWriter::new();
+        writer.write(1 << mode, mode + 1);
+        writer.write(partition, layout.partition_bits);
+        writer.write(rotation, layout.rotation_bits);
+        writer.write(0, layout.index_selection_bits);
+        for &channel in &channels[..3] {
+            for _ in 0..layout.endpoints {
+                writer.write(channel, layout.colour_bits);
+            }
+        }
+        if layout.alpha_bits > 0 {
+            for _ in 0..layout.endpoints {
+                writer.write(channels[3], layout.alpha_bits);
+            }
+        }
+        for _ in 0..layout.p_bits {
+            writer.write(p_bit, 1);
+        }
+        writer
+    }
+
+    #[test]
+    fn bc7_blocks_of_every_mode_decode_their_endpoints()
+    {
+        // With every endpoint the same, every texel is that endpoint whatever its index and subset, the expected colours
+        // are the stored bits and p-bit expanded to 8 bits
+        let cases: [(u32, [u32; 4], u32, [u8; 4]); 8] = [
+            (0, [15, 8, 0, 0], 1, [255, 140, 8, 255]),
+            (1, [63, 32, 0, 0], 1, [255, 131, 2, 255]),
+            (2, [31, 16, 1, 0], 0, [255, 132, 8, 255]),
+            (3, [127, 64, 0, 0], 0, [254, 128, 0, 255]),
+            (4, [31, 0, 16, 32], 0, [255, 0, 132, 130]),
+            (5, [127, 1, 64, 200], 0, [255, 2, 129, 200]),
+            (6, [100, 50, 0, 127], 1, [201, 101, 1, 255]),
+            (7, [31, 0, 15, 8], 1, [255, 4, 125, 69]),
+        ];
+        for (mode, channels, p_bit, expected) in cases {
+            let block = bc7_header(mode, 0, 0, channels, p_bit).block();
+            assert_eq!(decode_bc7(&block), [expected; 16], "mode {}", mode);
+        }
+    }
+
+    #[test]
+    fn bc7_mode_6_interpolates_with_4_bit_indices()
+    {
+        let mut writer = BitWriter::new();
+        writer.write(1 << 6, 7);
+        // Every channel of the first endpoint is 0 and of the second is 255, once their p-bits are appended
+        for _ in 0..4 {
+            writer.write(0, 7);
+            writer.write(127, 7);
+        }
+        writer.write(0, 1);
+        writer.write(1, 1);
+        // Each texel's index is its position, the anchor's index has 3 bits
+        writer.write(0, 3);
+        for texel in 1..16 {
+            writer.write(texel, 4);
+        }
+
+        let expected = [0, 16, 36, 52, 68, 84, 104, 120, 135, 151, 171, 187, 203, 219, 239, 255];
+        let texels = decode_bc7(&writer.block());
+        for (texel, value) in texels.iter().zip(expected) {
+            assert_eq!(*texel, [value; 4]);
+        }
+    }
+
+    #[test]
+    fn bc7_mode_1_splits_texels_between_partition_subsets()
+    {
+        let mut writer = BitWriter::new();
+        writer.write(1 << 1, 2);
+        // Partition 0 puts the two right hand columns in the second subset
+        writer.write(0, 6);
+        for (first_subset, second_subset) in [(63, 0), (0, 0), (0, 0)] {
+            writer.write(first_subset, 6);
+            writer.write(first_subset, 6);
+            writer.write(second_subset, 6);
+            writer.write(second_subset, 6);
+        }
+        writer.write(1, 1);
+        writer.write(0, 1);
+
+        let texels = decode_bc7(&writer.block());
+        for (texel, colour) in texels.iter().enumerate() {
+            let expected = if texel % 4 < 2 { [255, 2, 2, 255] } else { [0, 0, 0, 255] };
+            assert_eq!(*colour, expected, "texel {}", texel);
+        }
+    }
+
+    #[test]
+    fn bc7_mode_4_rotation_swaps_alpha_with_a_colour_channel()
+    {
+        // Red is swapped with alpha
+        let block = bc7_header(4, 0, 1, [31, 0, 0, 0], 0).block();
+        assert_eq!(decode_bc7(&block), [[0, 0, 0, 255]; 16]);
+    }
+
+    #[test]
+    fn bc7_mode_5_has_separate_alpha_indices()
+    {
+        let mut writer = bc7_header(5, 0, 0, [127, 127, 127, 0], 0);
+        // The second endpoint's alpha is 255
+        writer.bits |= 255 << (6 + 2 + 42 + 8);
+        // Colour indices are all 0, then alpha indices with the anchor's 1 bit set and every other index 3
+        writer.write(0, 31);
+        writer.write(1, 1);
+        for _ in 1..16 {
+            writer.write(3, 2);
+        }
+
+        let texels = decode_bc7(&writer.block());
+        assert_eq!(texels[0], [255, 255, 255, 84]);
+        assert_eq!(texels[1..], [[255, 255, 255, 255]; 15]);
+    }
+
+    #[test]
+    fn bc7_reserved_mode_decodes_to_transparent_black()
+    {
+        assert_eq!(decode_bc7(&[0; 16]), [[0; 4]; 16]);
+    }
+
+    #[test]
+    fn decompress_crops_partial_edge_blocks()
+    {
+        let blocks: Vec<u8> = [bc1_block(RED_565, RED_565), bc1_block(BLUE_565, BLUE_565)].concat();
+        // Two blocks side by side, the second only covering one column of the 5 texel wide level
+        let pixels = decompress(vk::Format::BC1_RGB_UNORM_BLOCK, &blocks, 5, 2);
+        assert_eq!(pixels.len(), 5 * 2 * 4);
+        assert_eq!(pixels[..4], RED);
+        assert_eq!(pixels[4 * 4..5 * 4], BLUE);
+        assert_eq!(pixels[5 * 4..6 * 4], RED);
+    }
+
+    #[test]
+    fn level_sizes_round_up_to_whole_blocks()
+    {
+        assert_eq!(level_size(vk::Format::BC1_RGBA_UNORM_BLOCK, 5, 1), Some(2 * 8));
+        assert_eq!(level_size(vk::Format::BC7_UNORM_BLOCK, 8, 8), Some(4 * 16));
+        assert_eq!(level_size(vk::Format::R8G8B8A8_UNORM, 5, 3), Some(5 * 3 * 4));
+    }
+
+    #[test]
+    fn level_sizes_too_large_for_usize_are_none()
+    {
+        assert_eq!(level_size(vk::Format::BC7_UNORM_BLOCK, u32::MAX, u32::MAX), None);
+        assert_eq!(level_size(vk::Format::R8G8B8A8_UNORM, u32::MAX, u32::MAX), None);
+    }
+}
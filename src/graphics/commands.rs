@@ -1,7 +1,11 @@
+use crate::graphics::compute::Dispatch;
+use crate::graphics::debug::DebugUtils;
+use crate::graphics::device::RenderingSupport;
+use crate::graphics::mesh::{DrawObject, Mesh};
 use crate::graphics::presentation::Swapchain;
-use crate::graphics::{pipeline, vk_app, vk_app::Result};
-use ash::vk;
+use crate::graphics::{bindless, pipeline, skybox, vk_app, vk_app::Result};
 use ash::vk::ClearColorValue;
+use ash::{khr, vk};
 /// Allow for multiple frames in flight (rendering of one frame does not interfere with recording of the next)
 ///
 /// 2 stops the CPU getting too far ahead of the GPU
@@ -25,7 +29,9 @@ pub fn create_command_pool(device: &ash::Device, queue_family_index: u32) -> Res
 /// A command buffer is allocated from a command pool and commands are recorded to it to later be submitted to a queue
 ///
 /// Each frame has its own command buffer so we can record a new frame while another is being presented
-pub fn create_command_buffers(device: &ash::Device, command_pool: vk::CommandPool) -> Result<Vec<vk::CommandBuffer>>
+pub fn create_command_buffers(
+    device: &ash::Device, debug_utils: &DebugUtils, command_pool: vk::CommandPool,
+) -> Result<Vec<vk::CommandBuffer>>
 {
     let command_buffer_allocate_info = vk::CommandBufferAllocateInfo::default()
         .command_pool(command_pool)
@@ -34,56 +40,225 @@ pub fn create_command_buffers(device: &ash::Device, command_pool: vk::CommandPoo
         .level(vk::CommandBufferLevel::PRIMARY)
         .command_buffer_count(MAX_FRAMES_IN_FLIGHT); // Number of comamnd buffers to allocate
 
-    Ok(unsafe { device.allocate_command_buffers(&command_buffer_allocate_info) }?)
+    let command_buffers = unsafe { device.allocate_command_buffers(&command_buffer_allocate_info) }?;
+    for (frame, &command_buffer) in command_buffers.iter().enumerate() {
+        debug_utils.set_name(command_buffer, format!("Command buffer (frame {})", frame).as_str());
+    }
+
+    Ok(command_buffers)
 }
 
-/// Record commands to begin the render pass, bind the vertex and index buffers and descriptor sets, set the dynamic states of the pipeline and lastly issue the draw commands
-pub fn record_command_buffer(
-    device: &ash::Device, command_buffer: vk::CommandBuffer, image_index: u32, pipeline: &pipeline::Pipeline,
-    swapchain: &Swapchain, vertex_buffer: vk::Buffer, index_buffer: vk::Buffer,
-    descriptor_sets_current_frame: Vec<vk::DescriptorSet>,
-) -> Result<()>
+/// The entry points used to begin and end rendering, chosen from the selected device's RenderingSupport
+///
+/// Vulkan 1.3 devices use the core functions, older devices with the extensions need the extension loaders
+pub enum RenderingPath
 {
-    let command_buffer_begin_info = vk::CommandBufferBeginInfo::default().flags(vk::CommandBufferUsageFlags::empty());
+    Core,
+    Extensions
+    {
+        dynamic_rendering: khr::dynamic_rendering::Device,
+        synchronization2:  khr::synchronization2::Device,
+    },
+    RenderPass,
+}
 
-    unsafe { device.begin_command_buffer(command_buffer, &command_buffer_begin_info) }?;
+impl RenderingPath
+{
+    pub fn new(instance: &ash::Instance, device: &ash::Device, rendering_support: RenderingSupport) -> Self
+    {
+        match rendering_support {
+            RenderingSupport::Core => RenderingPath::Core,
+            RenderingSupport::Extensions => RenderingPath::Extensions {
+                dynamic_rendering: khr::dynamic_rendering::Device::new(instance, device),
+                synchronization2:  khr::synchronization2::Device::new(instance, device),
+            },
+            RenderingSupport::RenderPass => RenderingPath::RenderPass,
+        }
+    }
 
-    // We are using SRGB which is floating point so must floating point for our clear values
-    // TODO: Make compatible with other formats
-    let clear_colour = vk::ClearValue { color: ClearColorValue { float32: [0.0, 0.0, 0.0, 1.0] } };
-    let clear_values: [vk::ClearValue; 1] = [clear_colour];
+    /// Whether render pass and framebuffer objects must be created
+    pub fn uses_render_pass(&self) -> bool { matches!(self, RenderingPath::RenderPass) }
 
-    let render_pass_begin_info = vk::RenderPassBeginInfo::default()
-        .render_pass(pipeline.render_pass)
-        .framebuffer(swapchain.framebuffers[image_index as usize])
+    unsafe fn cmd_pipeline_barrier2(
+        &self, device: &ash::Device, command_buffer: vk::CommandBuffer, dependency_info: &vk::DependencyInfo,
+    )
+    {
+        match self {
+            RenderingPath::Core => device.cmd_pipeline_barrier2(command_buffer, dependency_info),
+            RenderingPath::Extensions { synchronization2, .. } => {
+                synchronization2.cmd_pipeline_barrier2(command_buffer, dependency_info)
+            }
+            RenderingPath::RenderPass => unreachable!("synchronization2 is not enabled on the render pass path"),
+        }
+    }
+
+    /// Transition a swapchain image between layouts around dynamic rendering
+    ///
+    /// The render pass path does this with the attachment's initial and final layouts and the subpass dependency
+    unsafe fn transition_swapchain_image(
+        &self, device: &ash::Device, command_buffer: vk::CommandBuffer, image: vk::Image, old_layout: vk::ImageLayout,
+        new_layout: vk::ImageLayout,
+    )
+    {
+        let mut barrier = vk::ImageMemoryBarrier2::default()
+            .old_layout(old_layout)
+            .new_layout(new_layout)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(image)
+            .subresource_range(
+                vk::ImageSubresourceRange::default()
+                    .aspect_mask(vk::ImageAspectFlags::COLOR)
+                    .base_mip_level(0)
+                    .level_count(1)
+                    .base_array_layer(0)
+                    .layer_count(1),
+            );
+
+        barrier = if new_layout == vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL {
+            // Wait at the same stage the image available semaphore waits on, then allow colour attachment writes
+            barrier
+                .src_stage_mask(vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT)
+                .src_access_mask(vk::AccessFlags2::NONE)
+                .dst_stage_mask(vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT)
+                .dst_access_mask(vk::AccessFlags2::COLOR_ATTACHMENT_WRITE)
+        } else {
+            // Colour attachment writes must finish before presenting, presentation is synchronised by the render finished semaphore
+            barrier
+                .src_stage_mask(vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT)
+                .src_access_mask(vk::AccessFlags2::COLOR_ATTACHMENT_WRITE)
+                .dst_stage_mask(vk::PipelineStageFlags2::NONE)
+                .dst_access_mask(vk::AccessFlags2::NONE)
+        };
+
+        let image_memory_barriers = [barrier];
+        let dependency_info = vk::DependencyInfo::default().image_memory_barriers(&image_memory_barriers);
+        self.cmd_pipeline_barrier2(device, command_buffer, &dependency_info);
+    }
+
+    /// Begin rendering to a swapchain image, either by beginning the render pass or by transitioning the image and beginning dynamic rendering
+    unsafe fn begin_rendering(
+        &self, device: &ash::Device, command_buffer: vk::CommandBuffer, image_index: u32, pipeline: &pipeline::Pipeline,
+        swapchain: &Swapchain, clear_value: vk::ClearValue,
+    )
+    {
         // Render area determines where the shader loads and stores take place
-        .render_area(vk::Rect2D {
+        let render_area = vk::Rect2D {
             offset: vk::Offset2D { x: 0, y: 0 },
             extent: swapchain.settings.extent,
-        })
-        .clear_values(&clear_values);
+        };
 
-    unsafe {
-        // INLINE SubpassContents means the render pass commands are embedded in the primary command buffer itself and no secondary command buffers are executed
-        device.cmd_begin_render_pass(command_buffer, &render_pass_begin_info, vk::SubpassContents::INLINE);
-        device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline.graphics_pipeline);
+        if self.uses_render_pass() {
+            let clear_values: [vk::ClearValue; 1] = [clear_value];
+
+            let render_pass_begin_info = vk::RenderPassBeginInfo::default()
+                .render_pass(pipeline.render_pass)
+                .framebuffer(swapchain.framebuffers[image_index as usize])
+                .render_area(render_area)
+                .clear_values(&clear_values);
 
-        device.cmd_bind_vertex_buffers(command_buffer, 0, &[vertex_buffer], &[0]);
+            // INLINE SubpassContents means the render pass commands are embedded in the primary command buffer itself and no secondary command buffers are executed
+            device.cmd_begin_render_pass(command_buffer, &render_pass_begin_info, vk::SubpassContents::INLINE);
+            return;
+        }
 
-        // Need to
-        device.cmd_bind_descriptor_sets(
+        self.transition_swapchain_image(
+            device,
             command_buffer,
-            vk::PipelineBindPoint::GRAPHICS, // Must specify pipeline as descriptor sets are not unique to graphics pipelines
-            pipeline.pipeline_layout,
-            0,
-            &descriptor_sets_current_frame,
-            &[],
+            swapchain.images[image_index as usize],
+            vk::ImageLayout::UNDEFINED, // We don't care about the previous contents as the image is cleared
+            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
         );
 
-        device.cmd_bind_index_buffer(command_buffer, index_buffer, 0, vk::IndexType::UINT16);
+        let colour_attachments = [vk::RenderingAttachmentInfo::default()
+            .image_view(swapchain.image_views[image_index as usize])
+            .image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+            .load_op(vk::AttachmentLoadOp::CLEAR)
+            .store_op(vk::AttachmentStoreOp::STORE)
+            .clear_value(clear_value)];
+
+        let rendering_info = vk::RenderingInfo::default()
+            .render_area(render_area)
+            .layer_count(1)
+            .color_attachments(&colour_attachments);
+
+        match self {
+            RenderingPath::Core => device.cmd_begin_rendering(command_buffer, &rendering_info),
+            RenderingPath::Extensions { dynamic_rendering, .. } => {
+                dynamic_rendering.cmd_begin_rendering(command_buffer, &rendering_info)
+            }
+            RenderingPath::RenderPass => unreachable!(),
+        }
     }
 
-    // Viewport and scissor state for the pipeline are dynamic so need to set them in command buffer before submitting draw command
+    /// End rendering to a swapchain image and leave it ready for presentation
+    unsafe fn end_rendering(
+        &self, device: &ash::Device, command_buffer: vk::CommandBuffer, image_index: u32, swapchain: &Swapchain,
+    )
+    {
+        match self {
+            RenderingPath::Core => device.cmd_end_rendering(command_buffer),
+            RenderingPath::Extensions { dynamic_rendering, .. } => dynamic_rendering.cmd_end_rendering(command_buffer),
+            RenderingPath::RenderPass => {
+                device.cmd_end_render_pass(command_buffer);
+                return;
+            }
+        }
+
+        self.transition_swapchain_image(
+            device,
+            command_buffer,
+            swapchain.images[image_index as usize],
+            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            vk::ImageLayout::PRESENT_SRC_KHR,
+        );
+    }
+}
+
+/// What is bound for an object's draw besides its mesh
+pub struct ObjectBinding
+{
+    /// A frame's set holding the uniform buffer, and the object's texture unless textures are bindless
+    pub descriptor_set: vk::DescriptorSet,
+    /// Offset of the object's slice of the frame's uniform buffer
+    pub dynamic_offset: u32,
+    pub push_constants: pipeline::DrawPushConstants,
+}
+
+/// Record commands to begin rendering, set the dynamic states of the pipeline and lastly issue the draw commands
+///
+/// The skybox is drawn first, behind the objects. Each object binds its mesh's vertex and index buffers, unless the
+/// previous object used the same mesh, and is drawn with its descriptor set bound at the object's dynamic offset into
+/// the uniform buffer. The bindless set, if there is one, is bound once for every draw
+///
+/// The dispatches are recorded in order before rendering begins, so the draws can use what they write
+pub fn record_command_buffer(
+    device: &ash::Device, debug_utils: &DebugUtils, command_buffer: vk::CommandBuffer, image_index: u32,
+    rendering_path: &RenderingPath, pipeline: &pipeline::Pipeline, swapchain: &Swapchain, dispatches: &[Dispatch],
+    skybox: &skybox::Skybox, meshes: &[Mesh<vk_app::Vertex>], objects: &[DrawObject],
+    bindless_set: Option<vk::DescriptorSet>, object_bindings: &[ObjectBinding],
+) -> Result<()>
+{
+    let command_buffer_begin_info = vk::CommandBufferBeginInfo::default().flags(vk::CommandBufferUsageFlags::empty());
+
+    unsafe { device.begin_command_buffer(command_buffer, &command_buffer_begin_info) }?;
+    debug_utils.begin_label(command_buffer, "Draw frame");
+
+    if !dispatches.is_empty() {
+        debug_utils.begin_label(command_buffer, "Compute");
+        for dispatch in dispatches {
+            dispatch.cmd_record(device, command_buffer)?;
+        }
+        debug_utils.end_label(command_buffer);
+    }
+
+    // We are using SRGB which is floating point so must floating point for our clear values
+    // TODO: Make compatible with other formats
+    let clear_colour = vk::ClearValue { color: ClearColorValue { float32: [0.0, 0.0, 0.0, 1.0] } };
+
+    unsafe { rendering_path.begin_rendering(device, command_buffer, image_index, pipeline, swapchain, clear_colour) };
+
+    // Viewport and scissor state for the pipelines are dynamic so need to set them in command buffer before submitting draw commands
     let viewport = vk::Viewport::default()
         .x(0.0)
         .y(0.0)
@@ -98,10 +273,55 @@ pub fn record_command_buffer(
         .offset(vk::Offset2D { x: 0, y: 0 })
         .extent(swapchain.settings.extent);
 
+    unsafe { device.cmd_set_scissor(command_buffer, 0, [scissor].as_slice()) };
+
+    // Dynamic state stays set when the next pipeline is bound, as both pipelines make it dynamic
+    debug_utils.begin_label(command_buffer, "Skybox");
+    skybox.cmd_draw(device, command_buffer)?;
+    debug_utils.end_label(command_buffer);
+
     unsafe {
-        device.cmd_set_scissor(command_buffer, 0, [scissor].as_slice());
-        device.cmd_draw_indexed(command_buffer, vk_app::INDICES.len() as u32, 1, 0, 0, 0);
-        device.cmd_end_render_pass(command_buffer);
+        device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline.graphics_pipeline);
+
+        // Binding the frame's sets at set 0 leaves the bindless set bound as the layouts are compatible
+        if let Some(bindless_set) = bindless_set {
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                pipeline.pipeline_layout,
+                bindless::BINDLESS_SET,
+                &[bindless_set],
+                &[],
+            );
+        }
+
+        let mut bound_mesh = None;
+        for (object, object_binding) in objects.iter().zip(object_bindings) {
+            let mesh = &meshes[object.mesh];
+            if bound_mesh != Some(object.mesh) {
+                mesh.cmd_bind(device, command_buffer);
+                bound_mesh = Some(object.mesh);
+            }
+
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS, // Must specify pipeline as descriptor sets are not unique to graphics pipelines
+                pipeline.pipeline_layout,
+                0,
+                &[object_binding.descriptor_set],
+                &[object_binding.dynamic_offset], // One offset for each dynamic descriptor in the set, in binding order
+            );
+            pipeline.cmd_push_constants(
+                device,
+                command_buffer,
+                vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+                0,
+                &object_binding.push_constants,
+            )?;
+            mesh.cmd_draw(device, command_buffer);
+        }
+        rendering_path.end_rendering(device, command_buffer, image_index, swapchain);
+        debug_utils.end_label(command_buffer);
         Ok(device.end_command_buffer(command_buffer)?)
     }
 }
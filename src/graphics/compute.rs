@@ -0,0 +1,246 @@
+use crate::graphics::debug::DebugUtils;
+use crate::graphics::descriptors::DescriptorLayoutCache;
+use crate::graphics::device::LogicalDevice;
+use crate::graphics::pipeline;
+use crate::graphics::vk_app::Result;
+use ash::vk;
+use std::rc::Rc;
+
+/// A compute shader with its pipeline layout, for dispatches that run outside of rendering
+///
+/// The pipeline and its layout are destroyed when dropped, the descriptor set layout is owned by the layout cache the
+/// pipeline was created with
+pub struct ComputePipeline
+{
+    device:                    Rc<LogicalDevice>,
+    pub descriptor_set_layout: vk::DescriptorSetLayout,
+    pipeline_layout:           vk::PipelineLayout,
+    pipeline:                  vk::Pipeline,
+    push_constant_ranges:      Vec<vk::PushConstantRange>,
+}
+
+impl Drop for ComputePipeline
+{
+    fn drop(&mut self)
+    {
+        unsafe {
+            self.device.destroy_pipeline(self.pipeline, None);
+            self.device.destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+    }
+}
+
+impl ComputePipeline
+{
+    /// Create a compute pipeline from a SPIR-V file, with one descriptor set of the bindings
+    ///
+    /// The push constant ranges are checked the same way as for the graphics pipeline. The pipeline is named after the
+    /// file for debugging
+    pub fn new(
+        device: &Rc<LogicalDevice>, debug_utils: &DebugUtils, layout_cache: &mut DescriptorLayoutCache, shader_path: &str,
+        bindings: &[vk::DescriptorSetLayoutBinding], push_constant_ranges: &[vk::PushConstantRange],
+        max_push_constants_size: u32,
+    ) -> Result<Self>
+    {
+        pipeline::validate_push_constant_ranges(push_constant_ranges, max_push_constants_size)?;
+
+        let descriptor_set_layout = layout_cache.get_or_create(bindings)?;
+        let set_layouts = [descriptor_set_layout];
+        let pipeline_layout_create_info = vk::PipelineLayoutCreateInfo::default()
+            .set_layouts(&set_layouts)
+            .push_constant_ranges(push_constant_ranges);
+        let pipeline_layout = unsafe { device.create_pipeline_layout(&pipeline_layout_create_info, None) }?;
+
+        let shader_module = match pipeline::create_shader_module(device, shader_path.to_string()) {
+            Ok(shader_module) => shader_module,
+            Err(err) => {
+                unsafe { device.destroy_pipeline_layout(pipeline_layout, None) };
+                return Err(err);
+            }
+        };
+
+        // A compute pipeline has no fixed-function state, only the shader stage and the layout
+        let stage_create_info = vk::PipelineShaderStageCreateInfo::default()
+            .stage(vk::ShaderStageFlags::COMPUTE)
+            .module(shader_module)
+            .name(c"main");
+        let create_infos = [vk::ComputePipelineCreateInfo::default()
+            .stage(stage_create_info)
+            .layout(pipeline_layout)];
+        let result = unsafe { device.create_compute_pipelines(vk::PipelineCache::null(), &create_infos, None) };
+        unsafe { device.destroy_shader_module(shader_module, None) };
+        let pipeline = match result {
+            Ok(pipelines) => pipelines[0],
+            Err((_, err)) => {
+                unsafe { device.destroy_pipeline_layout(pipeline_layout, None) };
+                return Err(err.into());
+            }
+        };
+
+        debug_utils.set_name(pipeline_layout, format!("Compute pipeline layout ({})", shader_path).as_str());
+        debug_utils.set_name(pipeline, format!("Compute pipeline ({})", shader_path).as_str());
+
+        Ok(Self {
+            device: device.clone(),
+            descriptor_set_layout,
+            pipeline_layout,
+            pipeline,
+            push_constant_ranges: push_constant_ranges.to_vec(),
+        })
+    }
+}
+
+/// A barrier between a dispatch and the work before or after it that accesses the same resource
+pub enum Barrier
+{
+    Buffer
+    {
+        buffer:          vk::Buffer,
+        src_stage_mask:  vk::PipelineStageFlags,
+        src_access_mask: vk::AccessFlags,
+        dst_stage_mask:  vk::PipelineStageFlags,
+        dst_access_mask: vk::AccessFlags,
+    },
+    /// Also transitions every mip level and layer of the image between layouts
+    Image
+    {
+        image:           vk::Image,
+        old_layout:      vk::ImageLayout,
+        new_layout:      vk::ImageLayout,
+        src_stage_mask:  vk::PipelineStageFlags,
+        src_access_mask: vk::AccessFlags,
+        dst_stage_mask:  vk::PipelineStageFlags,
+        dst_access_mask: vk::AccessFlags,
+    },
+}
+
+/// A dispatch of a compute pipeline recorded before rendering begins, as dispatches can't be recorded while rendering
+///
+/// The barriers before the dispatch protect what it writes from earlier work still reading it, including the previous
+/// frames in flight, and the barriers after make what it writes visible to the dispatches and draws that read it
+pub struct Dispatch<'a>
+{
+    pub pipeline:        &'a ComputePipeline,
+    pub descriptor_set:  vk::DescriptorSet,
+    /// Pushed at offset 0 for the compute stage
+    pub push_constants:  &'a [u8],
+    pub group_counts:    [u32; 3],
+    pub barriers_before: Vec<Barrier>,
+    pub barriers_after:  Vec<Barrier>,
+}
+
+impl Dispatch<'_>
+{
+    /// Record the barriers before, the dispatch and the barriers after
+    pub fn cmd_record(&self, device: &ash::Device, command_buffer: vk::CommandBuffer) -> Result<()>
+    {
+        cmd_barriers(device, command_buffer, &self.barriers_before);
+
+        unsafe {
+            device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::COMPUTE, self.pipeline.pipeline);
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                self.pipeline.pipeline_layout,
+                0,
+                &[self.descriptor_set],
+                &[],
+            );
+        }
+        if !self.push_constants.is_empty() {
+            pipeline::cmd_push_constant_bytes(
+                device,
+                command_buffer,
+                self.pipeline.pipeline_layout,
+                &self.pipeline.push_constant_ranges,
+                vk::ShaderStageFlags::COMPUTE,
+                0,
+                self.push_constants,
+            )?;
+        }
+        let [x, y, z] = self.group_counts;
+        unsafe { device.cmd_dispatch(command_buffer, x, y, z) };
+
+        cmd_barriers(device, command_buffer, &self.barriers_after);
+        Ok(())
+    }
+}
+
+/// Record the barriers as a single pipeline barrier waiting on every barrier's source stages
+fn cmd_barriers(device: &ash::Device, command_buffer: vk::CommandBuffer, barriers: &[Barrier])
+{
+    if barriers.is_empty() {
+        return;
+    }
+
+    let mut src_stages = vk::PipelineStageFlags::empty();
+    let mut dst_stages = vk::PipelineStageFlags::empty();
+    let mut buffer_barriers = Vec::new();
+    let mut image_barriers = Vec::new();
+    for barrier in barriers {
+        match *barrier {
+            Barrier::Buffer {
+                buffer,
+                src_stage_mask,
+                src_access_mask,
+                dst_stage_mask,
+                dst_access_mask,
+            } => {
+                src_stages |= src_stage_mask;
+                dst_stages |= dst_stage_mask;
+                buffer_barriers.push(
+                    vk::BufferMemoryBarrier::default()
+                        .src_access_mask(src_access_mask)
+                        .dst_access_mask(dst_access_mask)
+                        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                        .buffer(buffer)
+                        .offset(0)
+                        .size(vk::WHOLE_SIZE),
+                );
+            }
+            Barrier::Image {
+                image,
+                old_layout,
+                new_layout,
+                src_stage_mask,
+                src_access_mask,
+                dst_stage_mask,
+                dst_access_mask,
+            } => {
+                src_stages |= src_stage_mask;
+                dst_stages |= dst_stage_mask;
+                image_barriers.push(
+                    vk::ImageMemoryBarrier::default()
+                        .old_layout(old_layout)
+                        .new_layout(new_layout)
+                        .src_access_mask(src_access_mask)
+                        .dst_access_mask(dst_access_mask)
+                        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                        .image(image)
+                        .subresource_range(
+                            vk::ImageSubresourceRange::default()
+                                .aspect_mask(vk::ImageAspectFlags::COLOR)
+                                .base_mip_level(0)
+                                .level_count(vk::REMAINING_MIP_LEVELS)
+                                .base_array_layer(0)
+                                .layer_count(vk::REMAINING_ARRAY_LAYERS),
+                        ),
+                );
+            }
+        }
+    }
+
+    unsafe {
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            src_stages,
+            dst_stages,
+            vk::DependencyFlags::empty(),
+            &[],
+            &buffer_barriers,
+            &image_barriers,
+        )
+    };
+}
@@ -0,0 +1,217 @@
+use crate::graphics::barriers::{self, ImageTransition};
+use crate::graphics::compute::{ComputePipeline, Dispatch};
+use crate::graphics::debug::DebugUtils;
+use crate::graphics::descriptors::{self, DescriptorAllocator, DescriptorLayoutCache};
+use crate::graphics::device::{LogicalDevice, SupportedPhysicalDevice};
+use crate::graphics::errors::VkAppError;
+use crate::graphics::samplers::{SamplerCache, SamplerDesc};
+use crate::graphics::textures::{self, Texture, TextureData, TextureKind};
+use crate::graphics::upload::UploadContext;
+use crate::graphics::vk_app::Result;
+use ash::vk;
+use std::rc::Rc;
+
+/// Invocations per workgroup along each axis of a face in equirect_to_cube.comp
+const GROUP_SIZE: u32 = 8;
+/// Every implementation supports storage images of this format, and it keeps the range of HDR environment maps
+const CUBEMAP_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+
+/// Converts equirectangular images, such as HDR environment maps, into cubemaps on the GPU
+///
+/// A compute shader writes each texel of each face from the image in the direction through it, then the faces' mip
+/// chains are blitted. The pipeline is destroyed when dropped, so the converter must outlive the uploads it records,
+/// and the sampler is owned by the sampler cache the converter was created with
+pub struct EquirectConverter
+{
+    device:   Rc<LogicalDevice>,
+    pipeline: ComputePipeline,
+    sampler:  vk::Sampler,
+}
+
+impl EquirectConverter
+{
+    pub fn new(
+        device: &Rc<LogicalDevice>, debug_utils: &DebugUtils, layout_cache: &mut DescriptorLayoutCache,
+        sampler_cache: &mut SamplerCache, max_push_constants_size: u32,
+    ) -> Result<Self>
+    {
+        let bindings = [
+            vk::DescriptorSetLayoutBinding::default()
+                .binding(0)
+                .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::COMPUTE),
+            vk::DescriptorSetLayoutBinding::default()
+                .binding(1)
+                .descriptor_type(vk::DescriptorType::SAMPLER)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::COMPUTE),
+            vk::DescriptorSetLayoutBinding::default()
+                .binding(2)
+                .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::COMPUTE),
+        ];
+        let pipeline = ComputePipeline::new(
+            device,
+            debug_utils,
+            layout_cache,
+            "equirect_to_cube.spv",
+            &bindings,
+            &[],
+            max_push_constants_size,
+        )?;
+
+        // Longitude wraps around the image but latitude stops at the poles, the shader picks the level to sample
+        let sampler = sampler_cache.get_or_create(&SamplerDesc {
+            address_modes: [
+                vk::SamplerAddressMode::REPEAT,
+                vk::SamplerAddressMode::CLAMP_TO_EDGE,
+                vk::SamplerAddressMode::CLAMP_TO_EDGE,
+            ],
+            max_anisotropy: None,
+            ..Default::default()
+        })?;
+
+        Ok(Self { device: device.clone(), pipeline, sampler })
+    }
+
+    /// Create a cubemap with square faces of face_size texels and a full mip chain, and record the upload of the
+    /// equirectangular image and the conversion in the upload context's current batch
+    ///
+    /// The image's centre faces +Z and its top row is +Y. The cubemap is R16G16B16A16_SFLOAT whatever the image's format,
+    /// so the image should be linear, e.g. from an HDR file. The uploaded image is freed once the batch completes. The
+    /// cubemap must not be drawn until the upload context is flushed and the upload has completed
+    pub fn convert(
+        &self, upload_context: &mut UploadContext, instance: &ash::Instance, physical_device: &SupportedPhysicalDevice,
+        debug_utils: &DebugUtils, equirect: &TextureData, face_size: u32,
+    ) -> Result<Texture>
+    {
+        if equirect.kind != TextureKind::Single {
+            return Err(VkAppError::DeviceError(format!(
+                "Equirectangular image {} must be a single image",
+                equirect.path
+            )));
+        }
+        if face_size == 0 {
+            return Err(VkAppError::DeviceError(format!("Cubemap of {} has no texels", equirect.path)));
+        }
+
+        let source = Texture::new(upload_context, instance, physical_device, &self.device, debug_utils, equirect)?;
+
+        let name = format!("{} cubemap", equirect.path);
+        let mip_levels = textures::mip_level_count(face_size, face_size);
+        // The faces are written as a storage image then blitted to fill the mip chain
+        let (image, allocation) = textures::create_image(
+            &self.device,
+            face_size,
+            face_size,
+            mip_levels,
+            TextureKind::Cube,
+            CUBEMAP_FORMAT,
+            vk::ImageUsageFlags::STORAGE
+                | vk::ImageUsageFlags::SAMPLED
+                | vk::ImageUsageFlags::TRANSFER_SRC
+                | vk::ImageUsageFlags::TRANSFER_DST,
+        )?;
+        debug_utils.set_name(image, format!("Texture {}", name).as_str());
+        let cubemap = Texture::with_view(
+            &self.device,
+            debug_utils,
+            &name,
+            (image, allocation),
+            CUBEMAP_FORMAT,
+            mip_levels,
+            TextureKind::Cube,
+        )?;
+
+        // Storage images can't be cube views, so the shader writes the faces as the layers of an array
+        let storage_view = StorageView::new(&self.device, debug_utils, &name, image)?;
+
+        let mut descriptor_allocator = DescriptorAllocator::new(&self.device, debug_utils, &name);
+        let descriptor_set = descriptor_allocator.allocate(self.pipeline.descriptor_set_layout)?;
+        descriptors::write_sampled_image(&self.device, descriptor_set, 0, source.view());
+        descriptors::write_sampler(&self.device, descriptor_set, 1, self.sampler);
+        descriptors::write_storage_image(&self.device, descriptor_set, 2, storage_view.view);
+
+        let command_buffer = upload_context.command_buffer()?;
+
+        // The shader writes the base level, the blits write the rest
+        let mut transitions =
+            vec![ImageTransition::colour(image, vk::ImageLayout::UNDEFINED, vk::ImageLayout::GENERAL).mips(0..1)];
+        if mip_levels > 1 {
+            transitions.push(
+                ImageTransition::colour(image, vk::ImageLayout::UNDEFINED, vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+                    .mips(1..mip_levels),
+            );
+        }
+        barriers::cmd_transition_images(&self.device, command_buffer, &transitions)?;
+
+        let group_count = face_size.div_ceil(GROUP_SIZE);
+        Dispatch {
+            pipeline: &self.pipeline,
+            descriptor_set,
+            push_constants: &[],
+            group_counts: [group_count, group_count, TextureKind::Cube.layer_count()],
+            barriers_before: Vec::new(),
+            barriers_after: Vec::new(),
+        }
+        .cmd_record(&self.device, command_buffer)?;
+
+        // The blits read the written base level, which is where mipmap generation expects it
+        let to_transfer =
+            ImageTransition::colour(image, vk::ImageLayout::GENERAL, vk::ImageLayout::TRANSFER_DST_OPTIMAL).mips(0..1);
+        barriers::cmd_transition_images(&self.device, command_buffer, &[to_transfer])?;
+        textures::cmd_generate_mipmaps(
+            &self.device,
+            command_buffer,
+            image,
+            face_size,
+            face_size,
+            mip_levels,
+            TextureKind::Cube.layer_count(),
+        )?;
+
+        // The dispatch reads the source and writes through the storage view, so they live until the batch completes
+        upload_context.retain_until_complete(source)?;
+        upload_context.retain_until_complete(storage_view)?;
+        upload_context.retain_until_complete(descriptor_allocator)?;
+
+        Ok(cubemap)
+    }
+}
+
+/// A view of the base level of a cubemap's faces as a 2D array, destroyed when dropped
+struct StorageView
+{
+    device: Rc<LogicalDevice>,
+    view:   vk::ImageView,
+}
+
+impl Drop for StorageView
+{
+    fn drop(&mut self) { unsafe { self.device.destroy_image_view(self.view, None) }; }
+}
+
+impl StorageView
+{
+    fn new(device: &Rc<LogicalDevice>, debug_utils: &DebugUtils, name: &str, image: vk::Image) -> Result<Self>
+    {
+        let image_view_create_info = vk::ImageViewCreateInfo::default()
+            .image(image)
+            .view_type(vk::ImageViewType::TYPE_2D_ARRAY)
+            .format(CUBEMAP_FORMAT)
+            .subresource_range(
+                vk::ImageSubresourceRange::default()
+                    .aspect_mask(vk::ImageAspectFlags::COLOR)
+                    .base_mip_level(0)
+                    .level_count(1)
+                    .base_array_layer(0)
+                    .layer_count(TextureKind::Cube.layer_count()),
+            );
+        let view = unsafe { device.create_image_view(&image_view_create_info, None)? };
+        debug_utils.set_name(view, format!("Storage view {}", name).as_str());
+
+        Ok(Self { device: device.clone(), view })
+    }
+}
@@ -0,0 +1,46 @@
+use crate::warn;
+use ash::{ext::debug_utils, vk};
+use std::ffi::CString;
+
+/// Gives Vulkan objects names and wraps command buffer regions in labels using VK_EXT_debug_utils
+///
+/// Validation messages and graphics debuggers such as RenderDoc then show "Texture cobble1.png" instead of a raw handle
+#[derive(Clone)]
+pub struct DebugUtils
+{
+    loader: debug_utils::Device,
+}
+
+impl DebugUtils
+{
+    pub fn new(instance: &ash::Instance, device: &ash::Device) -> Self
+    {
+        Self { loader: debug_utils::Device::new(instance, device) }
+    }
+
+    /// Names are only used for debugging so failing to set one is not an error
+    pub fn set_name<T: vk::Handle>(&self, handle: T, name: &str)
+    {
+        let name = CString::new(name).unwrap_or_else(|_| c"Invalid debug name".to_owned());
+        let name_info = vk::DebugUtilsObjectNameInfoEXT::default()
+            .object_handle(handle)
+            .object_name(&name);
+
+        if let Err(err) = unsafe { self.loader.set_debug_utils_object_name(&name_info) } {
+            warn!("Failed to set debug name {:?}: {}", name, err);
+        }
+    }
+
+    /// Begin a labelled region of a command buffer, every call must be matched by a call to end_label
+    pub fn begin_label(&self, command_buffer: vk::CommandBuffer, label: &str)
+    {
+        let label = CString::new(label).unwrap_or_else(|_| c"Invalid debug label".to_owned());
+        let label_info = vk::DebugUtilsLabelEXT::default().label_name(&label);
+        unsafe { self.loader.cmd_begin_debug_utils_label(command_buffer, &label_info) };
+    }
+
+    pub fn end_label(&self, command_buffer: vk::CommandBuffer)
+    {
+        unsafe { self.loader.cmd_end_debug_utils_label(command_buffer) };
+    }
+}
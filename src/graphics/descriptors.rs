@@ -0,0 +1,333 @@
+use crate::graphics::buffers::GpuBuffer;
+use crate::graphics::debug::DebugUtils;
+use crate::graphics::device::LogicalDevice;
+use crate::graphics::errors::VkAppError;
+use crate::graphics::vk_app::Result;
+use ash::vk;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// The sets in the first pool an allocator creates, each new pool holds more sets than the last
+const INITIAL_SETS_PER_POOL: u32 = 16;
+const MAX_SETS_PER_POOL: u32 = 4096;
+
+/// The descriptors of each type to make room for in a pool, per set the pool can hold
+///
+/// A pool can run out of one type of descriptor before it runs out of sets, in which case the allocator moves on to a
+/// new pool
+const POOL_SIZE_RATIOS: [(vk::DescriptorType, u32); 7] = [
+    (vk::DescriptorType::UNIFORM_BUFFER, 1),
+    (vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC, 1),
+    (vk::DescriptorType::COMBINED_IMAGE_SAMPLER, 2),
+    (vk::DescriptorType::SAMPLED_IMAGE, 1),
+    (vk::DescriptorType::SAMPLER, 1),
+    (vk::DescriptorType::STORAGE_BUFFER, 1),
+    (vk::DescriptorType::STORAGE_IMAGE, 1),
+];
+
+/// A layout binding without its immutable samplers, which the cache doesn't support, so it can be used as a key
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct BindingKey
+{
+    binding:          u32,
+    descriptor_type:  vk::DescriptorType,
+    descriptor_count: u32,
+    stage_flags:      vk::ShaderStageFlags,
+    binding_flags:    vk::DescriptorBindingFlags,
+}
+
+/// Creates each distinct descriptor set layout once and shares it between every pipeline and material that uses it
+///
+/// The layouts are destroyed when the cache is dropped, so the cache must outlive the pipelines created with them
+pub struct DescriptorLayoutCache
+{
+    device:      Rc<LogicalDevice>,
+    debug_utils: DebugUtils,
+    layouts:     HashMap<Vec<BindingKey>, vk::DescriptorSetLayout>,
+}
+
+impl Drop for DescriptorLayoutCache
+{
+    fn drop(&mut self)
+    {
+        for (_, layout) in self.layouts.drain() {
+            unsafe { self.device.destroy_descriptor_set_layout(layout, None) };
+        }
+    }
+}
+
+impl DescriptorLayoutCache
+{
+    pub fn new(device: &Rc<LogicalDevice>, debug_utils: &DebugUtils) -> Self
+    {
+        Self {
+            device:      device.clone(),
+            debug_utils: debug_utils.clone(),
+            layouts:     HashMap::new(),
+        }
+    }
+
+    /// Get the layout for the bindings, creating it if no layout with the same bindings has been requested before
+    ///
+    /// The order the bindings are given in doesn't matter
+    pub fn get_or_create(&mut self, bindings: &[vk::DescriptorSetLayoutBinding]) -> Result<vk::DescriptorSetLayout>
+    {
+        self.get_or_create_with_flags(bindings, &vec![vk::DescriptorBindingFlags::empty(); bindings.len()])
+    }
+
+    /// Get the layout for the bindings with descriptor indexing flags, one for each binding, creating it if needed
+    ///
+    /// A layout with an UPDATE_AFTER_BIND binding is created with UPDATE_AFTER_BIND_POOL, so its sets must be allocated
+    /// from a pool created with UPDATE_AFTER_BIND
+    pub fn get_or_create_with_flags(
+        &mut self, bindings: &[vk::DescriptorSetLayoutBinding], binding_flags: &[vk::DescriptorBindingFlags],
+    ) -> Result<vk::DescriptorSetLayout>
+    {
+        if bindings.iter().any(|binding| !binding.p_immutable_samplers.is_null()) {
+            return Err(VkAppError::DeviceError(String::from(
+                "Cached descriptor set layouts cannot have immutable samplers",
+            )));
+        }
+        if bindings.len() != binding_flags.len() {
+            return Err(VkAppError::DeviceError(format!(
+                "Descriptor set layout has {} bindings but {} binding flags",
+                bindings.len(),
+                binding_flags.len()
+            )));
+        }
+
+        let mut key: Vec<BindingKey> = bindings
+            .iter()
+            .zip(binding_flags)
+            .map(|(binding, &binding_flags)| BindingKey {
+                binding: binding.binding,
+                descriptor_type: binding.descriptor_type,
+                descriptor_count: binding.descriptor_count,
+                stage_flags: binding.stage_flags,
+                binding_flags,
+            })
+            .collect();
+        key.sort_by_key(|binding| binding.binding);
+
+        if let Some(&layout) = self.layouts.get(&key) {
+            return Ok(layout);
+        }
+
+        // Only chain the binding flags when there are some, so layouts work without descriptor indexing
+        let mut binding_flags_create_info =
+            vk::DescriptorSetLayoutBindingFlagsCreateInfo::default().binding_flags(binding_flags);
+        let mut layout_create_info = vk::DescriptorSetLayoutCreateInfo::default().bindings(bindings);
+        if binding_flags.iter().any(|flags| !flags.is_empty()) {
+            layout_create_info = layout_create_info.push_next(&mut binding_flags_create_info);
+        }
+        if binding_flags
+            .iter()
+            .any(|flags| flags.contains(vk::DescriptorBindingFlags::UPDATE_AFTER_BIND))
+        {
+            layout_create_info = layout_create_info.flags(vk::DescriptorSetLayoutCreateFlags::UPDATE_AFTER_BIND_POOL);
+        }
+        let layout = unsafe { self.device.create_descriptor_set_layout(&layout_create_info, None)? };
+        self.debug_utils
+            .set_name(layout, format!("Descriptor set layout {}", self.layouts.len()).as_str());
+
+        self.layouts.insert(key, layout);
+        Ok(layout)
+    }
+}
+
+/// Allocates descriptor sets of any layout, creating a new pool whenever the current one is exhausted
+///
+/// Sets are freed all at once by reset, which makes the pools available to allocate from again, so an allocator can be
+/// used for sets that only live for a frame. The pools are destroyed when the allocator is dropped
+pub struct DescriptorAllocator
+{
+    device:        Rc<LogicalDevice>,
+    debug_utils:   DebugUtils,
+    /// Used to name the allocator's pools
+    name:          String,
+    /// The pool allocated from, None until the first allocation
+    current_pool:  Option<vk::DescriptorPool>,
+    /// Exhausted pools with sets still allocated from them
+    used_pools:    Vec<vk::DescriptorPool>,
+    /// Pools that have been reset and have no sets allocated from them
+    free_pools:    Vec<vk::DescriptorPool>,
+    sets_per_pool: u32,
+    pool_count:    usize,
+}
+
+impl Drop for DescriptorAllocator
+{
+    fn drop(&mut self)
+    {
+        let pools = self
+            .current_pool
+            .take()
+            .into_iter()
+            .chain(self.used_pools.drain(..))
+            .chain(self.free_pools.drain(..));
+        for pool in pools {
+            // Destroying a pool frees every set allocated from it
+            unsafe { self.device.destroy_descriptor_pool(pool, None) };
+        }
+    }
+}
+
+impl DescriptorAllocator
+{
+    pub fn new(device: &Rc<LogicalDevice>, debug_utils: &DebugUtils, name: &str) -> Self
+    {
+        Self {
+            device:        device.clone(),
+            debug_utils:   debug_utils.clone(),
+            name:          name.to_string(),
+            current_pool:  None,
+            used_pools:    Vec::new(),
+            free_pools:    Vec::new(),
+            sets_per_pool: INITIAL_SETS_PER_POOL,
+            pool_count:    0,
+        }
+    }
+
+    /// Allocate a set with the layout, moving on to another pool if the current one has run out of space
+    pub fn allocate(&mut self, layout: vk::DescriptorSetLayout) -> Result<vk::DescriptorSet>
+    {
+        let pool = match self.current_pool {
+            Some(pool) => pool,
+            None => self.next_pool()?,
+        };
+
+        match self.allocate_from(pool, layout) {
+            Ok(descriptor_set) => Ok(descriptor_set),
+            // The pool doesn't have enough descriptors left of a type in the layout, so retire it and use a fresh one
+            Err(vk::Result::ERROR_OUT_OF_POOL_MEMORY) | Err(vk::Result::ERROR_FRAGMENTED_POOL) => {
+                self.used_pools.push(pool);
+                self.current_pool = None;
+                let pool = self.next_pool()?;
+                Ok(self.allocate_from(pool, layout)?)
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Free every set allocated from the allocator so the pools can be allocated from again
+    ///
+    /// None of the sets may still be in use by the GPU
+    pub fn reset(&mut self) -> Result<()>
+    {
+        for pool in self.current_pool.take().into_iter().chain(self.used_pools.drain(..)) {
+            unsafe {
+                self.device
+                    .reset_descriptor_pool(pool, vk::DescriptorPoolResetFlags::empty())?
+            };
+            self.free_pools.push(pool);
+        }
+        Ok(())
+    }
+
+    fn allocate_from(
+        &self, pool: vk::DescriptorPool, layout: vk::DescriptorSetLayout,
+    ) -> std::result::Result<vk::DescriptorSet, vk::Result>
+    {
+        let layouts = [layout];
+        let descriptor_set_allocate_info = vk::DescriptorSetAllocateInfo::default()
+            .descriptor_pool(pool)
+            .set_layouts(&layouts);
+        unsafe { self.device.allocate_descriptor_sets(&descriptor_set_allocate_info) }.map(|sets| sets[0])
+    }
+
+    /// Make a reset pool the current pool, or create a new pool if none are free
+    fn next_pool(&mut self) -> Result<vk::DescriptorPool>
+    {
+        let pool = match self.free_pools.pop() {
+            Some(pool) => pool,
+            None => {
+                let pool = self.create_pool(self.sets_per_pool)?;
+                // Each new pool is larger so an allocator that needs many sets ends up with few pools
+                self.sets_per_pool = (self.sets_per_pool * 2).min(MAX_SETS_PER_POOL);
+                pool
+            }
+        };
+        self.current_pool = Some(pool);
+        Ok(pool)
+    }
+
+    fn create_pool(&mut self, max_sets: u32) -> Result<vk::DescriptorPool>
+    {
+        let pool_sizes =
+            POOL_SIZE_RATIOS.map(|(ty, ratio)| vk::DescriptorPoolSize { ty, descriptor_count: ratio * max_sets });
+
+        // Sets are freed by resetting the whole pool, so FREE_DESCRIPTOR_SET isn't needed
+        let pool_create_info = vk::DescriptorPoolCreateInfo::default()
+            .pool_sizes(&pool_sizes)
+            .max_sets(max_sets);
+        let pool = unsafe { self.device.create_descriptor_pool(&pool_create_info, None) }?;
+
+        self.debug_utils
+            .set_name(pool, format!("Descriptor pool {} ({})", self.pool_count, self.name).as_str());
+        self.pool_count += 1;
+
+        Ok(pool)
+    }
+}
+
+/// Point a storage buffer binding of a set at the whole of a buffer
+pub fn write_storage_buffer<T: Copy>(
+    device: &ash::Device, descriptor_set: vk::DescriptorSet, binding: u32, buffer: &GpuBuffer<T>,
+)
+{
+    let buffer_info = [vk::DescriptorBufferInfo::default()
+        .buffer(buffer.handle())
+        .offset(0)
+        .range(vk::WHOLE_SIZE)];
+    let descriptor_write = vk::WriteDescriptorSet::default()
+        .dst_set(descriptor_set)
+        .dst_binding(binding)
+        .dst_array_element(0)
+        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+        .buffer_info(&buffer_info);
+    unsafe { device.update_descriptor_sets(&[descriptor_write], &[]) };
+}
+
+/// Point a storage image binding of a set at an image view, shaders load and store the image in GENERAL layout
+pub fn write_storage_image(device: &ash::Device, descriptor_set: vk::DescriptorSet, binding: u32, image_view: vk::ImageView)
+{
+    let image_info = [vk::DescriptorImageInfo::default()
+        .image_layout(vk::ImageLayout::GENERAL)
+        .image_view(image_view)];
+    let descriptor_write = vk::WriteDescriptorSet::default()
+        .dst_set(descriptor_set)
+        .dst_binding(binding)
+        .dst_array_element(0)
+        .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+        .image_info(&image_info);
+    unsafe { device.update_descriptor_sets(&[descriptor_write], &[]) };
+}
+
+/// Point a sampled image binding of a set at an image view, shaders sample the image in SHADER_READ_ONLY_OPTIMAL layout
+/// with a sampler from a separate binding
+pub fn write_sampled_image(device: &ash::Device, descriptor_set: vk::DescriptorSet, binding: u32, image_view: vk::ImageView)
+{
+    let image_info = [vk::DescriptorImageInfo::default()
+        .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+        .image_view(image_view)];
+    let descriptor_write = vk::WriteDescriptorSet::default()
+        .dst_set(descriptor_set)
+        .dst_binding(binding)
+        .dst_array_element(0)
+        .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+        .image_info(&image_info);
+    unsafe { device.update_descriptor_sets(&[descriptor_write], &[]) };
+}
+
+/// Point a sampler binding of a set at a sampler
+pub fn write_sampler(device: &ash::Device, descriptor_set: vk::DescriptorSet, binding: u32, sampler: vk::Sampler)
+{
+    let image_info = [vk::DescriptorImageInfo::default().sampler(sampler)];
+    let descriptor_write = vk::WriteDescriptorSet::default()
+        .dst_set(descriptor_set)
+        .dst_binding(binding)
+        .dst_array_element(0)
+        .descriptor_type(vk::DescriptorType::SAMPLER)
+        .image_info(&image_info);
+    unsafe { device.update_descriptor_sets(&[descriptor_write], &[]) };
+}
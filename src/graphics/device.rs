@@ -1,6 +1,7 @@
-use crate::graphics::{errors::VkAppError, presentation, vk_app::Result};
+use crate::graphics::{allocator::Allocator, errors::VkAppError, presentation, vk_app::Result};
 use crate::{log, project, warn};
 use ash::{ext::debug_utils, khr, vk, Entry, Instance};
+use std::cell::RefCell;
 use std::ffi::{CStr, CString};
 
 /// An error that describes some problem with the capabilities of a physical device or the execution of a function using a physical device
@@ -30,7 +31,7 @@ impl<'a, const SIZE: usize> Extensions<'a, SIZE>
     /// Checks if all the extension list are found within the available extensions
     ///
     /// Error returns a String of comma-separated requested extensions that were not found within the available extensions
-    pub fn are_in<T: ExtensionNames>(&self, available_extensions: Vec<T>) -> std::result::Result<(), String>
+    pub fn are_in<T: ExtensionNames>(&self, available_extensions: &[T]) -> std::result::Result<(), String>
     {
         if let Some(not_found_layers) = self
             .0
@@ -75,7 +76,20 @@ impl ExtensionNames for vk::ExtensionProperties
 
 const VALIDATION_LAYERS: Extensions<1> = Extensions([c"VK_LAYER_KHRONOS_validation"]);
 const EXTENSIONS: Extensions<3> = Extensions([vk::KHR_SURFACE_NAME, vk::EXT_DEBUG_UTILS_NAME, vk::KHR_WIN32_SURFACE_NAME]);
+/// The instance extensions needed to create a surface for a window
+const SURFACE_EXTENSIONS: Extensions<2> = Extensions([vk::KHR_SURFACE_NAME, vk::KHR_WIN32_SURFACE_NAME]);
 const DEVICE_EXTENSIONS: Extensions<1> = Extensions([vk::KHR_SWAPCHAIN_NAME]);
+/// Device extensions that provide dynamic rendering and synchronization2 on devices older than Vulkan 1.3
+const DYNAMIC_RENDERING_EXTENSIONS: Extensions<2> =
+    Extensions([vk::KHR_DYNAMIC_RENDERING_NAME, vk::KHR_SYNCHRONIZATION2_NAME]);
+/// Optional device extension for querying each memory heap's budget
+const MEMORY_BUDGET_EXTENSIONS: Extensions<1> = Extensions([vk::EXT_MEMORY_BUDGET_NAME]);
+/// Optional device extension for bindless textures on devices older than Vulkan 1.2, it depends on VK_KHR_maintenance3
+/// which is core from 1.1
+const DESCRIPTOR_INDEXING_EXTENSIONS: Extensions<1> = Extensions([vk::EXT_DESCRIPTOR_INDEXING_NAME]);
+
+/// The newest Vulkan version we have a code path for
+const MAX_API_VERSION: u32 = vk::API_VERSION_1_3;
 
 unsafe extern "system" fn vulkan_debug_callback(
     message_severity: vk::DebugUtilsMessageSeverityFlagsEXT, message_type: vk::DebugUtilsMessageTypeFlagsEXT,
@@ -102,8 +116,49 @@ unsafe extern "system" fn vulkan_debug_callback(
     vk::FALSE
 }
 
+#[cfg(not(any(feature = "loaded", feature = "linked")))]
+compile_error!("Either the loaded or linked feature must be enabled to load Vulkan");
+
+/// Load the Vulkan loader library at runtime
+///
+/// Fails with a LoaderError rather than crashing if no Vulkan driver is installed
+#[cfg(feature = "loaded")]
+pub fn create_entry() -> Result<Entry> { Ok(unsafe { Entry::load() }?) }
+
+/// Use the Vulkan loader linked at build time, the binary will not start on machines without it
+#[cfg(all(feature = "linked", not(feature = "loaded")))]
+pub fn create_entry() -> Result<Entry> { Ok(Entry::linked()) }
+
+/// Formats a packed Vulkan version number as major.minor.patch
+pub fn version_string(version: u32) -> String
+{
+    format!(
+        "{}.{}.{}",
+        vk::api_version_major(version),
+        vk::api_version_minor(version),
+        vk::api_version_patch(version)
+    )
+}
+
+/// Negotiate the Vulkan version to request when creating the instance
+///
+/// This is the highest version the loader supports, capped at the newest version we have a code path for
+///
+/// A Vulkan 1.0 loader does not have vkEnumerateInstanceVersion, in which case we must request 1.0
+pub fn get_instance_api_version(entry: &Entry) -> Result<u32>
+{
+    let loader_version = unsafe { entry.try_enumerate_instance_version() }?.unwrap_or(vk::API_VERSION_1_0);
+    let api_version = loader_version.min(MAX_API_VERSION);
+    log!(
+        "Vulkan loader supports version {}, requesting version {}",
+        version_string(loader_version),
+        version_string(api_version)
+    );
+    Ok(api_version)
+}
+
 /// Initialize the Vulkan library by creating a connection between the application and the Vulkan library
-pub fn create_instance(entry: &Entry) -> Result<Instance>
+pub fn create_instance(entry: &Entry, api_version: u32) -> Result<Instance>
 {
     let app_name = CString::new(project::APP_NAME).unwrap();
     let engine_name = CString::new("No Engine").unwrap();
@@ -113,19 +168,19 @@ pub fn create_instance(entry: &Entry) -> Result<Instance>
         .application_version(vk::make_api_version(0, project::VERSION_MAJOR, project::VERSION_MINOR, 0))
         .engine_name(engine_name.as_c_str())
         .engine_version(vk::make_api_version(0, 0, 1, 0))
-        .api_version(vk::API_VERSION_1_0);
+        .api_version(api_version);
 
     // A validation layer is a debugging tool that hooks into Vulkan function calls to apply additional operations
     // TODO: Should only request and enable validation layers if in DEBUG mode
     let instance_layer_properties = unsafe { entry.enumerate_instance_layer_properties() }?;
-    VALIDATION_LAYERS.are_in(instance_layer_properties).map_err(|err_string| {
+    VALIDATION_LAYERS.are_in(&instance_layer_properties).map_err(|err_string| {
         VkAppError::InstanceError(format!("Did not find requested validation layer(s) {}", err_string))
     })?;
 
     // An instance extension is a non-device related extension
     let extension_properties = unsafe { entry.enumerate_instance_extension_properties(None) }?;
     EXTENSIONS
-        .are_in(extension_properties)
+        .are_in(&extension_properties)
         .map_err(|err_string| VkAppError::InstanceError(format!("Did not find requested extension(s) {}", err_string)))?;
 
     let extension_ptrs = EXTENSIONS.as_ptrs();
@@ -139,6 +194,31 @@ pub fn create_instance(entry: &Entry) -> Result<Instance>
     Ok(unsafe { entry.create_instance(&instance_info, None) }?)
 }
 
+/// Create an instance for inspecting devices rather than rendering
+///
+/// Validation layers and debug utils are not enabled so this works on machines without the Vulkan SDK installed
+///
+/// The surface extensions are only enabled if available, returns whether they were so a surface can be created
+pub fn create_inspection_instance(entry: &Entry, api_version: u32) -> Result<(Instance, bool)>
+{
+    let app_name = CString::new(project::APP_NAME).unwrap();
+    let app_info = vk::ApplicationInfo::default()
+        .application_name(app_name.as_c_str())
+        .application_version(vk::make_api_version(0, project::VERSION_MAJOR, project::VERSION_MINOR, 0))
+        .api_version(api_version);
+
+    let extension_properties = unsafe { entry.enumerate_instance_extension_properties(None) }?;
+    let surface_support = SURFACE_EXTENSIONS.are_in(&extension_properties).is_ok();
+    let extension_ptrs = SURFACE_EXTENSIONS.as_ptrs();
+
+    let mut instance_info = vk::InstanceCreateInfo::default().application_info(&app_info);
+    if surface_support {
+        instance_info = instance_info.enabled_extension_names(&extension_ptrs);
+    }
+
+    Ok((unsafe { entry.create_instance(&instance_info, None) }?, surface_support))
+}
+
 pub fn create_debug_messenger(
     entry: &Entry, instance: &Instance,
 ) -> Result<(debug_utils::Instance, vk::DebugUtilsMessengerEXT)>
@@ -160,120 +240,261 @@ pub fn create_debug_messenger(
     Ok((debug_utils_loader, debug_call_back))
 }
 
+/// How a device records rendering commands, from most to least preferred
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum RenderingSupport
+{
+    /// Dynamic rendering and synchronization2 are core features (Vulkan 1.3)
+    Core,
+    /// Dynamic rendering and synchronization2 come from the VK_KHR_dynamic_rendering and VK_KHR_synchronization2 extensions
+    Extensions,
+    /// Vulkan 1.0 render pass and framebuffer objects
+    RenderPass,
+}
+
+/// Where a device's descriptor indexing features come from, which decides whether textures can be drawn bindless
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum DescriptorIndexingSupport
+{
+    /// Descriptor indexing is a core feature (Vulkan 1.2)
+    Core,
+    /// Descriptor indexing comes from the VK_EXT_descriptor_indexing extension
+    Extension,
+    /// Textures are bound one descriptor set at a time
+    Unsupported,
+}
+
 /// Describes a device that has the necessary capabilities to be used for our Vulkan app
 #[derive(Clone)]
 pub struct SupportedPhysicalDevice
 {
-    pub vk_physical_device:    vk::PhysicalDevice,
-    pub device_name:           String,
-    pub graphics_family_index: u32,
-    pub present_family_index:  u32,
+    pub vk_physical_device:     vk::PhysicalDevice,
+    pub device_name:            String,
+    pub graphics_family_index:  u32,
+    pub present_family_index:   u32,
+    /// The Vulkan version usable with this device, the lower of the device's version and the instance's version
+    pub api_version:            u32,
+    pub rendering_support:      RenderingSupport,
+    /// VK_EXT_memory_budget is available, and vkGetPhysicalDeviceMemoryProperties2 to query it with (Vulkan 1.1)
+    pub memory_budget:          bool,
+    pub descriptor_indexing:    DescriptorIndexingSupport,
+    /// BC1 to BC7 compressed formats can be sampled, otherwise compressed textures are decompressed on the CPU
+    pub texture_compression_bc: bool,
 }
 
-/// Enumerates the available physical devices and returns a list of them and the device's corresponding swapchain settings
-pub fn get_physical_devices(
-    instance: &Instance, surface_loader: &khr::surface::Instance, surface: vk::SurfaceKHR,
-) -> Result<Vec<(SupportedPhysicalDevice, presentation::SurfaceDetails)>>
+/// Checks whether the device can render without render pass and framebuffer objects
+fn get_rendering_support(
+    instance: &Instance, physical_device: vk::PhysicalDevice, api_version: u32,
+    extension_properties: &[vk::ExtensionProperties],
+) -> RenderingSupport
 {
-    let physical_devices = unsafe { instance.enumerate_physical_devices() }?;
-    let mut supported_devices: Vec<(SupportedPhysicalDevice, presentation::SurfaceDetails)> = Vec::new();
-    for physical_device in physical_devices {
-        let device_properties = unsafe { instance.get_physical_device_properties(physical_device) };
-        let device_name = unsafe { CStr::from_ptr(device_properties.device_name.as_ptr()) }
-            .to_str()
-            .unwrap_or_else(|utf_error| {
-                warn!("Error reading device name from ptr, {}", utf_error);
-                "Unknown Device"
-            });
-        log!("Found device {}", device_name);
+    // vkGetPhysicalDeviceFeatures2 is core from 1.1, and VK_KHR_dynamic_rendering depends on VK_KHR_create_renderpass2 and VK_KHR_depth_stencil_resolve which are core from 1.2
+    // Rather than enabling that chain of extensions on older devices we use the render pass path for them
+    if api_version < vk::API_VERSION_1_2 {
+        return RenderingSupport::RenderPass;
+    }
 
-        // The device must have the extensions we requested
-        let extension_properties = match unsafe { instance.enumerate_device_extension_properties(physical_device) } {
-            Ok(value) => value,
-            Err(vk_error) => {
-                warn!(
-                    "Error getting device {} extension properties: {}, skipping",
-                    device_name,
-                    vk_error.to_string()
-                );
-                continue;
-            }
-        };
-        if let Err(err_string) = DEVICE_EXTENSIONS.are_in(extension_properties) {
-            warn!(
-                "Device {} does not have required device extension(s): {}, skipping",
-                device_name, err_string
-            );
-            continue;
+    if api_version >= vk::API_VERSION_1_3 {
+        let mut vulkan_13_features = vk::PhysicalDeviceVulkan13Features::default();
+        {
+            let mut features = vk::PhysicalDeviceFeatures2::default().push_next(&mut vulkan_13_features);
+            unsafe { instance.get_physical_device_features2(physical_device, &mut features) };
         }
+        if vulkan_13_features.dynamic_rendering == vk::TRUE && vulkan_13_features.synchronization2 == vk::TRUE {
+            return RenderingSupport::Core;
+        }
+    }
 
-        /* Almost every operation in Vulkan requires commands to be submitted to a queue
-           There are different types from queues which come from different queue families
-           Each queue family allows only a subset of commands
-           We check which queue families are supported by the device and which one(s) support the commands we want to use
-        */
-        let mut graphics_family_index: u32 = 0;
-        let mut present_family_index: u32 = 0;
-        let mut graphics_support: bool = false;
-        let mut present_support: bool = false;
-
-        // For now, we just try and look for any queue family that support graphics and presenting to a surface
-        // TODO: Investigate if using the same queue family for graphics and presenting is more efficient
-        for (queue_family_properties, index) in
-            unsafe { instance.get_physical_device_queue_family_properties(physical_device) }
-                .iter()
-                .zip(0u32..)
-        // Zip with u32 instead of enumerate() as vk::QueueFlags is u32
+    if DYNAMIC_RENDERING_EXTENSIONS.are_in(extension_properties).is_ok() {
+        let mut dynamic_rendering_features = vk::PhysicalDeviceDynamicRenderingFeaturesKHR::default();
+        let mut synchronization2_features = vk::PhysicalDeviceSynchronization2FeaturesKHR::default();
         {
-            if (queue_family_properties.queue_flags & vk::QueueFlags::GRAPHICS).contains(vk::QueueFlags::GRAPHICS) {
-                graphics_family_index = index;
-                graphics_support = true;
-            }
-            if unsafe { surface_loader.get_physical_device_surface_support(physical_device, index, surface) }? {
-                present_family_index = index;
-                present_support = true;
-            }
-            if graphics_support && present_support {
-                break;
-            }
+            let mut features = vk::PhysicalDeviceFeatures2::default()
+                .push_next(&mut dynamic_rendering_features)
+                .push_next(&mut synchronization2_features);
+            unsafe { instance.get_physical_device_features2(physical_device, &mut features) };
+        }
+        if dynamic_rendering_features.dynamic_rendering == vk::TRUE && synchronization2_features.synchronization2 == vk::TRUE
+        {
+            return RenderingSupport::Extensions;
         }
+    }
+
+    RenderingSupport::RenderPass
+}
+
+/// Checks whether the device has the descriptor indexing features needed for bindless textures
+///
+/// Bindless textures are an array of sampled images that is only partially filled and written to while frames using it
+/// are in flight, indexed by a push constant in the fragment shader
+fn get_descriptor_indexing_support(
+    instance: &Instance, physical_device: vk::PhysicalDevice, api_version: u32,
+    extension_properties: &[vk::ExtensionProperties],
+) -> DescriptorIndexingSupport
+{
+    // vkGetPhysicalDeviceFeatures2 is core from 1.1
+    if api_version < vk::API_VERSION_1_1 {
+        return DescriptorIndexingSupport::Unsupported;
+    }
+
+    let features = unsafe { instance.get_physical_device_features(physical_device) };
+    if features.shader_sampled_image_array_dynamic_indexing == vk::FALSE {
+        return DescriptorIndexingSupport::Unsupported;
+    }
+
+    let support = if api_version >= vk::API_VERSION_1_2 {
+        DescriptorIndexingSupport::Core
+    } else if DESCRIPTOR_INDEXING_EXTENSIONS.are_in(extension_properties).is_ok() {
+        DescriptorIndexingSupport::Extension
+    } else {
+        return DescriptorIndexingSupport::Unsupported;
+    };
+
+    // The extension's feature struct has the same members as the descriptor indexing part of the 1.2 features
+    let mut descriptor_indexing_features = vk::PhysicalDeviceDescriptorIndexingFeatures::default();
+    {
+        let mut features = vk::PhysicalDeviceFeatures2::default().push_next(&mut descriptor_indexing_features);
+        unsafe { instance.get_physical_device_features2(physical_device, &mut features) };
+    }
+    if descriptor_indexing_features.runtime_descriptor_array == vk::TRUE
+        && descriptor_indexing_features.descriptor_binding_partially_bound == vk::TRUE
+        && descriptor_indexing_features.descriptor_binding_sampled_image_update_after_bind == vk::TRUE
+        && descriptor_indexing_features.descriptor_binding_update_unused_while_pending == vk::TRUE
+    {
+        support
+    } else {
+        DescriptorIndexingSupport::Unsupported
+    }
+}
 
-        if !graphics_support {
-            warn!("Device {} does not support graphics queue, skipping", device_name);
-            continue;
+/// Reads the device name from the device properties
+pub fn get_device_name(device_properties: &vk::PhysicalDeviceProperties) -> String
+{
+    match device_properties.device_name_as_c_str() {
+        Ok(device_name) => device_name.to_string_lossy().into_owned(),
+        Err(err) => {
+            warn!("Error reading device name, {}", err);
+            String::from("Unknown Device")
         }
+    }
+}
 
-        if !present_support {
-            warn!("Device {} does not support present queue, skipping", device_name);
-            continue;
+/// Checks whether a physical device has the capabilities our Vulkan app needs
+///
+/// Returns Ok(Err(reason)) with a description of why the device can't be used, so the reason can be both logged and reported
+pub fn check_physical_device(
+    instance: &Instance, instance_api_version: u32, physical_device: vk::PhysicalDevice,
+    surface_loader: &khr::surface::Instance, surface: vk::SurfaceKHR,
+) -> Result<std::result::Result<(SupportedPhysicalDevice, presentation::SurfaceDetails), String>>
+{
+    let device_properties = unsafe { instance.get_physical_device_properties(physical_device) };
+    let device_name = get_device_name(&device_properties);
+
+    // The device must have the extensions we requested
+    let extension_properties = match unsafe { instance.enumerate_device_extension_properties(physical_device) } {
+        Ok(value) => value,
+        Err(vk_error) => return Ok(Err(format!("Error getting device extension properties: {}", vk_error))),
+    };
+    if let Err(err_string) = DEVICE_EXTENSIONS.are_in(&extension_properties) {
+        return Ok(Err(format!("Does not have required device extension(s): {}", err_string)));
+    }
+
+    /* Almost every operation in Vulkan requires commands to be submitted to a queue
+       There are different types from queues which come from different queue families
+       Each queue family allows only a subset of commands
+       We check which queue families are supported by the device and which one(s) support the commands we want to use
+    */
+    let mut graphics_family_index: u32 = 0;
+    let mut present_family_index: u32 = 0;
+    let mut graphics_support: bool = false;
+    let mut present_support: bool = false;
+
+    // For now, we just try and look for any queue family that support graphics and presenting to a surface
+    // TODO: Investigate if using the same queue family for graphics and presenting is more efficient
+    for (queue_family_properties, index) in unsafe { instance.get_physical_device_queue_family_properties(physical_device) }
+        .iter()
+        .zip(0u32..)
+    // Zip with u32 instead of enumerate() as vk::QueueFlags is u32
+    {
+        if (queue_family_properties.queue_flags & vk::QueueFlags::GRAPHICS).contains(vk::QueueFlags::GRAPHICS) {
+            graphics_family_index = index;
+            graphics_support = true;
         }
+        if unsafe { surface_loader.get_physical_device_surface_support(physical_device, index, surface) }? {
+            present_family_index = index;
+            present_support = true;
+        }
+        if graphics_support && present_support {
+            break;
+        }
+    }
+
+    if !graphics_support {
+        return Ok(Err(String::from("Does not support graphics queue")));
+    }
+
+    if !present_support {
+        return Ok(Err(String::from("Does not support present queue")));
+    }
+
+    let surface_details = match presentation::get_surface_details(physical_device, surface, surface_loader) {
+        Ok(surface_details) => surface_details,
+        Err(e) => return Ok(Err(format!("Failed to get acceptable surface capabilities, {}", e))),
+    };
+
+    let physical_device_features = unsafe { instance.get_physical_device_features(physical_device) };
+    if physical_device_features.sampler_anisotropy == vk::FALSE {
+        warn!("Device {} does not support sampler anisotropy", device_name);
+    }
+
+    // Device level functionality is limited by the version the instance was created with
+    let api_version = device_properties.api_version.min(instance_api_version);
+    let rendering_support = get_rendering_support(instance, physical_device, api_version, &extension_properties);
+    let memory_budget = api_version >= vk::API_VERSION_1_1 && MEMORY_BUDGET_EXTENSIONS.are_in(&extension_properties).is_ok();
+    let descriptor_indexing = get_descriptor_indexing_support(instance, physical_device, api_version, &extension_properties);
+
+    Ok(Ok((
+        SupportedPhysicalDevice {
+            vk_physical_device: physical_device,
+            device_name,
+            graphics_family_index,
+            present_family_index,
+            api_version,
+            rendering_support,
+            memory_budget,
+            descriptor_indexing,
+            texture_compression_bc: physical_device_features.texture_compression_bc == vk::TRUE,
+        },
+        surface_details,
+    )))
+}
 
-        let surface_details = match presentation::get_surface_details(physical_device, surface, surface_loader) {
-            Ok(surface_details) => surface_details,
-            Err(e) => {
-                warn!(
-                    "Device {} failed to get acceptable surface capabilities, {}, skipping",
+/// Enumerates the available physical devices and returns a list of them and the device's corresponding swapchain settings
+pub fn get_physical_devices(
+    instance: &Instance, instance_api_version: u32, surface_loader: &khr::surface::Instance, surface: vk::SurfaceKHR,
+) -> Result<Vec<(SupportedPhysicalDevice, presentation::SurfaceDetails)>>
+{
+    let physical_devices = unsafe { instance.enumerate_physical_devices() }?;
+    let mut supported_devices: Vec<(SupportedPhysicalDevice, presentation::SurfaceDetails)> = Vec::new();
+    for physical_device in physical_devices {
+        let device_properties = unsafe { instance.get_physical_device_properties(physical_device) };
+        let device_name = get_device_name(&device_properties);
+        log!("Found device {}", device_name);
+
+        match check_physical_device(instance, instance_api_version, physical_device, surface_loader, surface)? {
+            Ok((supported_device, surface_details)) => {
+                log!(
+                    "Device {} supports Vulkan {}, rendering support {:?}, descriptor indexing {:?}, BC compression {}",
                     device_name,
-                    e.to_string()
+                    version_string(supported_device.api_version),
+                    supported_device.rendering_support,
+                    supported_device.descriptor_indexing,
+                    supported_device.texture_compression_bc
                 );
-                continue;
+                supported_devices.push((supported_device, surface_details));
             }
-        };
-
-        let physical_device_features = unsafe { instance.get_physical_device_features(physical_device) };
-        if physical_device_features.sampler_anisotropy == vk::FALSE {
-            warn!("Device {} does not support sampler anisotropy, skipping", device_name);
+            Err(reason) => warn!("Device {} skipped: {}", device_name, reason),
         }
-
-        supported_devices.push((
-            SupportedPhysicalDevice {
-                vk_physical_device: physical_device,
-                device_name: device_name.to_string(),
-                graphics_family_index,
-                present_family_index,
-            },
-            surface_details,
-        ));
     }
 
     Ok(supported_devices)
@@ -310,14 +531,89 @@ pub fn create_logical_device(instance: &Instance, physical_device: &SupportedPhy
 
     // We require anisotropy
     // TODO: Make an option
-    let device_features = vk::PhysicalDeviceFeatures::default().sampler_anisotropy(true);
+    let bindless = physical_device.descriptor_indexing != DescriptorIndexingSupport::Unsupported;
+    let device_features = vk::PhysicalDeviceFeatures::default()
+        .sampler_anisotropy(true)
+        .shader_sampled_image_array_dynamic_indexing(bindless)
+        .texture_compression_bc(physical_device.texture_compression_bc);
 
     // At this point we should know that the physical device supports the requested device extensions so we don't need to check again
-    let device_extension_ptrs = DEVICE_EXTENSIONS.as_ptrs();
-    let device_info = vk::DeviceCreateInfo::default()
+    let mut device_extension_ptrs = DEVICE_EXTENSIONS.as_ptrs().to_vec();
+    if physical_device.rendering_support == RenderingSupport::Extensions {
+        device_extension_ptrs.extend(DYNAMIC_RENDERING_EXTENSIONS.as_ptrs());
+    }
+    if physical_device.memory_budget {
+        device_extension_ptrs.extend(MEMORY_BUDGET_EXTENSIONS.as_ptrs());
+    }
+    if physical_device.descriptor_indexing == DescriptorIndexingSupport::Extension {
+        device_extension_ptrs.extend(DESCRIPTOR_INDEXING_EXTENSIONS.as_ptrs());
+    }
+
+    let mut vulkan_13_features = vk::PhysicalDeviceVulkan13Features::default()
+        .dynamic_rendering(true)
+        .synchronization2(true);
+    let mut dynamic_rendering_features = vk::PhysicalDeviceDynamicRenderingFeaturesKHR::default().dynamic_rendering(true);
+    let mut synchronization2_features = vk::PhysicalDeviceSynchronization2FeaturesKHR::default().synchronization2(true);
+    let mut vulkan_12_features = vk::PhysicalDeviceVulkan12Features::default()
+        .runtime_descriptor_array(true)
+        .descriptor_binding_partially_bound(true)
+        .descriptor_binding_sampled_image_update_after_bind(true)
+        .descriptor_binding_update_unused_while_pending(true);
+    let mut descriptor_indexing_features = vk::PhysicalDeviceDescriptorIndexingFeatures::default()
+        .runtime_descriptor_array(true)
+        .descriptor_binding_partially_bound(true)
+        .descriptor_binding_sampled_image_update_after_bind(true)
+        .descriptor_binding_update_unused_while_pending(true);
+
+    let mut device_info = vk::DeviceCreateInfo::default()
         .queue_create_infos(queue_create_infos.as_slice())
         .enabled_features(&device_features)
         .enabled_extension_names(&device_extension_ptrs);
 
+    // The features must be enabled as well as the extensions being available
+    device_info = match physical_device.rendering_support {
+        RenderingSupport::Core => device_info.push_next(&mut vulkan_13_features),
+        RenderingSupport::Extensions => device_info
+            .push_next(&mut dynamic_rendering_features)
+            .push_next(&mut synchronization2_features),
+        RenderingSupport::RenderPass => device_info,
+    };
+    // The 1.2 features struct can't be chained along with the extension's struct for the same features
+    device_info = match physical_device.descriptor_indexing {
+        DescriptorIndexingSupport::Core => device_info.push_next(&mut vulkan_12_features),
+        DescriptorIndexingSupport::Extension => device_info.push_next(&mut descriptor_indexing_features),
+        DescriptorIndexingSupport::Unsupported => device_info,
+    };
+
     Ok(unsafe { instance.create_device(physical_device.vk_physical_device, &device_info, None) }?)
 }
+
+/// The logical device and its memory allocator, shared by every resource that frees itself when dropped
+///
+/// Resources such as GpuBuffer hold an Rc to this so the device is only destroyed after they have all been dropped
+pub struct LogicalDevice
+{
+    device:        ash::Device,
+    pub allocator: RefCell<Allocator>,
+}
+
+impl LogicalDevice
+{
+    pub fn new(device: ash::Device, allocator: Allocator) -> Self { Self { device, allocator: RefCell::new(allocator) } }
+}
+
+impl std::ops::Deref for LogicalDevice
+{
+    type Target = ash::Device;
+
+    fn deref(&self) -> &Self::Target { &self.device }
+}
+
+impl Drop for LogicalDevice
+{
+    fn drop(&mut self)
+    {
+        self.allocator.get_mut().cleanup();
+        unsafe { self.device.destroy_device(None) };
+    }
+}
@@ -6,6 +6,9 @@ use std::fmt::{Display, Formatter};
 pub enum VkAppError
 {
     VkError(vk::Result),
+    /// The Vulkan loader library could not be found or loaded at runtime
+    #[cfg(feature = "loaded")]
+    LoaderError(ash::LoadingError),
     IoError(std::io::Error, String),
     /// An
     InstanceError(String),
@@ -13,12 +16,20 @@ pub enum VkAppError
     DeviceError(String),
 }
 
+impl VkAppError
+{
+    /// The logical device has been lost and must be recreated, along with everything created from it
+    pub fn is_device_lost(&self) -> bool { matches!(self, VkAppError::VkError(vk::Result::ERROR_DEVICE_LOST)) }
+}
+
 impl log::ProjectError for VkAppError
 {
     fn title(&self) -> String
     {
         String::from(match *self {
             VkAppError::VkError(_) => "Vulkan",
+            #[cfg(feature = "loaded")]
+            VkAppError::LoaderError(_) => "Vulkan Loader",
             VkAppError::IoError(_, _) => "IO",
             VkAppError::DeviceError(_) => "Device",
             VkAppError::InstanceError(_) => "Instance",
@@ -30,6 +41,12 @@ impl Display for VkAppError {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match *self {
             VkAppError::VkError(ref err) => write!(f, "Code {}: {}", err.as_raw(), err.to_string()),
+            #[cfg(feature = "loaded")]
+            VkAppError::LoaderError(ref err) => write!(
+                f,
+                "Could not load Vulkan ({}).\n\nVulkan is installed with your graphics driver. Install or update the driver for your GPU from NVIDIA, AMD or Intel, then try again.",
+                err
+            ),
             VkAppError::IoError(ref err, ref file) => write!(f, "{} for file {}", err.to_string(), file),
             VkAppError::DeviceError(ref err) => write!(f, "{}", err.to_string()),
             VkAppError::InstanceError(ref err) => write!(f, "{}", err.to_string()),
@@ -43,6 +60,8 @@ impl std::error::Error for VkAppError
     {
         match *self {
             VkAppError::VkError(ref err) => Some(err),
+            #[cfg(feature = "loaded")]
+            VkAppError::LoaderError(ref err) => Some(err),
             VkAppError::IoError(ref err, _) => Some(err),
             VkAppError::InstanceError(_) => None,
             VkAppError::DeviceError(_) => None,
@@ -62,6 +81,12 @@ impl From<vk::Result> for VkAppError
     }
 }
 
+#[cfg(feature = "loaded")]
+impl From<ash::LoadingError> for VkAppError
+{
+    fn from(loading_error: ash::LoadingError) -> Self { VkAppError::LoaderError(loading_error) }
+}
+
 pub trait IOResultToResultExt<T>
 {
     fn to_result(self, path: &str) -> crate::graphics::vk_app::Result<T>;
@@ -0,0 +1,661 @@
+use crate::graphics::errors::VkAppError;
+use crate::graphics::samplers::SamplerDesc;
+use crate::graphics::textures::{invalid_data, ImageLoader, TextureData, TextureKind};
+use crate::graphics::vk_app::Result;
+use ash::vk;
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a];
+/// Start of image marker followed by the first byte of the next marker
+const JPEG_MAGIC: [u8; 3] = [0xff, 0xd8, 0xff];
+/// TGA 2.0 files end with this signature, older files have no identifying bytes at all
+const TGA_FOOTER_SIGNATURE: &[u8; 18] = b"TRUEVISION-XFILE.\0";
+const TGA_HEADER_SIZE: usize = 18;
+const HDR_MAGICS: [&[u8]; 2] = [b"#?RADIANCE", b"#?RGBE"];
+
+/// Loads PNGs of any colour type and bit depth into RGBA pixels
+///
+/// Palettes and greyscale are expanded, a tRNS chunk becomes the alpha channel, 16-bit channels are reduced to 8 bits
+/// and images without transparency are made opaque
+pub struct PngLoader;
+
+impl ImageLoader for PngLoader
+{
+    fn extensions(&self) -> &[&str] { &["png"] }
+
+    fn recognises(&self, bytes: &[u8]) -> bool { bytes.starts_with(&PNG_SIGNATURE) }
+
+    fn load(&self, path: &str, bytes: &[u8]) -> Result<TextureData>
+    {
+        let mut decoder = png::Decoder::new(bytes);
+        // Leaves only 8-bit greyscale, greyscale with alpha, RGB and RGBA to convert
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
+        let mut reader = decoder.read_info().map_err(|err| png_error(err, path))?;
+        let mut buf = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buf).map_err(|err| png_error(err, path))?;
+        buf.truncate(info.buffer_size());
+
+        let pixels = match info.color_type {
+            png::ColorType::Rgba => buf,
+            png::ColorType::Rgb => rgb_to_rgba(&buf),
+            png::ColorType::GrayscaleAlpha => buf.chunks_exact(2).flat_map(|ga| [ga[0], ga[0], ga[0], ga[1]]).collect(),
+            png::ColorType::Grayscale => grey_to_rgba(&buf),
+            png::ColorType::Indexed => return Err(invalid_data(path, "Indexed colour was not expanded to RGB")),
+        };
+
+        Ok(rgba8_texture(path, info.width, info.height, pixels))
+    }
+}
+
+/// Keeps the decoder's IO errors as they are and reports malformed or unsupported files as invalid data
+fn png_error(err: png::DecodingError, path: &str) -> VkAppError
+{
+    match err {
+        png::DecodingError::IoError(err) => VkAppError::IoError(err, path.to_string()),
+        err => invalid_data(path, format!("Could not decode PNG: {}", err)),
+    }
+}
+
+/// Loads baseline and progressive JPEGs into opaque RGBA pixels
+///
+/// Greyscale is expanded, greyscale of more than 8 bits is scaled to 8 bits and CMYK is converted to RGB without a colour profile
+pub struct JpegLoader;
+
+impl ImageLoader for JpegLoader
+{
+    fn extensions(&self) -> &[&str] { &["jpg", "jpeg"] }
+
+    fn recognises(&self, bytes: &[u8]) -> bool { bytes.starts_with(&JPEG_MAGIC) }
+
+    fn load(&self, path: &str, bytes: &[u8]) -> Result<TextureData>
+    {
+        let mut decoder = jpeg_decoder::Decoder::new(bytes);
+        let buf = decoder
+            .decode()
+            .map_err(|err| invalid_data(path, format!("Could not decode JPEG: {}", err)))?;
+        let info = decoder.info().ok_or_else(|| invalid_data(path, "JPEG has no frame"))?;
+
+        let pixels = match info.pixel_format {
+            jpeg_decoder::PixelFormat::RGB24 => rgb_to_rgba(&buf),
+            jpeg_decoder::PixelFormat::L8 => grey_to_rgba(&buf),
+            jpeg_decoder::PixelFormat::L16 => {
+                let precision = jpeg_precision(bytes).ok_or_else(|| invalid_data(path, "JPEG has no frame header"))?;
+                grey_to_rgba(&reduce_to_8_bits(&buf, precision))
+            }
+            jpeg_decoder::PixelFormat::CMYK32 => buf
+                .chunks_exact(4)
+                .flat_map(|cmyk| {
+                    let white = 255 - cmyk[3] as u32;
+                    let channel = |value: u8| ((255 - value as u32) * white / 255) as u8;
+                    [channel(cmyk[0]), channel(cmyk[1]), channel(cmyk[2]), u8::MAX]
+                })
+                .collect(),
+        };
+
+        Ok(rgba8_texture(path, info.width as u32, info.height as u32, pixels))
+    }
+}
+
+/// The bits in each sample from the first frame header, which jpeg-decoder doesn't report
+fn jpeg_precision(bytes: &[u8]) -> Option<u32>
+{
+    // After the start of image marker, each segment before the frame header starts with its marker and its length,
+    // which counts the length but not the marker
+    let mut offset = 2;
+    loop {
+        let segment = bytes.get(offset..offset + 4)?;
+        if segment[0] != 0xff {
+            return None;
+        }
+        // Markers may be padded with fill bytes
+        if segment[1] == 0xff {
+            offset += 1;
+            continue;
+        }
+        // Start of frame markers, apart from DHT, JPG and DAC which share their range
+        if (0xc0..=0xcf).contains(&segment[1]) && ![0xc4, 0xc8, 0xcc].contains(&segment[1]) {
+            return bytes.get(offset + 4).map(|&precision| precision as u32);
+        }
+        offset += 2 + u16::from_be_bytes([segment[2], segment[3]]) as usize;
+    }
+}
+
+/// Scale native endian 16-bit samples of 9 to 16 bits precision down to 8 bits, lossless JPEGs keep their samples
+/// unscaled
+fn reduce_to_8_bits(samples: &[u8], precision: u32) -> Vec<u8>
+{
+    let max = (1 << precision) - 1;
+    samples
+        .chunks_exact(2)
+        .map(|value| (u16::from_ne_bytes([value[0], value[1]]) as u32 * 255 / max) as u8)
+        .collect()
+}
+
+/// Loads TGAs into RGBA pixels, uncompressed or run length encoded, in true colour, greyscale or colour mapped
+///
+/// Pixels are 8, 15, 16, 24 or 32 bits. Alpha is only kept when the header says the pixels have alpha bits, otherwise the
+/// image is opaque
+pub struct TgaLoader;
+
+impl ImageLoader for TgaLoader
+{
+    fn extensions(&self) -> &[&str] { &["tga"] }
+
+    fn recognises(&self, bytes: &[u8]) -> bool { bytes.ends_with(TGA_FOOTER_SIGNATURE) }
+
+    fn load(&self, path: &str, bytes: &[u8]) -> Result<TextureData>
+    {
+        if bytes.len() < TGA_HEADER_SIZE {
+            return Err(invalid_data(path, "TGA file ends in its header"));
+        }
+        let id_length = bytes[0] as usize;
+        let colour_map_type = bytes[1];
+        let image_type = bytes[2];
+        let colour_map_first = u16::from_le_bytes([bytes[3], bytes[4]]) as usize;
+        let colour_map_length = u16::from_le_bytes([bytes[5], bytes[6]]) as usize;
+        let colour_map_entry_bits = bytes[7];
+        let width = u16::from_le_bytes([bytes[12], bytes[13]]) as u32;
+        let height = u16::from_le_bytes([bytes[14], bytes[15]]) as u32;
+        let pixel_bits = bytes[16];
+        let descriptor = bytes[17];
+        let has_alpha = descriptor & 0x0f != 0;
+        let right_to_left = descriptor & 0x10 != 0;
+        let top_to_bottom = descriptor & 0x20 != 0;
+
+        if width == 0 || height == 0 {
+            return Err(invalid_data(path, "TGA has no pixels"));
+        }
+
+        // Image types 1 to 3 are uncompressed, 9 to 11 are the same run length encoded
+        let run_length_encoded = image_type & 0x08 != 0;
+        let colour_mapped = match image_type & 0x07 {
+            1 if colour_map_type == 1 => true,
+            2 | 3 => false,
+            _ => return Err(invalid_data(path, format!("TGA image type {} is not supported", image_type))),
+        };
+        let greyscale = image_type & 0x07 == 3;
+
+        let colour_map_start = TGA_HEADER_SIZE + id_length;
+        // The colour map's fields are meaningless when there is no colour map
+        let colour_map_size = if colour_map_type == 1 {
+            colour_map_length * (colour_map_entry_bits as usize).div_ceil(8)
+        } else {
+            0
+        };
+        let colour_map = bytes
+            .get(colour_map_start..colour_map_start + colour_map_size)
+            .ok_or_else(|| invalid_data(path, "TGA file ends in its colour map"))?;
+        let mut data = bytes.get(colour_map_start + colour_map_size..).unwrap_or_default();
+
+        // Converts the bytes of a pixel or colour map entry to RGBA
+        let to_rgba = |value: &[u8], bits: u8| -> Option<[u8; 4]> {
+            let alpha = |value: u8| if has_alpha { value } else { u8::MAX };
+            match (bits, greyscale) {
+                (8, true) => Some([value[0], value[0], value[0], u8::MAX]),
+                (16, true) => Some([value[0], value[0], value[0], alpha(value[1])]),
+                (15 | 16, false) => {
+                    let packed = u16::from_le_bytes([value[0], value[1]]);
+                    let channel = |shift: u16| {
+                        let value = (packed >> shift) & 0x1f;
+                        ((value << 3) | (value >> 2)) as u8
+                    };
+                    let alpha = if bits == 16 && packed & 0x8000 == 0 {
+                        alpha(0)
+                    } else {
+                        u8::MAX
+                    };
+                    Some([channel(10), channel(5), channel(0), alpha])
+                }
+                (24, false) => Some([value[2], value[1], value[0], u8::MAX]),
+                (32, false) => Some([value[2], value[1], value[0], alpha(value[3])]),
+                _ => None,
+            }
+        };
+        let pixel_size = (pixel_bits as usize).div_ceil(8);
+        let read_pixel = |value: &[u8]| -> Option<[u8; 4]> {
+            if colour_mapped {
+                let index = match pixel_bits {
+                    8 => value[0] as usize,
+                    16 => u16::from_le_bytes([value[0], value[1]]) as usize,
+                    _ => return None,
+                };
+                let entry_size = (colour_map_entry_bits as usize).div_ceil(8);
+                let entry = index.checked_sub(colour_map_first)? * entry_size;
+                to_rgba(colour_map.get(entry..entry + entry_size)?, colour_map_entry_bits)
+            } else {
+                to_rgba(value, pixel_bits)
+            }
+        };
+        let bad_pixel = || invalid_data(path, format!("TGA has unsupported or malformed {}-bit pixels", pixel_bits));
+
+        let pixel_count = width as usize * height as usize;
+        let mut rows: Vec<[u8; 4]> = Vec::with_capacity(pixel_count);
+        while rows.len() < pixel_count {
+            // A run length packet is a count and either one pixel repeated or that many raw pixels
+            let (count, repeated) = if run_length_encoded {
+                let (&packet, rest) = data
+                    .split_first()
+                    .ok_or_else(|| invalid_data(path, "TGA file ends in its pixels"))?;
+                data = rest;
+                ((packet & 0x7f) as usize + 1, packet & 0x80 != 0)
+            } else {
+                (pixel_count, false)
+            };
+            let count = count.min(pixel_count - rows.len());
+
+            let packet_size = if repeated { pixel_size } else { pixel_size * count };
+            let packet = data
+                .get(..packet_size)
+                .ok_or_else(|| invalid_data(path, "TGA file ends in its pixels"))?;
+            data = &data[packet_size..];
+            if repeated {
+                let pixel = read_pixel(packet).ok_or_else(bad_pixel)?;
+                rows.extend(std::iter::repeat_n(pixel, count));
+            } else {
+                for value in packet.chunks_exact(pixel_size) {
+                    rows.push(read_pixel(value).ok_or_else(bad_pixel)?);
+                }
+            }
+        }
+
+        // Rows are stored bottom to top unless the descriptor says otherwise
+        let mut pixels = Vec::with_capacity(pixel_count * 4);
+        for y in 0..height as usize {
+            let row_index = if top_to_bottom { y } else { height as usize - 1 - y };
+            let row = &rows[row_index * width as usize..][..width as usize];
+            if right_to_left {
+                pixels.extend(row.iter().rev().flatten());
+            } else {
+                pixels.extend(row.iter().flatten());
+            }
+        }
+
+        Ok(rgba8_texture(path, width, height, pixels))
+    }
+}
+
+/// Loads Radiance RGBE images, flat or run length encoded, into R32G32B32A32_SFLOAT pixels with opaque alpha
+///
+/// Only the standard orientation with rows from top to bottom, and its vertical flip, are supported. The pixels are
+/// linear radiance so they are not sRGB encoded
+pub struct HdrLoader;
+
+impl ImageLoader for HdrLoader
+{
+    fn extensions(&self) -> &[&str] { &["hdr"] }
+
+    fn recognises(&self, bytes: &[u8]) -> bool { HDR_MAGICS.iter().any(|magic| bytes.starts_with(magic)) }
+
+    fn load(&self, path: &str, bytes: &[u8]) -> Result<TextureData>
+    {
+        // The header is lines of text ending with an empty line, followed by the resolution line
+        let mut lines = bytes.split(|&byte| byte == b'\n');
+        let mut header_size = 0;
+        for line in lines.by_ref() {
+            header_size += line.len() + 1;
+            if line.is_empty() {
+                break;
+            }
+            if line.starts_with(b"FORMAT=") && line != b"FORMAT=32-bit_rle_rgbe" {
+                return Err(invalid_data(
+                    path,
+                    format!("HDR format {} is not supported", String::from_utf8_lossy(&line[7..])),
+                ));
+            }
+        }
+        let resolution = lines
+            .next()
+            .ok_or_else(|| invalid_data(path, "HDR file ends in its header"))?;
+        header_size += resolution.len() + 1;
+
+        let resolution = String::from_utf8_lossy(resolution);
+        let (flip, height, width) = match resolution.split_whitespace().collect::<Vec<_>>()[..] {
+            ["-Y", height, "+X", width] => (false, height, width),
+            ["+Y", height, "+X", width] => (true, height, width),
+            _ => return Err(invalid_data(path, format!("HDR resolution {} is not supported", resolution))),
+        };
+        let (Ok(width), Ok(height)) = (width.parse::<u32>(), height.parse::<u32>()) else {
+            return Err(invalid_data(path, format!("HDR resolution {} is malformed", resolution)));
+        };
+        if width == 0 || height == 0 {
+            return Err(invalid_data(path, "HDR has no pixels"));
+        }
+
+        let mut data = bytes.get(header_size..).unwrap_or_default();
+        let mut rows = Vec::with_capacity(height as usize);
+        for _ in 0..height {
+            let (row, rest) = read_rgbe_scanline(data, width as usize)
+                .ok_or_else(|| invalid_data(path, "HDR file ends in its pixels or has a malformed scanline"))?;
+            rows.push(row);
+            data = rest;
+        }
+        if flip {
+            rows.reverse();
+        }
+
+        let pixels = rows
+            .iter()
+            .flatten()
+            .flat_map(|rgbe| {
+                // The exponent scales all three mantissas, with the mantissas' 8 bits below the point
+                let scale = if rgbe[3] == 0 {
+                    0.0
+                } else {
+                    libm::ldexpf(1.0, rgbe[3] as i32 - (128 + 8))
+                };
+                let channel = |value: u8| (value as f32 + 0.5) * scale;
+                [channel(rgbe[0]), channel(rgbe[1]), channel(rgbe[2]), 1.0]
+            })
+            .flat_map(f32::to_ne_bytes)
+            .collect();
+
+        Ok(TextureData {
+            path: path.to_string(),
+            width,
+            height,
+            kind: TextureKind::Single,
+            format: vk::Format::R32G32B32A32_SFLOAT,
+            pixels,
+            mip_pixels: Vec::new(),
+            sampler: SamplerDesc::default(),
+        })
+    }
+}
+
+/// Read a scanline of RGBE pixels, returning it and the data after it, or None if it is malformed
+///
+/// Scanlines are flat pixels, the old run length encoding that repeats the previous pixel, or the newer encoding that
+/// stores each channel separately in runs and literal spans
+fn read_rgbe_scanline(data: &[u8], width: usize) -> Option<(Vec<[u8; 4]>, &[u8])>
+{
+    let mut row = Vec::with_capacity(width);
+
+    // The newer encoding starts with 2, 2 and the width, which can't be mistaken for a flat pixel's exponent
+    if (8..=0x7fff).contains(&width) && data.len() >= 4 && data[..2] == [2, 2] && data[2] & 0x80 == 0 {
+        if u16::from_be_bytes([data[2], data[3]]) as usize != width {
+            return None;
+        }
+        let mut data = &data[4..];
+        row.resize(width, [0; 4]);
+        for channel in 0..4 {
+            let mut x = 0;
+            while x < width {
+                let (&count, rest) = data.split_first()?;
+                if count > 128 {
+                    // A run of one value
+                    let count = (count - 128) as usize;
+                    let value = *rest.first()?;
+                    for pixel in row.get_mut(x..x + count)? {
+                        pixel[channel] = value;
+                    }
+                    data = &rest[1..];
+                    x += count;
+                } else {
+                    let count = count as usize;
+                    if count == 0 {
+                        return None;
+                    }
+                    for (pixel, &value) in row.get_mut(x..x + count)?.iter_mut().zip(rest.get(..count)?) {
+                        pixel[channel] = value;
+                    }
+                    data = &rest[count..];
+                    x += count;
+                }
+            }
+        }
+        return Some((row, data));
+    }
+
+    let mut data = data;
+    let mut repeat_shift = 0;
+    while row.len() < width {
+        let pixel: [u8; 4] = data.get(..4)?.try_into().ok()?;
+        data = &data[4..];
+        if pixel[..3] == [1, 1, 1] {
+            // Repeat the previous pixel, with consecutive repeats counting in higher bits
+            let previous = *row.last()?;
+            // Enough consecutive repeats would shift the count past the width of usize
+            let count = (pixel[3] as usize).checked_shl(repeat_shift)?;
+            row.extend(std::iter::repeat_n(previous, count.min(width - row.len())));
+            repeat_shift += 8;
+        } else {
+            row.push(pixel);
+            repeat_shift = 0;
+        }
+    }
+    Some((row, data))
+}
+
+fn rgb_to_rgba(rgb: &[u8]) -> Vec<u8>
+{
+    rgb.chunks_exact(3)
+        .flat_map(|rgb| [rgb[0], rgb[1], rgb[2], u8::MAX])
+        .collect()
+}
+
+fn grey_to_rgba(grey: &[u8]) -> Vec<u8> { grey.iter().flat_map(|&grey| [grey, grey, grey, u8::MAX]).collect() }
+
+/// Decoded 8-bit images are colour images so they are sRGB
+fn rgba8_texture(path: &str, width: u32, height: u32, pixels: Vec<u8>) -> TextureData
+{
+    TextureData {
+        path: path.to_string(),
+        width,
+        height,
+        kind: TextureKind::Single,
+        format: vk::Format::R8G8B8A8_SRGB,
+        pixels,
+        mip_pixels: Vec::new(),
+        sampler: SamplerDesc::default(),
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    const RED_BGR: [u8; 3] = [0, 0, 255];
+    const GREEN_BGR: [u8; 3] = [0, 255, 0];
+    const BLUE_BGR: [u8; 3] = [255, 0, 0];
+
+    /// A TGA header for an image without an ID or colour map
+    fn tga_header(image_type: u8, width: u16, height: u16, pixel_bits: u8, descriptor: u8) -> Vec<u8>
+    {
+        let mut header = vec![0; TGA_HEADER_SIZE];
+        header[2] = image_type;
+        header[12..14].copy_from_slice(&width.to_le_bytes());
+        header[14..16].copy_from_slice(&height.to_le_bytes());
+        header[16] = pixel_bits;
+        header[17] = descriptor;
+        header
+    }
+
+    #[test]
+    fn jpeg_precision_is_read_from_the_frame_header()
+    {
+        // An APP0 segment, then a lossless frame header of 12 bits precision
+        let jpeg = [
+            vec![0xff, 0xd8],
+            vec![0xff, 0xe0, 0, 4, 0, 0],
+            vec![0xff, 0xff, 0xc3, 0, 11, 12, 0, 1, 0, 1, 1, 1, 0x11, 0],
+        ]
+        .concat();
+        assert_eq!(jpeg_precision(&jpeg), Some(12));
+
+        // A Huffman table is in the range of the start of frame markers but isn't one
+        let jpeg = [vec![0xff, 0xd8], vec![0xff, 0xc4, 0, 3, 0], vec![0xff, 0xc0, 0, 11, 8]].concat();
+        assert_eq!(jpeg_precision(&jpeg), Some(8));
+
+        assert_eq!(jpeg_precision(&[0xff, 0xd8, 0xff, 0xe0, 0, 4]), None);
+    }
+
+    #[test]
+    fn high_precision_jpeg_samples_are_scaled_to_8_bits()
+    {
+        let samples: Vec<u8> = [0u16, 2048, 4095].iter().flat_map(|sample| sample.to_ne_bytes()).collect();
+        assert_eq!(reduce_to_8_bits(&samples, 12), [0, 127, 255]);
+        let samples: Vec<u8> = [511u16, 65535].iter().flat_map(|sample| sample.to_ne_bytes()).collect();
+        assert_eq!(reduce_to_8_bits(&samples[..2], 9), [255]);
+        assert_eq!(reduce_to_8_bits(&samples[2..], 16), [255]);
+    }
+
+    #[test]
+    fn run_length_encoded_tgas_expand_runs_and_raw_packets()
+    {
+        let mut tga = tga_header(10, 2, 2, 24, 0);
+        // A run of two red pixels, then two raw pixels, with the bottom row first
+        tga.push(0x81);
+        tga.extend(RED_BGR);
+        tga.push(0x01);
+        tga.extend(BLUE_BGR);
+        tga.extend(GREEN_BGR);
+
+        let texture = TgaLoader.load("test.tga", &tga).unwrap();
+        assert_eq!((texture.width, texture.height), (2, 2));
+        assert_eq!(texture.format, vk::Format::R8G8B8A8_SRGB);
+        assert_eq!(
+            texture.pixels,
+            [[0, 0, 255, 255], [0, 255, 0, 255], [255, 0, 0, 255], [255, 0, 0, 255]].concat()
+        );
+    }
+
+    #[test]
+    fn tga_runs_can_span_rows_and_keep_alpha()
+    {
+        // 32-bit pixels with 8 alpha bits, stored top to bottom
+        let mut tga = tga_header(10, 3, 2, 32, 0x28);
+        tga.push(0x83);
+        tga.extend([10, 20, 30, 40]);
+        tga.push(0x81);
+        tga.extend([50, 60, 70, 80]);
+
+        let texture = TgaLoader.load("test.tga", &tga).unwrap();
+        assert_eq!(
+            texture.pixels,
+            [
+                [30, 20, 10, 40],
+                [30, 20, 10, 40],
+                [30, 20, 10, 40],
+                [30, 20, 10, 40],
+                [70, 60, 50, 80],
+                [70, 60, 50, 80]
+            ]
+            .concat()
+        );
+    }
+
+    #[test]
+    fn truncated_run_length_encoded_tgas_are_rejected()
+    {
+        let mut tga = tga_header(10, 2, 2, 24, 0);
+        tga.push(0x81);
+        tga.extend(RED_BGR);
+        assert!(TgaLoader.load("test.tga", &tga).is_err());
+
+        // A raw packet of two pixels with only one
+        tga.push(0x01);
+        tga.extend(BLUE_BGR);
+        assert!(TgaLoader.load("test.tga", &tga).is_err());
+    }
+
+    /// A scanline of the newer run length encoding, 8 pixels wide
+    fn rle_scanline() -> Vec<u8>
+    {
+        let mut scanline = vec![2, 2, 0, 8];
+        // Red is one run
+        scanline.extend([128 + 8, 100]);
+        // Green is literal
+        scanline.push(8);
+        scanline.extend(0..8);
+        // Blue is two runs
+        scanline.extend([128 + 4, 10, 128 + 4, 20]);
+        // The exponent is a run then a literal span
+        scanline.extend([128 + 6, 129, 2, 130, 131]);
+        scanline
+    }
+
+    #[test]
+    fn run_length_encoded_hdr_scanlines_decode_each_channel()
+    {
+        let mut data = rle_scanline();
+        data.extend([1, 2, 3]);
+        let (row, rest) = read_rgbe_scanline(&data, 8).unwrap();
+        assert_eq!(rest, [1, 2, 3]);
+        let expected: Vec<[u8; 4]> = (0..8u8)
+            .map(|x| {
+                [
+                    100,
+                    x,
+                    if x < 4 { 10 } else { 20 },
+                    [129, 129, 129, 129, 129, 129, 130, 131][x as usize],
+                ]
+            })
+            .collect();
+        assert_eq!(row, expected);
+    }
+
+    #[test]
+    fn malformed_hdr_scanlines_are_rejected()
+    {
+        // The scanline says it is a different width
+        assert!(read_rgbe_scanline(&rle_scanline(), 9).is_none());
+
+        let mut overrun = rle_scanline();
+        overrun[4] = 128 + 9;
+        assert!(read_rgbe_scanline(&overrun, 8).is_none());
+
+        let mut empty_span = rle_scanline();
+        empty_span[6] = 0;
+        assert!(read_rgbe_scanline(&empty_span, 8).is_none());
+
+        let scanline = rle_scanline();
+        assert!(read_rgbe_scanline(&scanline[..scanline.len() - 1], 8).is_none());
+    }
+
+    #[test]
+    fn flat_hdr_scanlines_repeat_the_previous_pixel()
+    {
+        let data = [[10, 20, 30, 128], [1, 1, 1, 2], [40, 50, 60, 128]].concat();
+        let (row, rest) = read_rgbe_scanline(&data, 4).unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(
+            row,
+            [[10, 20, 30, 128], [10, 20, 30, 128], [10, 20, 30, 128], [40, 50, 60, 128]]
+        );
+
+        // There is no previous pixel to repeat
+        assert!(read_rgbe_scanline(&[1, 1, 1, 2], 2).is_none());
+    }
+
+    #[test]
+    fn hdr_repeats_counting_past_the_width_of_usize_are_rejected()
+    {
+        // Empty repeats don't fill the row, so each one counts in the next higher bits
+        let repeats = usize::BITS as usize / 8 + 1;
+        let data = [vec![10, 20, 30, 128], [1, 1, 1, 0].repeat(repeats)].concat();
+        assert!(read_rgbe_scanline(&data, 2).is_none());
+
+        // One fewer is still a valid, if pointless, scanline once a pixel fills the row
+        let data = [vec![10, 20, 30, 128], [1, 1, 1, 0].repeat(repeats - 1), vec![40, 50, 60, 128]].concat();
+        assert!(read_rgbe_scanline(&data, 2).is_some());
+    }
+
+    #[test]
+    fn hdr_pixels_are_scaled_by_their_exponent()
+    {
+        let mut hdr = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 1 +X 8\n".to_vec();
+        hdr.extend(rle_scanline());
+
+        let texture = HdrLoader.load("test.hdr", &hdr).unwrap();
+        assert_eq!((texture.width, texture.height), (8, 1));
+        assert_eq!(texture.format, vk::Format::R32G32B32A32_SFLOAT);
+        let pixels: Vec<f32> = texture
+            .pixels
+            .chunks_exact(4)
+            .map(|value| f32::from_ne_bytes([value[0], value[1], value[2], value[3]]))
+            .collect();
+        // An exponent of 129 scales the mantissas by 2^-7, with half added to each mantissa
+        assert_eq!(pixels[..4], [100.5 / 128.0, 0.5 / 128.0, 10.5 / 128.0, 1.0]);
+        assert_eq!(pixels[7 * 4..], [100.5 / 32.0, 7.5 / 32.0, 20.5 / 32.0, 1.0]);
+    }
+}
@@ -0,0 +1,183 @@
+use crate::graphics::allocator::MemoryPurpose;
+use crate::graphics::buffers::GpuBuffer;
+use crate::graphics::debug::DebugUtils;
+use crate::graphics::device::LogicalDevice;
+use crate::graphics::errors::VkAppError;
+use crate::graphics::upload::UploadContext;
+use crate::graphics::vk_app::Result;
+use crate::maths::matrix;
+use ash::vk;
+use std::rc::Rc;
+
+/// Indices of a mesh, u16 indices take half the memory and can be used when there are at most 65536 vertices
+pub enum IndexData
+{
+    U16(Vec<u16>),
+    U32(Vec<u32>),
+}
+
+impl IndexData
+{
+    pub fn len(&self) -> usize
+    {
+        match self {
+            IndexData::U16(indices) => indices.len(),
+            IndexData::U32(indices) => indices.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool { self.len() == 0 }
+
+    fn max(&self) -> Option<u32>
+    {
+        match self {
+            IndexData::U16(indices) => indices.iter().max().map(|&index| index as u32),
+            IndexData::U32(indices) => indices.iter().max().copied(),
+        }
+    }
+}
+
+/// Vertices and indices of a mesh kept on the CPU, so the mesh can be uploaded again if the device is lost
+pub struct MeshData<V: Copy>
+{
+    /// Used to name the mesh's buffers
+    pub name:     String,
+    pub vertices: Vec<V>,
+    pub indices:  IndexData,
+}
+
+/// The device local index buffer of a mesh, in whichever index type its data used
+enum IndexBuffer
+{
+    U16(GpuBuffer<u16>),
+    U32(GpuBuffer<u32>),
+}
+
+/// Vertex and index buffers uploaded from MeshData, along with what is needed to draw them
+///
+/// Any number of meshes can be alive at once, each frees its buffers when dropped
+pub struct Mesh<V: Copy>
+{
+    vertex_buffer: GpuBuffer<V>,
+    index_buffer:  IndexBuffer,
+    index_count:   u32,
+}
+
+impl<V: Copy> Mesh<V>
+{
+    /// Create the mesh's buffers and record the upload of its data in the upload context's current batch
+    ///
+    /// The mesh must not be drawn until the upload context is flushed and the upload has completed
+    pub fn new(
+        upload_context: &mut UploadContext, device: &Rc<LogicalDevice>, debug_utils: &DebugUtils, data: &MeshData<V>,
+    ) -> Result<Self>
+    {
+        if data.vertices.is_empty() || data.indices.is_empty() {
+            return Err(VkAppError::DeviceError(format!(
+                "Mesh {} has no vertices or no indices",
+                data.name
+            )));
+        }
+        // An out of range index reads outside of the vertex buffer
+        if let Some(max_index) = data
+            .indices
+            .max()
+            .filter(|&max_index| max_index as usize >= data.vertices.len())
+        {
+            return Err(VkAppError::DeviceError(format!(
+                "Mesh {} has index {} but only {} vertices",
+                data.name,
+                max_index,
+                data.vertices.len()
+            )));
+        }
+
+        /*  The most optimal memory for the GPU to read from has the VK_MEMORY_PROPERTY_DEVICE_LOCAL_BIT flag
+           This memory is usually not accessible by the CPU on dedicated graphics cards
+           The upload context writes the data to a staging buffer which can be accessed by the CPU
+           The staging buffer then uploads the data to device local memory
+        */
+        let vertex_buffer = GpuBuffer::new(
+            device,
+            data.vertices.len(),
+            vk::BufferUsageFlags::TRANSFER_DST | vk::BufferUsageFlags::VERTEX_BUFFER,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            MemoryPurpose::Vertex,
+        )?;
+        vertex_buffer.set_name(debug_utils, format!("Vertex buffer {}", data.name).as_str());
+        upload_context.upload_buffer(&data.vertices, &vertex_buffer)?;
+
+        let index_buffer = match &data.indices {
+            IndexData::U16(indices) => IndexBuffer::U16(create_index_buffer(upload_context, device, indices)?),
+            IndexData::U32(indices) => IndexBuffer::U32(create_index_buffer(upload_context, device, indices)?),
+        };
+        debug_utils.set_name(index_buffer.handle(), format!("Index buffer {}", data.name).as_str());
+
+        Ok(Self {
+            vertex_buffer,
+            index_buffer,
+            index_count: data.indices.len() as u32,
+        })
+    }
+
+    /// Bind the mesh's vertex and index buffers for the following draws
+    pub fn cmd_bind(&self, device: &ash::Device, command_buffer: vk::CommandBuffer)
+    {
+        unsafe {
+            device.cmd_bind_vertex_buffers(command_buffer, 0, &[self.vertex_buffer.handle()], &[0]);
+            device.cmd_bind_index_buffer(command_buffer, self.index_buffer.handle(), 0, self.index_buffer.index_type());
+        }
+    }
+
+    /// Draw every index of the mesh, which must be bound
+    pub fn cmd_draw(&self, device: &ash::Device, command_buffer: vk::CommandBuffer)
+    {
+        unsafe { device.cmd_draw_indexed(command_buffer, self.index_count, 1, 0, 0, 0) };
+    }
+}
+
+impl IndexBuffer
+{
+    fn handle(&self) -> vk::Buffer
+    {
+        match self {
+            IndexBuffer::U16(buffer) => buffer.handle(),
+            IndexBuffer::U32(buffer) => buffer.handle(),
+        }
+    }
+
+    fn index_type(&self) -> vk::IndexType
+    {
+        match self {
+            IndexBuffer::U16(_) => vk::IndexType::UINT16,
+            IndexBuffer::U32(_) => vk::IndexType::UINT32,
+        }
+    }
+}
+
+fn create_index_buffer<T: Copy>(
+    upload_context: &mut UploadContext, device: &Rc<LogicalDevice>, indices: &[T],
+) -> Result<GpuBuffer<T>>
+{
+    let index_buffer = GpuBuffer::new(
+        device,
+        indices.len(),
+        vk::BufferUsageFlags::TRANSFER_DST | vk::BufferUsageFlags::INDEX_BUFFER,
+        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        MemoryPurpose::Index,
+    )?;
+    upload_context.upload_buffer(indices, &index_buffer)?;
+    Ok(index_buffer)
+}
+
+/// One object to draw, a textured mesh at a position in the world
+#[derive(Copy, Clone)]
+pub struct DrawObject
+{
+    /// Index of the mesh in the renderer's meshes
+    pub mesh:      usize,
+    /// Index of the texture in the renderer's textures
+    pub texture:   usize,
+    /// Model matrix placing the mesh in the world
+    pub transform: matrix::Matrix4f,
+}
@@ -0,0 +1,225 @@
+use crate::graphics::allocator::MemoryPurpose;
+use crate::graphics::buffers::{self, GpuBuffer};
+use crate::graphics::compute::{Barrier, ComputePipeline, Dispatch};
+use crate::graphics::debug::DebugUtils;
+use crate::graphics::descriptors::{self, DescriptorLayoutCache};
+use crate::graphics::device::LogicalDevice;
+use crate::graphics::textures::Texture;
+use crate::graphics::upload::UploadContext;
+use crate::graphics::vk_app::Result;
+use ash::vk;
+use std::rc::Rc;
+
+const PARTICLE_COUNT: u32 = 16;
+/// Width and height of the image the particles are rendered into
+pub const PARTICLE_IMAGE_SIZE: u32 = 256;
+/// Invocations per workgroup of particles_simulate.comp
+const SIMULATE_GROUP_SIZE: u32 = 64;
+/// Invocations per workgroup along each axis of particles_render.comp
+const RENDER_GROUP_SIZE: u32 = 8;
+
+/// Matches the Particle struct in the particle shaders, with std430 layout
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct Particle
+{
+    position: [f32; 2],
+    velocity: [f32; 2],
+}
+
+/// Matches the push constant block of both particle shaders
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct ParticlePushConstants
+{
+    delta_time:     f32,
+    particle_count: u32,
+}
+
+/// Particles simulated on the GPU and rendered into a texture every frame before it is drawn
+///
+/// One dispatch moves the particles in a storage buffer, a second reads them and writes the texture as a storage image,
+/// drawing each particle's sprite from a layer of an array texture over them
+pub struct ParticleSystem
+{
+    particle_buffer:   GpuBuffer<Particle>,
+    simulate_pipeline: ComputePipeline,
+    render_pipeline:   ComputePipeline,
+    /// The texture's image and view, the texture is owned by the renderer along with the other textures
+    output_image:      vk::Image,
+    output_view:       vk::ImageView,
+    /// The sprites' array view and sampler, the sprites are also owned by the renderer
+    sprites:           (vk::ImageView, vk::Sampler),
+    push_constants:    ParticlePushConstants,
+}
+
+impl ParticleSystem
+{
+    /// Create the particle buffer and record the upload of the particles' starting positions in the upload context's
+    /// current batch, which must complete before the first dispatch
+    ///
+    /// The sprites are the view of an array texture and its sampler, the particles take turns using its layers
+    pub fn new(
+        upload_context: &mut UploadContext, device: &Rc<LogicalDevice>, debug_utils: &DebugUtils,
+        layout_cache: &mut DescriptorLayoutCache, output: &Texture, sprites: (vk::ImageView, vk::Sampler),
+        max_push_constants_size: u32,
+    ) -> Result<Self>
+    {
+        // Spread the particles around a circle, moving in different directions
+        let particles: Vec<Particle> = (0..PARTICLE_COUNT)
+            .map(|index| {
+                let angle = index as f32 / PARTICLE_COUNT as f32 * std::f32::consts::TAU;
+                let speed = 0.2 + 0.05 * (index % 4) as f32;
+                Particle {
+                    position: [0.5 * libm::cosf(angle), 0.5 * libm::sinf(angle)],
+                    velocity: [speed * libm::cosf(angle * 3.0), speed * libm::sinf(angle * 3.0)],
+                }
+            })
+            .collect();
+
+        let particle_buffer = GpuBuffer::new(
+            device,
+            particles.len(),
+            vk::BufferUsageFlags::TRANSFER_DST | vk::BufferUsageFlags::STORAGE_BUFFER,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            MemoryPurpose::Storage,
+        )?;
+        particle_buffer.set_name(debug_utils, "Particle buffer");
+        upload_context.upload_buffer(&particles, &particle_buffer)?;
+
+        // Both shaders use the same set, the simulation just doesn't touch the image or the sprites
+        let bindings = [
+            vk::DescriptorSetLayoutBinding::default()
+                .binding(0)
+                .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::COMPUTE),
+            vk::DescriptorSetLayoutBinding::default()
+                .binding(1)
+                .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::COMPUTE),
+            vk::DescriptorSetLayoutBinding::default()
+                .binding(2)
+                .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::COMPUTE),
+            vk::DescriptorSetLayoutBinding::default()
+                .binding(3)
+                .descriptor_type(vk::DescriptorType::SAMPLER)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::COMPUTE),
+        ];
+        let push_constant_ranges = [vk::PushConstantRange::default()
+            .stage_flags(vk::ShaderStageFlags::COMPUTE)
+            .offset(0)
+            .size(size_of::<ParticlePushConstants>() as u32)];
+
+        let simulate_pipeline = ComputePipeline::new(
+            device,
+            debug_utils,
+            layout_cache,
+            "particles_simulate.spv",
+            &bindings,
+            &push_constant_ranges,
+            max_push_constants_size,
+        )?;
+        let render_pipeline = ComputePipeline::new(
+            device,
+            debug_utils,
+            layout_cache,
+            "particles_render.spv",
+            &bindings,
+            &push_constant_ranges,
+            max_push_constants_size,
+        )?;
+
+        Ok(Self {
+            particle_buffer,
+            simulate_pipeline,
+            render_pipeline,
+            output_image: output.image(),
+            output_view: output.view(),
+            sprites,
+            push_constants: ParticlePushConstants { delta_time: 0.0, particle_count: PARTICLE_COUNT },
+        })
+    }
+
+    /// The layout of the set both dispatches use
+    pub fn descriptor_set_layout(&self) -> vk::DescriptorSetLayout { self.simulate_pipeline.descriptor_set_layout }
+
+    /// Point a set with descriptor_set_layout at the particle buffer, the output image and the sprites
+    pub fn write_descriptor_set(&self, device: &ash::Device, descriptor_set: vk::DescriptorSet)
+    {
+        descriptors::write_storage_buffer(device, descriptor_set, 0, &self.particle_buffer);
+        descriptors::write_storage_image(device, descriptor_set, 1, self.output_view);
+        descriptors::write_sampled_image(device, descriptor_set, 2, self.sprites.0);
+        descriptors::write_sampler(device, descriptor_set, 3, self.sprites.1);
+    }
+
+    /// Advance the simulation by delta_time seconds in the next dispatches
+    pub fn update(&mut self, delta_time: f32) { self.push_constants.delta_time = delta_time; }
+
+    /// The dispatches that simulate the particles and render them to the texture, with the barriers that keep them in
+    /// order with each other, with the previous frames and with the draws that sample the texture
+    pub fn dispatches(&self, descriptor_set: vk::DescriptorSet) -> [Dispatch<'_>; 2]
+    {
+        let push_constants = buffers::as_bytes(std::slice::from_ref(&self.push_constants));
+        let particle_buffer = self.particle_buffer.handle();
+
+        let simulate = Dispatch {
+            pipeline: &self.simulate_pipeline,
+            descriptor_set,
+            push_constants,
+            group_counts: [PARTICLE_COUNT.div_ceil(SIMULATE_GROUP_SIZE), 1, 1],
+            // The previous frame's dispatches must have finished with the particles before they are moved again
+            barriers_before: vec![Barrier::Buffer {
+                buffer:          particle_buffer,
+                src_stage_mask:  vk::PipelineStageFlags::COMPUTE_SHADER,
+                src_access_mask: vk::AccessFlags::SHADER_WRITE,
+                dst_stage_mask:  vk::PipelineStageFlags::COMPUTE_SHADER,
+                dst_access_mask: vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE,
+            }],
+            barriers_after: vec![Barrier::Buffer {
+                buffer:          particle_buffer,
+                src_stage_mask:  vk::PipelineStageFlags::COMPUTE_SHADER,
+                src_access_mask: vk::AccessFlags::SHADER_WRITE,
+                dst_stage_mask:  vk::PipelineStageFlags::COMPUTE_SHADER,
+                dst_access_mask: vk::AccessFlags::SHADER_READ,
+            }],
+        };
+
+        let render = Dispatch {
+            pipeline: &self.render_pipeline,
+            descriptor_set,
+            push_constants,
+            group_counts: [
+                PARTICLE_IMAGE_SIZE.div_ceil(RENDER_GROUP_SIZE),
+                PARTICLE_IMAGE_SIZE.div_ceil(RENDER_GROUP_SIZE),
+                1,
+            ],
+            // The image is completely overwritten so its previous contents are discarded, once the previous frame's
+            // fragment shaders have finished sampling it
+            barriers_before: vec![Barrier::Image {
+                image:           self.output_image,
+                old_layout:      vk::ImageLayout::UNDEFINED,
+                new_layout:      vk::ImageLayout::GENERAL,
+                src_stage_mask:  vk::PipelineStageFlags::FRAGMENT_SHADER,
+                src_access_mask: vk::AccessFlags::empty(),
+                dst_stage_mask:  vk::PipelineStageFlags::COMPUTE_SHADER,
+                dst_access_mask: vk::AccessFlags::SHADER_WRITE,
+            }],
+            barriers_after: vec![Barrier::Image {
+                image:           self.output_image,
+                old_layout:      vk::ImageLayout::GENERAL,
+                new_layout:      vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                src_stage_mask:  vk::PipelineStageFlags::COMPUTE_SHADER,
+                src_access_mask: vk::AccessFlags::SHADER_WRITE,
+                dst_stage_mask:  vk::PipelineStageFlags::FRAGMENT_SHADER,
+                dst_access_mask: vk::AccessFlags::SHADER_READ,
+            }],
+        };
+
+        [simulate, render]
+    }
+}
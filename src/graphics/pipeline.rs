@@ -1,4 +1,7 @@
-use crate::graphics::errors::IOResultToResultExt;
+use crate::graphics::buffers;
+use crate::graphics::debug::DebugUtils;
+use crate::graphics::descriptors::DescriptorLayoutCache;
+use crate::graphics::errors::{IOResultToResultExt, VkAppError};
 use crate::graphics::presentation::SwapchainSettings;
 use crate::graphics::vk_app;
 use crate::graphics::vk_app::Result;
@@ -7,10 +10,41 @@ use std::mem::offset_of;
 
 pub(crate) struct Pipeline
 {
+    /// Null when the pipeline is used with dynamic rendering or draws within another pipeline's render pass
     pub render_pass:           vk::RenderPass,
+    /// Owned by the layout cache the pipeline was created with
     pub descriptor_set_layout: vk::DescriptorSetLayout,
     pub pipeline_layout:       vk::PipelineLayout,
     pub graphics_pipeline:     vk::Pipeline,
+    /// The push constant ranges the pipeline layout was created with
+    pub push_constant_ranges:  Vec<vk::PushConstantRange>,
+}
+
+/// Small per-draw values pushed straight into the command buffer, without touching descriptors
+///
+/// Read in shaders from a `layout(push_constant) uniform` block with the same layout
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct DrawPushConstants
+{
+    /// Index of the object being drawn, matching its slice of the frame's uniform buffer
+    pub object_index:  u32,
+    /// Slot of the object's texture in the bindless texture array, unused when textures are bound per draw
+    pub texture_index: u32,
+    /// Slot of the object's texture's sampler in the bindless sampler array, unused when textures are bound per draw
+    pub sampler_index: u32,
+}
+
+impl DrawPushConstants
+{
+    /// The push constant ranges for a pipeline drawing with DrawPushConstants
+    pub fn ranges() -> [vk::PushConstantRange; 1]
+    {
+        [vk::PushConstantRange::default()
+            .stage_flags(vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT)
+            .offset(0)
+            .size(size_of::<Self>() as u32)]
+    }
 }
 
 impl Pipeline
@@ -18,22 +52,107 @@ impl Pipeline
     pub fn cleanup(&self, device: &ash::Device)
     {
         unsafe {
-            device.destroy_descriptor_set_layout(self.descriptor_set_layout, None);
             device.destroy_pipeline_layout(self.pipeline_layout, None);
             device.destroy_render_pass(self.render_pass, None);
             device.destroy_pipeline(self.graphics_pipeline, None);
         }
     }
+
+    pub fn set_debug_names(&self, debug_utils: &DebugUtils)
+    {
+        if self.render_pass != vk::RenderPass::null() {
+            debug_utils.set_name(self.render_pass, "Render pass");
+        }
+        debug_utils.set_name(self.pipeline_layout, "Pipeline layout");
+        debug_utils.set_name(self.graphics_pipeline, "Graphics pipeline");
+    }
+
+    /// Record an update of the push constants at offset with value, for the shader stages in stage_flags
+    ///
+    /// The bytes updated must be covered by the pipeline's push constant ranges, as the validation layers would require
+    pub fn cmd_push_constants<T: Copy>(
+        &self, device: &ash::Device, command_buffer: vk::CommandBuffer, stage_flags: vk::ShaderStageFlags, offset: u32,
+        value: &T,
+    ) -> Result<()>
+    {
+        cmd_push_constant_bytes(
+            device,
+            command_buffer,
+            self.pipeline_layout,
+            &self.push_constant_ranges,
+            stage_flags,
+            offset,
+            buffers::as_bytes(std::slice::from_ref(value)),
+        )
+    }
+}
+
+/// Record an update of the push constants of a pipeline layout created with push_constant_ranges
+///
+/// Checks the bytes are covered by the ranges for exactly the stages in stage_flags, as the validation layers would
+pub(crate) fn cmd_push_constant_bytes(
+    device: &ash::Device, command_buffer: vk::CommandBuffer, pipeline_layout: vk::PipelineLayout,
+    push_constant_ranges: &[vk::PushConstantRange], stage_flags: vk::ShaderStageFlags, offset: u32, bytes: &[u8],
+) -> Result<()>
+{
+    let size = bytes.len() as u32;
+    if !offset.is_multiple_of(4) || !size.is_multiple_of(4) {
+        return Err(VkAppError::DeviceError(format!(
+            "Push constants at offset {} of {} bytes must have an offset and size that are multiples of 4",
+            offset, size
+        )));
+    }
+
+    for byte in offset..offset + size {
+        let overlapping = push_constant_ranges
+            .iter()
+            .filter(|range| range.offset <= byte && byte < range.offset + range.size);
+        // Every stage pushed to must be declared for the byte, and every stage declared for the byte must be pushed to
+        let declared_stages = overlapping.fold(vk::ShaderStageFlags::empty(), |stages, range| stages | range.stage_flags);
+        if declared_stages != stage_flags {
+            return Err(VkAppError::DeviceError(format!(
+                "Push constant byte {} is declared for stages {:?} but was pushed for stages {:?}",
+                byte, declared_stages, stage_flags
+            )));
+        }
+    }
+
+    unsafe { device.cmd_push_constants(command_buffer, pipeline_layout, stage_flags, offset, bytes) };
+    Ok(())
 }
 
 /// Create the pipeline which converts a buffer of vertices or indices to a framebuffer
-pub fn create_pipeline(device: &ash::Device, swapchain_settings: SwapchainSettings) -> Result<Pipeline>
+///
+/// With dynamic rendering no render pass is created and the attachment formats are given to the pipeline directly.
+/// The push constant ranges must fit within the device's maxPushConstantsSize, and the descriptor set layout comes from
+/// the layout cache
+///
+/// With a bindless set layout the frame's set only holds the uniform buffer, and the fragment shader samples the
+/// bindless set at the indices pushed for each draw
+pub fn create_pipeline(
+    device: &ash::Device, layout_cache: &mut DescriptorLayoutCache, swapchain_settings: SwapchainSettings,
+    use_render_pass: bool, push_constant_ranges: &[vk::PushConstantRange], max_push_constants_size: u32,
+    bindless_set_layout: Option<vk::DescriptorSetLayout>,
+) -> Result<Pipeline>
 {
-    let render_pass = create_render_pass(device, swapchain_settings)?;
-    let descriptor_set_layout = create_descriptor_set_layout(device)?;
-    let pipeline_layout = create_pipeline_layout(device, descriptor_set_layout)?;
+    validate_push_constant_ranges(push_constant_ranges, max_push_constants_size)?;
+
+    let render_pass = if use_render_pass {
+        create_render_pass(device, swapchain_settings)?
+    } else {
+        vk::RenderPass::null()
+    };
+    let bindings = descriptor_set_bindings();
+    let (descriptor_set_layout, fragment_shader_path) = match bindless_set_layout {
+        // The uniform buffer is the first binding
+        Some(_) => (layout_cache.get_or_create(&bindings[..1])?, "fragmentshader_bindless.spv"),
+        None => (layout_cache.get_or_create(&bindings)?, "fragmentshader.spv"),
+    };
+    let set_layouts: Vec<vk::DescriptorSetLayout> =
+        std::iter::once(descriptor_set_layout).chain(bindless_set_layout).collect();
+    let pipeline_layout = create_pipeline_layout(device, &set_layouts, push_constant_ranges)?;
     let vertex_shader_module = create_shader_module(device, String::from("vertexshader.spv"))?;
-    let fragment_shader_module = create_shader_module(device, String::from("fragmentshader.spv"))?;
+    let fragment_shader_module = create_shader_module(device, String::from(fragment_shader_path))?;
     let graphics_pipeline = create_graphics_pipeline(
         device,
         swapchain_settings,
@@ -41,6 +160,7 @@ pub fn create_pipeline(device: &ash::Device, swapchain_settings: SwapchainSettin
         render_pass,
         vertex_shader_module,
         fragment_shader_module,
+        true,
     )?;
 
     Ok(Pipeline {
@@ -48,9 +168,93 @@ pub fn create_pipeline(device: &ash::Device, swapchain_settings: SwapchainSettin
         descriptor_set_layout,
         pipeline_layout,
         graphics_pipeline,
+        push_constant_ranges: push_constant_ranges.to_vec(),
     })
 }
 
+/// Create the pipeline that draws the skybox behind everything else, in the render pass of the main pipeline when
+/// rendering with render passes
+///
+/// The vertex shader makes a triangle covering the screen from the vertex indices so no vertex buffer is bound, and the
+/// fragment shader samples the cubemap and sampler in the set in the direction through each fragment
+pub fn create_skybox_pipeline(
+    device: &ash::Device, layout_cache: &mut DescriptorLayoutCache, swapchain_settings: SwapchainSettings,
+    render_pass: vk::RenderPass, push_constant_ranges: &[vk::PushConstantRange], max_push_constants_size: u32,
+) -> Result<Pipeline>
+{
+    validate_push_constant_ranges(push_constant_ranges, max_push_constants_size)?;
+
+    let bindings = [
+        vk::DescriptorSetLayoutBinding::default()
+            .binding(0)
+            .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT),
+        vk::DescriptorSetLayoutBinding::default()
+            .binding(1)
+            .descriptor_type(vk::DescriptorType::SAMPLER)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT),
+    ];
+    let descriptor_set_layout = layout_cache.get_or_create(&bindings)?;
+    let pipeline_layout = create_pipeline_layout(device, &[descriptor_set_layout], push_constant_ranges)?;
+    let vertex_shader_module = create_shader_module(device, String::from("skybox_vertex.spv"))?;
+    let fragment_shader_module = create_shader_module(device, String::from("skybox_fragment.spv"))?;
+    let graphics_pipeline = create_graphics_pipeline(
+        device,
+        swapchain_settings,
+        pipeline_layout,
+        render_pass,
+        vertex_shader_module,
+        fragment_shader_module,
+        false,
+    )?;
+
+    Ok(Pipeline {
+        render_pass: vk::RenderPass::null(), // Owned by the main pipeline
+        descriptor_set_layout,
+        pipeline_layout,
+        graphics_pipeline,
+        push_constant_ranges: push_constant_ranges.to_vec(),
+    })
+}
+
+/// Check push constant ranges against the rules for creating a pipeline layout, so a mistake is reported as an error
+/// rather than relying on the validation layers
+pub(crate) fn validate_push_constant_ranges(
+    push_constant_ranges: &[vk::PushConstantRange], max_push_constants_size: u32,
+) -> Result<()>
+{
+    let mut declared_stages = vk::ShaderStageFlags::empty();
+    for range in push_constant_ranges {
+        let error = if range.size == 0 || !range.offset.is_multiple_of(4) || !range.size.is_multiple_of(4) {
+            Some(String::from(
+                "must have an offset and a non-zero size that are multiples of 4",
+            ))
+        } else if range.offset + range.size > max_push_constants_size {
+            Some(format!(
+                "exceeds the device's maxPushConstantsSize of {} bytes",
+                max_push_constants_size
+            ))
+        } else if range.stage_flags.is_empty() {
+            Some(String::from("must be used by at least one shader stage"))
+        } else if declared_stages.intersects(range.stage_flags) {
+            Some(String::from("shares a shader stage with another range"))
+        } else {
+            None
+        };
+
+        if let Some(error) = error {
+            return Err(VkAppError::DeviceError(format!(
+                "Push constant range of {} bytes at offset {} for stages {:?} {}",
+                range.size, range.offset, range.stage_flags, error
+            )));
+        }
+        declared_stages |= range.stage_flags;
+    }
+    Ok(())
+}
+
 /// The render pass specifies details about the framebuffer attachments that are used while rendering
 fn create_render_pass(device: &ash::Device, swapchain_settings: SwapchainSettings) -> Result<vk::RenderPass>
 {
@@ -109,11 +313,14 @@ fn create_render_pass(device: &ash::Device, swapchain_settings: SwapchainSetting
 }
 
 /// The descriptor layout specifies the types of resources that are going to be accessed by the pipeline, for example uniform buffers and images
-fn create_descriptor_set_layout(device: &ash::Device) -> Result<vk::DescriptorSetLayout>
+///
+/// The texture binding is left out when drawing bindless
+fn descriptor_set_bindings() -> [vk::DescriptorSetLayoutBinding<'static>; 2]
 {
     let ubo_layout_binding = vk::DescriptorSetLayoutBinding::default()
         .binding(0)
-        .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+        // Every object has its own slice of the uniform buffer, selected by the dynamic offset passed when binding the set
+        .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC)
         .descriptor_count(1)
         .stage_flags(vk::ShaderStageFlags::VERTEX);
 
@@ -123,18 +330,17 @@ fn create_descriptor_set_layout(device: &ash::Device) -> Result<vk::DescriptorSe
         .descriptor_count(1)
         .stage_flags(vk::ShaderStageFlags::FRAGMENT);
 
-    let bindings = [ubo_layout_binding, sampler_layout_binding];
-    let layout_create_info = vk::DescriptorSetLayoutCreateInfo::default().bindings(&bindings);
-
-    Ok(unsafe { device.create_descriptor_set_layout(&layout_create_info, None)? })
+    [ubo_layout_binding, sampler_layout_binding]
 }
 
 /// The pipeline layout specifies uniform values in shaders and push constants (another way of passing dynamic values to shaders)
-fn create_pipeline_layout(device: &ash::Device, descriptor_set_layout: vk::DescriptorSetLayout)
-    -> Result<vk::PipelineLayout>
+fn create_pipeline_layout(
+    device: &ash::Device, set_layouts: &[vk::DescriptorSetLayout], push_constant_ranges: &[vk::PushConstantRange],
+) -> Result<vk::PipelineLayout>
 {
-    let layouts = [descriptor_set_layout];
-    let pipeline_layout_create_info = vk::PipelineLayoutCreateInfo::default().set_layouts(&layouts);
+    let pipeline_layout_create_info = vk::PipelineLayoutCreateInfo::default()
+        .set_layouts(set_layouts)
+        .push_constant_ranges(push_constant_ranges);
 
     Ok(unsafe { device.create_pipeline_layout(&pipeline_layout_create_info, None) }?)
 }
@@ -158,10 +364,13 @@ pub fn create_shader_module(device: &ash::Device, path: String) -> Result<vk::Sh
 ///
 /// Pipeline layout: Uniform and push values referenced by shader that can be updated at draw time
 ///
-/// Render pass: Attachments referenced by the pipeline stages and their usage
+/// Render pass: Attachments referenced by the pipeline stages and their usage, or the attachment formats if using dynamic rendering
+///
+/// Vertex input: Whether vk_app::Vertex vertices are read from a vertex buffer, otherwise the vertex shader makes its own
 fn create_graphics_pipeline(
     device: &ash::Device, swapchain_settings: SwapchainSettings, pipeline_layout: vk::PipelineLayout,
     render_pass: vk::RenderPass, vertex_shader_module: vk::ShaderModule, fragment_shader_module: vk::ShaderModule,
+    vertex_input: bool,
 ) -> Result<vk::Pipeline>
 {
     /*  Initialize dynamic state information for the viewport and scissor
@@ -274,9 +483,13 @@ fn create_graphics_pipeline(
 
     let binding_descriptions = [binding_description];
     // Tell the pipeline about how we pass vertex information to the GPU
-    let vertex_input_create_info = vk::PipelineVertexInputStateCreateInfo::default()
-        .vertex_binding_descriptions(&binding_descriptions)
-        .vertex_attribute_descriptions(&attribute_descriptions);
+    let vertex_input_create_info = if vertex_input {
+        vk::PipelineVertexInputStateCreateInfo::default()
+            .vertex_binding_descriptions(&binding_descriptions)
+            .vertex_attribute_descriptions(&attribute_descriptions)
+    } else {
+        vk::PipelineVertexInputStateCreateInfo::default()
+    };
 
     let input_assembly_create_info = vk::PipelineInputAssemblyStateCreateInfo::default()
         .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
@@ -309,6 +522,16 @@ fn create_graphics_pipeline(
         .base_pipeline_handle(vk::Pipeline::null()) // No parent pipeline
         .base_pipeline_index(-1);
 
+    // Without a render pass the pipeline must be told the formats of the attachments it will render to
+    let colour_attachment_formats = [swapchain_settings.format.format];
+    let mut rendering_create_info =
+        vk::PipelineRenderingCreateInfo::default().color_attachment_formats(&colour_attachment_formats);
+    let graphics_pipeline_create_info = if render_pass == vk::RenderPass::null() {
+        graphics_pipeline_create_info.push_next(&mut rendering_create_info)
+    } else {
+        graphics_pipeline_create_info
+    };
+
     let create_infos = [graphics_pipeline_create_info];
     let graphics_pipelines = unsafe { device.create_graphics_pipelines(vk::PipelineCache::null(), &create_infos, None) }
         .map_err(|errors| errors.1)?;
@@ -1,4 +1,4 @@
-use crate::graphics::{device::SupportedPhysicalDevice, errors::VkAppError, pipeline, vk_app::Result};
+use crate::graphics::{debug::DebugUtils, device::SupportedPhysicalDevice, errors::VkAppError, pipeline, vk_app::Result};
 use crate::{log, project};
 use ash::{khr, vk, Device, Entry, Instance};
 
@@ -147,7 +147,9 @@ pub struct Swapchain
     pub swapchain_device: khr::swapchain::Device,
     pub vk_swapchain:     vk::SwapchainKHR,
     pub settings:         SwapchainSettings,
+    pub images:           Vec<vk::Image>,
     pub image_views:      Vec<vk::ImageView>,
+    /// Only created when rendering with render pass objects, dynamic rendering renders to the image views directly
     pub framebuffers:     Vec<vk::Framebuffer>,
 }
 
@@ -166,6 +168,21 @@ impl Swapchain
         }
     }
 
+    /// Name the swapchain and everything created for its images, called again once framebuffers have been created
+    pub fn set_debug_names(&self, debug_utils: &DebugUtils)
+    {
+        debug_utils.set_name(self.vk_swapchain, "Swapchain");
+        for (index, &image) in self.images.iter().enumerate() {
+            debug_utils.set_name(image, format!("Swapchain image {}", index).as_str());
+        }
+        for (index, &image_view) in self.image_views.iter().enumerate() {
+            debug_utils.set_name(image_view, format!("Swapchain image view {}", index).as_str());
+        }
+        for (index, &framebuffer) in self.framebuffers.iter().enumerate() {
+            debug_utils.set_name(framebuffer, format!("Swapchain framebuffer {}", index).as_str());
+        }
+    }
+
     /// The render pass expects a single framebuffer with the same format as the swapchain images
     ///
     /// A vk::Framebuffer object references all the vk::ImageView objects that represent the framebuffer's attachments
@@ -235,11 +252,14 @@ pub fn create_swapchain(
 
     let swapchain_device = khr::swapchain::Device::new(instance, device);
     let vk_swapchain = unsafe { swapchain_device.create_swapchain(&swapchain_create_info, None) }?;
-    let image_views = create_swapchain_image_views(device, &swapchain_device, vk_swapchain, swapchain_settings)?;
+    // Retrieve images stored by swapchain
+    let images = unsafe { swapchain_device.get_swapchain_images(vk_swapchain) }?;
+    let image_views = create_swapchain_image_views(device, &images, swapchain_settings)?;
     Ok(Swapchain {
         swapchain_device,
         vk_swapchain,
         settings: swapchain_settings,
+        images,
         image_views,
         framebuffers: Vec::new(),
     })
@@ -249,14 +269,11 @@ pub fn create_swapchain(
 ///
 /// Create a basic image view for every image in the swapchain to use them as targets later
 fn create_swapchain_image_views(
-    device: &Device, swapchain_device: &khr::swapchain::Device, vk_swapchain: vk::SwapchainKHR,
-    swapchain_settings: SwapchainSettings,
+    device: &Device, swapchain_images: &[vk::Image], swapchain_settings: SwapchainSettings,
 ) -> Result<Vec<vk::ImageView>>
 {
-    // Retrieve images stored by swapchain
-    let swapchain_images = unsafe { swapchain_device.get_swapchain_images(vk_swapchain) }?;
     let mut swapchain_image_views: Vec<vk::ImageView> = Vec::new();
-    for swapchain_image in swapchain_images {
+    for &swapchain_image in swapchain_images {
         let image_view_info = vk::ImageViewCreateInfo::default()
             .image(swapchain_image)
             .view_type(vk::ImageViewType::TYPE_2D)
@@ -0,0 +1,738 @@
+use crate::graphics::vk_app::Result;
+use crate::graphics::*;
+use crate::{log, project, warn};
+use ash::vk;
+use std::rc::Rc;
+
+/// Index of the texture the particles are rendered into, the loaded textures follow it
+pub const PARTICLE_TEXTURE: usize = 0;
+/// Index of the first loaded texture
+pub const FIRST_LOADED_TEXTURE: usize = PARTICLE_TEXTURE + 1;
+
+/// A texture being uploaded to replace the texture in a slot, swapped in once its upload has completed
+struct PendingTexture
+{
+    index:   usize,
+    texture: textures::Texture,
+    sampler: vk::Sampler,
+    ticket:  upload::UploadTicket,
+}
+
+/// A replaced or released texture, freed along with its bindless slot once the frames that could draw it have completed
+struct RetiredTexture
+{
+    /// The frame the texture was retired before
+    retired_before: u64,
+    _texture:       textures::Texture, // Only kept to be dropped
+    /// None when the texture never had a slot of its own
+    bindless_slot:  Option<u32>,
+}
+
+/// Owns every object created from the logical device, and the device itself
+///
+/// GPU buffers share ownership of the device and free themselves when the renderer's fields are dropped, after which
+/// the device is destroyed. Nothing here outlives the device so when the device is lost the whole renderer can be dropped and rebuilt from the
+/// CPU-side data retained by VkApp, without touching the instance or the surface
+pub struct Renderer
+{
+    instance:                 ash::Instance, // Not owned, destroyed by VkApp after the renderer
+    physical_device:          device::SupportedPhysicalDevice,
+    // The surface is owned by VkApp, we only keep the details for creating the swapchain
+    surface:                  presentation::Surface,
+    device:                   Rc<device::LogicalDevice>,
+    debug_utils:              debug::DebugUtils,
+    graphics_queue:           vk::Queue,
+    present_queue:            vk::Queue,
+    swapchain:                presentation::Swapchain,
+    rendering_path:           commands::RenderingPath,
+    pipeline:                 pipeline::Pipeline,
+    command_pool:             vk::CommandPool,
+    // The texture the particles are rendered into followed by the loaded textures, None where a texture was released
+    textures:                 Vec<Option<textures::Texture>>,
+    texture_samplers:         Vec<vk::Sampler>,
+    sampler_cache:            samplers::SamplerCache, // Owns the texture samplers, must outlive the descriptor sets using them
+    // Kept after the first uploads for uploading replacement textures, such as streamed textures
+    upload_context:           upload::UploadContext,
+    pending_textures:         Vec<PendingTexture>,
+    retired_textures:         Vec<RetiredTexture>,
+    // None when the device doesn't support descriptor indexing, then each texture is bound with its own set
+    bindless:                 Option<bindless::BindlessTextures>,
+    // Each texture's slot in the bindless texture array, and its sampler's slot in the sampler array, 0 when textures are
+    // bound per draw
+    bindless_texture_slots:   Vec<u32>,
+    bindless_sampler_slots:   Vec<u32>,
+    meshes:                   Vec<mesh::Mesh<vk_app::Vertex>>,
+    skybox:                   skybox::Skybox,
+    particles:                particles::ParticleSystem,
+    _particle_sprites:        textures::Texture, // Sampled by the particle system's dispatches
+    // When the previous frame was drawn, for advancing the particle simulation
+    last_frame_time:          std::time::Instant,
+    uniform_buffers:          Vec<buffers::DynamicUniformBuffer<buffers::UniformBufferObject>>,
+    _descriptor_layout_cache: descriptors::DescriptorLayoutCache, // Owns the pipeline's and bindless descriptor set layouts, must outlive them
+    // Each frame's descriptor sets are allocated while recording it and freed when the frame is next recorded
+    descriptor_allocators:    Vec<descriptors::DescriptorAllocator>,
+    command_buffers:          Vec<vk::CommandBuffer>,
+    sync_objects:             commands::SyncObjects,
+    // current_frame keeps track of the index to use the right objects (command buffers, semaphores)
+    current_frame:            usize,
+    // Frames drawn so far, for knowing when a frame that drew a retired texture has completed
+    frame_count:              u64,
+}
+
+impl Drop for Renderer
+{
+    fn drop(&mut self)
+    {
+        log!("Cleaning up Renderer for device {}", self.physical_device.device_name);
+        unsafe {
+            // Fails with ERROR_DEVICE_LOST if the device has been lost, destroying objects is still valid in that case
+            if let Err(err) = self.device.device_wait_idle() {
+                warn!(
+                    "Failed to wait for device {} to be idle: {}",
+                    self.physical_device.device_name, err
+                );
+            }
+
+            self.swapchain.cleanup(&self.device);
+
+            self.pipeline.cleanup(&self.device);
+            self.sync_objects.cleanup(&self.device);
+            self.device.destroy_command_pool(self.command_pool, None);
+        }
+        // The textures, samplers, buffers and then the device are destroyed when the fields are dropped
+    }
+}
+
+impl Renderer
+{
+    /// Create the logical device for the selected physical device and every GPU resource needed to draw
+    ///
+    /// Objects can draw the texture the particles are rendered into, at PARTICLE_TEXTURE, and the loaded textures after
+    /// it in order. Released textures are None and keep their index, so the textures after them keep theirs
+    ///
+    /// The sky is a cubemap, or an equirectangular image converted to a cubemap with faces a quarter of its width, and is
+    /// drawn behind the objects. The particle sprites are an array texture with a sprite in each layer
+    pub fn new(
+        instance: &ash::Instance, physical_device: device::SupportedPhysicalDevice, surface: presentation::Surface,
+        textures: &[Option<&textures::TextureData>], meshes: &[mesh::MeshData<vk_app::Vertex>], sky: &textures::TextureData,
+        particle_sprites: &textures::TextureData,
+    ) -> Result<Self>
+    {
+        let vk_device = device::create_logical_device(instance, &physical_device)?;
+        let debug_utils = debug::DebugUtils::new(instance, &vk_device);
+        let allocator = allocator::Allocator::new(instance, &physical_device, &vk_device, &debug_utils);
+        let device = Rc::new(device::LogicalDevice::new(vk_device, allocator));
+
+        let (graphics_queue, present_queue) = unsafe {
+            (
+                device.get_device_queue(physical_device.graphics_family_index, 0),
+                device.get_device_queue(physical_device.present_family_index, 0),
+            )
+        };
+
+        debug_utils.set_name(device.handle(), physical_device.device_name.as_str());
+        debug_utils.set_name(graphics_queue, "Graphics queue");
+        if present_queue != graphics_queue {
+            debug_utils.set_name(present_queue, "Present queue");
+        }
+
+        let rendering_path = commands::RenderingPath::new(instance, &device, physical_device.rendering_support);
+        log!("Rendering with {:?}", physical_device.rendering_support);
+
+        let properties = unsafe { instance.get_physical_device_properties(physical_device.vk_physical_device) };
+
+        let mut descriptor_layout_cache = descriptors::DescriptorLayoutCache::new(&device, &debug_utils);
+
+        let mut bindless = match physical_device.descriptor_indexing {
+            device::DescriptorIndexingSupport::Unsupported => None,
+            _ => Some(bindless::BindlessTextures::new(
+                instance,
+                &physical_device,
+                &device,
+                &debug_utils,
+                &mut descriptor_layout_cache,
+            )?),
+        };
+        log!(
+            "Bindless textures {}",
+            if bindless.is_some() { "enabled" } else { "not supported" }
+        );
+
+        let mut swapchain = presentation::create_swapchain(instance, &device, &physical_device, &surface)?;
+        let pipeline = pipeline::create_pipeline(
+            &device,
+            &mut descriptor_layout_cache,
+            swapchain.settings,
+            rendering_path.uses_render_pass(),
+            &pipeline::DrawPushConstants::ranges(),
+            properties.limits.max_push_constants_size,
+            bindless.as_ref().map(|bindless| bindless.set_layout),
+        )?;
+        if rendering_path.uses_render_pass() {
+            swapchain.create_framebuffers(&device, &pipeline)?;
+        }
+        swapchain.set_debug_names(&debug_utils);
+        pipeline.set_debug_names(&debug_utils);
+
+        let command_pool = commands::create_command_pool(&device, physical_device.graphics_family_index)?;
+        debug_utils.set_name(command_pool, "Command pool");
+
+        // Every upload is batched into a single submission, which we wait for once before the first frame. Later
+        // uploads are submitted as they are made and waited on without blocking, see replace_texture
+        let mut upload_context =
+            upload::UploadContext::new(instance, &device, &debug_utils, &physical_device, graphics_queue)?;
+
+        // Textures sampled the same way share a sampler
+        let mut sampler_cache = samplers::SamplerCache::new(instance, &physical_device, &device, &debug_utils);
+        // The particles are drawn across the whole quad so they shouldn't wrap around its edges
+        let particle_sampler = samplers::SamplerDesc {
+            address_modes: [vk::SamplerAddressMode::CLAMP_TO_EDGE; 3],
+            ..Default::default()
+        };
+
+        let particle_texture = textures::Texture::new_storage(
+            &device,
+            &debug_utils,
+            "Particles",
+            particles::PARTICLE_IMAGE_SIZE,
+            particles::PARTICLE_IMAGE_SIZE,
+        )?;
+        if !matches!(particle_sprites.kind, textures::TextureKind::Array(_)) {
+            return Err(errors::VkAppError::DeviceError(format!(
+                "Particle sprites {} must be an array texture",
+                particle_sprites.path
+            )));
+        }
+        let particle_sprites = textures::Texture::new(
+            &mut upload_context,
+            instance,
+            &physical_device,
+            &device,
+            &debug_utils,
+            particle_sprites,
+        )?;
+        let particles = particles::ParticleSystem::new(
+            &mut upload_context,
+            &device,
+            &debug_utils,
+            &mut descriptor_layout_cache,
+            &particle_texture,
+            // Sprites shouldn't wrap around their edges either
+            (particle_sprites.view(), sampler_cache.get_or_create(&particle_sampler)?),
+            properties.limits.max_push_constants_size,
+        )?;
+
+        let sky_cubemap = if sky.kind == textures::TextureKind::Cube {
+            textures::Texture::new(&mut upload_context, instance, &physical_device, &device, &debug_utils, sky)?
+        } else {
+            let equirect_converter = cubemaps::EquirectConverter::new(
+                &device,
+                &debug_utils,
+                &mut descriptor_layout_cache,
+                &mut sampler_cache,
+                properties.limits.max_push_constants_size,
+            )?;
+            let sky_cubemap = equirect_converter.convert(
+                &mut upload_context,
+                instance,
+                &physical_device,
+                &debug_utils,
+                sky,
+                (sky.width / 4).max(1),
+            )?;
+            // The converter is only needed for the sky, so it is freed once the conversion has completed
+            upload_context.retain_until_complete(equirect_converter)?;
+            sky_cubemap
+        };
+        let skybox = skybox::Skybox::new(
+            &device,
+            &debug_utils,
+            pipeline::create_skybox_pipeline(
+                &device,
+                &mut descriptor_layout_cache,
+                swapchain.settings,
+                pipeline.render_pass,
+                &skybox::SkyboxPushConstants::ranges(),
+                properties.limits.max_push_constants_size,
+            )?,
+            sky_cubemap,
+            sampler_cache.get_or_create(&samplers::SamplerDesc::default())?,
+        )?;
+
+        let mut all_textures = vec![Some(particle_texture)];
+        let mut texture_samplers = vec![sampler_cache.get_or_create(&particle_sampler)?];
+        for texture_data in textures {
+            match texture_data {
+                Some(texture_data) => {
+                    all_textures.push(Some(textures::Texture::new(
+                        &mut upload_context,
+                        instance,
+                        &physical_device,
+                        &device,
+                        &debug_utils,
+                        texture_data,
+                    )?));
+                    texture_samplers.push(sampler_cache.get_or_create(&texture_data.sampler)?);
+                }
+                None => {
+                    all_textures.push(None);
+                    texture_samplers.push(vk::Sampler::null());
+                }
+            }
+        }
+
+        // Filling the slots doesn't have to wait for the uploads, only drawing with them does
+        let mut bindless_texture_slots = Vec::with_capacity(all_textures.len());
+        let mut bindless_sampler_slots = Vec::with_capacity(all_textures.len());
+        for (index, texture) in all_textures.iter().enumerate() {
+            let (texture_slot, sampler_slot) = match (bindless.as_mut(), texture) {
+                (Some(bindless), Some(texture)) => bindless_slots(bindless, texture, texture_samplers[index])?,
+                // Released textures are never drawn
+                _ => (0, 0),
+            };
+            bindless_texture_slots.push(texture_slot);
+            bindless_sampler_slots.push(sampler_slot);
+        }
+
+        let meshes = meshes
+            .iter()
+            .map(|mesh_data| mesh::Mesh::new(&mut upload_context, &device, &debug_utils, mesh_data))
+            .collect::<Result<Vec<_>>>()?;
+
+        upload_context.wait_idle()?;
+
+        let uniform_buffers =
+            buffers::create_uniform_buffers(&device, &debug_utils, properties.limits.min_uniform_buffer_offset_alignment)?;
+
+        let descriptor_allocators = (0..commands::MAX_FRAMES_IN_FLIGHT)
+            .map(|frame| descriptors::DescriptorAllocator::new(&device, &debug_utils, format!("frame {}", frame).as_str()))
+            .collect();
+
+        let command_buffers = commands::create_command_buffers(&device, &debug_utils, command_pool)?;
+
+        let sync_objects = commands::create_sync_objects(&device)?;
+
+        log!("GPU memory: {}", device.allocator.borrow().stats());
+
+        Ok(Self {
+            instance: instance.clone(),
+            physical_device,
+            surface,
+            device,
+            debug_utils,
+            graphics_queue,
+            present_queue,
+            swapchain,
+            rendering_path,
+            pipeline,
+            command_pool,
+            textures: all_textures,
+            texture_samplers,
+            sampler_cache,
+            upload_context,
+            pending_textures: Vec::new(),
+            retired_textures: Vec::new(),
+            bindless,
+            bindless_texture_slots,
+            bindless_sampler_slots,
+            meshes,
+            skybox,
+            particles,
+            _particle_sprites: particle_sprites,
+            last_frame_time: std::time::Instant::now(),
+            uniform_buffers,
+            _descriptor_layout_cache: descriptor_layout_cache,
+            descriptor_allocators,
+            command_buffers,
+            sync_objects,
+            current_frame: 0,
+            frame_count: 0,
+        })
+    }
+
+    /// Upload a texture to replace the texture in a slot, e.g. a streamed texture replacing its placeholder
+    ///
+    /// The upload is submitted straight away but drawing doesn't wait for it, the slot keeps drawing the texture it has
+    /// until the upload has completed and the new texture is swapped in at the start of a frame. The replaced texture is
+    /// freed once no frame in flight can be drawing it
+    pub fn replace_texture(&mut self, index: usize, texture_data: &textures::TextureData) -> Result<()>
+    {
+        if index < FIRST_LOADED_TEXTURE || self.textures.get(index).is_none_or(Option::is_none) {
+            return Err(errors::VkAppError::DeviceError(format!(
+                "Cannot replace texture {} as it isn't a loaded texture",
+                index
+            )));
+        }
+
+        let sampler = self.sampler_cache.get_or_create(&texture_data.sampler)?;
+        let texture = textures::Texture::new(
+            &mut self.upload_context,
+            &self.instance,
+            &self.physical_device,
+            &self.device,
+            &self.debug_utils,
+            texture_data,
+        )?;
+        let ticket = self.upload_context.flush()?;
+        self.pending_textures.push(PendingTexture { index, texture, sampler, ticket });
+        Ok(())
+    }
+
+    /// Upload a texture to a released index, or the index after the last texture, blocking until it is uploaded
+    ///
+    /// Blocking lets objects draw the texture straight away, so this is for small textures such as placeholders, which
+    /// larger textures then replace with replace_texture
+    pub fn insert_texture(&mut self, index: usize, texture_data: &textures::TextureData) -> Result<()>
+    {
+        if index < FIRST_LOADED_TEXTURE
+            || index > self.textures.len()
+            || self.textures.get(index).is_some_and(Option::is_some)
+        {
+            return Err(errors::VkAppError::DeviceError(format!(
+                "Cannot insert texture {} as the index is in use or after the end of the textures",
+                index
+            )));
+        }
+
+        let sampler = self.sampler_cache.get_or_create(&texture_data.sampler)?;
+        let texture = textures::Texture::new(
+            &mut self.upload_context,
+            &self.instance,
+            &self.physical_device,
+            &self.device,
+            &self.debug_utils,
+            texture_data,
+        )?;
+        let ticket = self.upload_context.flush()?;
+        self.upload_context.wait(ticket)?;
+
+        let (texture_slot, sampler_slot) = match self.bindless.as_mut() {
+            Some(bindless) => bindless_slots(bindless, &texture, sampler)?,
+            None => (0, 0),
+        };
+        if index == self.textures.len() {
+            self.textures.push(Some(texture));
+            self.texture_samplers.push(sampler);
+            self.bindless_texture_slots.push(texture_slot);
+            self.bindless_sampler_slots.push(sampler_slot);
+        } else {
+            self.textures[index] = Some(texture);
+            self.texture_samplers[index] = sampler;
+            self.bindless_texture_slots[index] = texture_slot;
+            self.bindless_sampler_slots[index] = sampler_slot;
+        }
+
+        Ok(())
+    }
+
+    /// Release the texture at an index, objects can't draw it until a texture is inserted there again
+    ///
+    /// The texture, and any replacement still being uploaded for it, is freed once no frame in flight can be using it
+    pub fn release_texture(&mut self, index: usize) -> Result<()>
+    {
+        if index < FIRST_LOADED_TEXTURE || index >= self.textures.len() {
+            return Err(errors::VkAppError::DeviceError(format!(
+                "Cannot release texture {} as it isn't a loaded texture",
+                index
+            )));
+        }
+
+        let (released, pending): (Vec<PendingTexture>, Vec<PendingTexture>) = std::mem::take(&mut self.pending_textures)
+            .into_iter()
+            .partition(|pending| pending.index == index);
+        self.pending_textures = pending;
+        // A replacement's upload was submitted after the last frame, so it is only known to have completed once the next
+        // frame has
+        let next_frame = self.frame_count + 1;
+        self.retired_textures
+            .extend(released.into_iter().map(|pending| RetiredTexture {
+                retired_before: next_frame,
+                _texture:       pending.texture,
+                bindless_slot:  None,
+            }));
+        // The texture's bindless slot is freed with it, as frames in flight could still be reading the slot
+        if let Some(texture) = self.textures[index].take() {
+            self.retired_textures.push(RetiredTexture {
+                retired_before: self.frame_count,
+                _texture:       texture,
+                bindless_slot:  self.bindless.as_ref().map(|_| self.bindless_texture_slots[index]),
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Swap in the replacement textures whose uploads have completed and free the replaced textures no frame in flight
+    /// can be drawing, called once the current frame's previous submission has completed
+    fn swap_in_replaced_textures(&mut self) -> Result<()>
+    {
+        // The frames before the current frame's previous submission have completed, which are the frames that could
+        // have drawn a texture replaced fewer than MAX_FRAMES_IN_FLIGHT frames ago
+        let frame_count = self.frame_count;
+        let (completed, retired): (Vec<RetiredTexture>, Vec<RetiredTexture>) = std::mem::take(&mut self.retired_textures)
+            .into_iter()
+            .partition(|retired| retired.retired_before + commands::MAX_FRAMES_IN_FLIGHT as u64 <= frame_count + 1);
+        self.retired_textures = retired;
+        for retired in completed {
+            if let (Some(bindless), Some(slot)) = (self.bindless.as_mut(), retired.bindless_slot) {
+                bindless.free_texture(slot)?;
+            }
+        }
+
+        // Replacements are swapped in the order they were made, so a slot ends up with its latest replacement
+        let mut index = 0;
+        while index < self.pending_textures.len() {
+            if !self.upload_context.is_complete(self.pending_textures[index].ticket)? {
+                index += 1;
+                continue;
+            }
+            let pending = self.pending_textures.remove(index);
+
+            // Bindless slots can't be overwritten while a frame in flight could read them, so the texture gets new slots
+            // and the replaced texture's slot is freed with it
+            let replaced_slot = self.bindless.as_ref().map(|_| self.bindless_texture_slots[pending.index]);
+            if let Some(bindless) = self.bindless.as_mut() {
+                (
+                    self.bindless_texture_slots[pending.index],
+                    self.bindless_sampler_slots[pending.index],
+                ) = bindless_slots(bindless, &pending.texture, pending.sampler)?;
+            }
+            // Without bindless each frame writes its own sets when it is recorded, so the next frame uses the new texture
+            self.texture_samplers[pending.index] = pending.sampler;
+            if let Some(replaced) = self.textures[pending.index].replace(pending.texture) {
+                self.retired_textures.push(RetiredTexture {
+                    retired_before: self.frame_count,
+                    _texture:       replaced,
+                    bindless_slot:  replaced_slot,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Memory use by heap, memory type and purpose
+    pub fn memory_snapshot(&self) -> allocator::MemorySnapshot { self.device.allocator.borrow().snapshot() }
+
+    /// Draw each object's mesh with its transform and texture
+    pub fn draw_frame(&mut self, objects: &[mesh::DrawObject]) -> Result<()>
+    {
+        if let Some(object) = objects.iter().find(|object| object.mesh >= self.meshes.len()) {
+            return Err(errors::VkAppError::DeviceError(format!(
+                "Object uses mesh {} but there are only {} meshes",
+                object.mesh,
+                self.meshes.len()
+            )));
+        }
+        if let Some(object) = objects
+            .iter()
+            .find(|object| self.textures.get(object.texture).is_none_or(Option::is_none))
+        {
+            return Err(errors::VkAppError::DeviceError(format!(
+                "Object uses texture {} which doesn't exist or has been released",
+                object.texture
+            )));
+        }
+
+        unsafe {
+            // Wait until the current previous frame has finished
+            self.device
+                .wait_for_fences(&[self.sync_objects.in_flight_fences[self.current_frame]], true, u64::MAX)?;
+
+            self.swap_in_replaced_textures()?;
+
+            // The frame's previous descriptor sets are no longer in use now its fence has been signalled
+            let descriptor_allocator = &mut self.descriptor_allocators[self.current_frame];
+            descriptor_allocator.reset()?;
+            // Bindless textures are all in one set so the frame only needs one set for its uniform buffer, otherwise
+            // each texture needs a set of its own
+            let descriptor_set_count = if self.bindless.is_some() { 1 } else { self.textures.len() };
+            let mut descriptor_sets = Vec::with_capacity(descriptor_set_count);
+            for texture_index in 0..descriptor_set_count {
+                let texture = match (&self.bindless, &self.textures[texture_index]) {
+                    (Some(_), _) => None,
+                    (None, Some(texture)) => Some((texture.view(), self.texture_samplers[texture_index])),
+                    // Released textures aren't drawn so don't need a set
+                    (None, None) => {
+                        descriptor_sets.push(vk::DescriptorSet::null());
+                        continue;
+                    }
+                };
+                let descriptor_set = descriptor_allocator.allocate(self.pipeline.descriptor_set_layout)?;
+                buffers::write_descriptor_set(
+                    &self.device,
+                    descriptor_set,
+                    &self.uniform_buffers[self.current_frame],
+                    texture,
+                );
+                descriptor_sets.push(descriptor_set);
+            }
+
+            let particle_descriptor_set = descriptor_allocator.allocate(self.particles.descriptor_set_layout())?;
+            self.particles.write_descriptor_set(&self.device, particle_descriptor_set);
+            // A long pause, e.g. while the window is being moved, shouldn't throw the particles across the image
+            let now = std::time::Instant::now();
+            self.particles
+                .update(now.duration_since(self.last_frame_time).as_secs_f32().min(0.1));
+            self.last_frame_time = now;
+
+            // Acquire an image from the swapchain
+            let (image_index, suboptimal_surface) = match self.swapchain.swapchain_device.acquire_next_image(
+                self.swapchain.vk_swapchain,
+                u64::MAX, // Disable timeout for images to become available
+                self.sync_objects.image_available_semaphores[self.current_frame], // Synchronization object for when presentation execution has finished using the image
+                vk::Fence::null(),
+            ) {
+                Ok((image_index, suboptimal_surface)) => (image_index, suboptimal_surface),
+                Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => {
+                    // Swapchain has become incompatible with surface and can no longer be used for rendering, must be recreated and try again in next draw
+                    self.recreate_swapchain()?;
+                    return Ok(());
+                }
+                Err(err) => return Err(err.into()),
+            };
+
+            let object_dynamic_offsets = buffers::update_uniform_buffer(&self.uniform_buffers[self.current_frame], objects)?;
+            let object_bindings: Vec<commands::ObjectBinding> = objects
+                .iter()
+                .zip(object_dynamic_offsets)
+                .enumerate()
+                .map(|(object_index, (object, dynamic_offset))| {
+                    let (descriptor_set, texture_index, sampler_index) = match self.bindless {
+                        Some(_) => (
+                            descriptor_sets[0],
+                            self.bindless_texture_slots[object.texture],
+                            self.bindless_sampler_slots[object.texture],
+                        ),
+                        None => (descriptor_sets[object.texture], 0, 0),
+                    };
+                    commands::ObjectBinding {
+                        descriptor_set,
+                        dynamic_offset,
+                        push_constants: pipeline::DrawPushConstants {
+                            object_index: object_index as u32,
+                            texture_index,
+                            sampler_index,
+                        },
+                    }
+                })
+                .collect();
+
+            // Only reset the fence if we are sure we are submitting work to prevent deadlock
+            self.device
+                .reset_fences(&[self.sync_objects.in_flight_fences[self.current_frame]])?;
+
+            self.device
+                .reset_command_buffer(self.command_buffers[self.current_frame], vk::CommandBufferResetFlags::empty())?;
+
+            commands::record_command_buffer(
+                &self.device,
+                &self.debug_utils,
+                self.command_buffers[self.current_frame],
+                image_index,
+                &self.rendering_path,
+                &self.pipeline,
+                &self.swapchain,
+                &self.particles.dispatches(particle_descriptor_set),
+                &self.skybox,
+                &self.meshes,
+                objects,
+                self.bindless.as_ref().map(|bindless| bindless.set),
+                &object_bindings,
+            )?;
+
+            // Semaphores to wait on before execution begins
+            let wait_semaphores: [vk::Semaphore; 1] = [self.sync_objects.image_available_semaphores[self.current_frame]];
+            // Which stage of the pipeline to wait on. We wait at the point of writing colours to the image until its available
+            let wait_stages: [vk::PipelineStageFlags; 1] = [vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT];
+            // Which semaphores to signal once the command buffer has finished execution
+            let signal_semaphores: [vk::Semaphore; 1] = [self.sync_objects.render_finished_semaphores[self.current_frame]];
+
+            let command_buffers = [self.command_buffers[self.current_frame]];
+
+            let submit_info = vk::SubmitInfo::default()
+                .wait_semaphores(&wait_semaphores)
+                .wait_dst_stage_mask(&wait_stages)
+                .command_buffers(&command_buffers)
+                .signal_semaphores(&signal_semaphores);
+
+            self.device.queue_submit(
+                self.graphics_queue,
+                [submit_info].as_slice(),
+                self.sync_objects.in_flight_fences[self.current_frame],
+            )?;
+
+            // Finally, submit the result of the render pass back to the swapchain for presentation
+            let image_indices = [image_index];
+            let swapchains = [self.swapchain.vk_swapchain];
+            let present_info = vk::PresentInfoKHR::default()
+                .wait_semaphores(&signal_semaphores)
+                .image_indices(&image_indices)
+                .swapchains(&swapchains);
+
+            self.swapchain
+                .swapchain_device
+                .queue_present(self.present_queue, &present_info)?;
+
+            // A suboptimal surface is considered a success code and we have acquired an image successfully
+            // So recreate it after presenting the image
+            if suboptimal_surface {
+                log!("Suboptimal surface");
+                self.recreate_swapchain()?;
+            }
+        }
+
+        // Advance the frame, looping back round after every MAX_FRAMES_IN_FLIGHT frames
+        self.current_frame = (self.current_frame + 1) % commands::MAX_FRAMES_IN_FLIGHT as usize;
+        self.frame_count += 1;
+
+        Ok(())
+    }
+
+    /// The window surface can change such that the swapchain is no longer compatible with it (e.g a window resize)
+    ///
+    /// When these events occur, we should recreate the swapchain so it is compatible with the surface
+    pub fn recreate_swapchain(&mut self) -> Result<()>
+    {
+        log!("Recreating swapchain");
+
+        log!(
+            "Window Dimensions: Width {}, Height {}",
+            project::WINDOW_WIDTH.get(),
+            project::WINDOW_HEIGHT.get()
+        );
+
+        // Wait for in process execution to finish first
+        unsafe { self.device.device_wait_idle()? };
+
+        // Delete the previous swapchain
+        self.swapchain.cleanup(&self.device);
+
+        // Update the surface details with the new surface
+        self.surface.details = presentation::get_surface_details(
+            self.physical_device.vk_physical_device,
+            self.surface.vk_surface,
+            &self.surface.loader,
+        )?;
+
+        // Create the new swapchain
+        self.swapchain = presentation::create_swapchain(&self.instance, &self.device, &self.physical_device, &self.surface)?;
+        if self.rendering_path.uses_render_pass() {
+            self.swapchain.create_framebuffers(&self.device, &self.pipeline)?;
+        }
+        self.swapchain.set_debug_names(&self.debug_utils);
+
+        Ok(())
+    }
+}
+
+/// Fill a bindless slot with the texture and find its sampler's slot, which every texture using the sampler shares
+fn bindless_slots(
+    bindless: &mut bindless::BindlessTextures, texture: &textures::Texture, sampler: vk::Sampler,
+) -> Result<(u32, u32)>
+{
+    // The sampler first, so a full sampler array doesn't leave the texture's slot filled but unused
+    let sampler_slot = bindless.add_sampler(sampler)?;
+    let texture_slot = bindless.add_texture(texture.view())?;
+    Ok((texture_slot, sampler_slot))
+}
@@ -0,0 +1,532 @@
+use crate::graphics::errors::{IOResultToResultExt, VkAppError};
+use crate::graphics::vk_app::Result;
+use crate::graphics::{device, presentation};
+use ash::{khr, vk};
+use serde_json::{json, Map, Value};
+use std::fmt::Write;
+
+pub const USAGE: &str = "Usage: VkProjectRust gpu-report [--json] [--output <path>]";
+
+/// Creates a JSON object from the named fields of a Vulkan struct, using the field names as keys
+macro_rules! fields {
+    ($vk_struct: expr, $($field: ident),* $(,)?) => {{
+        let mut map = Map::new();
+        $(map.insert(String::from(stringify!($field)), json!($vk_struct.$field));)*
+        map
+    }};
+}
+
+/// Like fields! but for VkBool32 fields, which are reported as true or false rather than 1 or 0
+macro_rules! bool_fields {
+    ($vk_struct: expr, $($field: ident),* $(,)?) => {{
+        let mut map = Map::new();
+        $(map.insert(String::from(stringify!($field)), json!($vk_struct.$field == vk::TRUE));)*
+        map
+    }};
+}
+
+/// Like fields! but for Vulkan flags, which are reported by their names
+macro_rules! flag_fields {
+    ($map: expr, $vk_struct: expr, $($field: ident),* $(,)?) => {{
+        $($map.insert(String::from(stringify!($field)), json!(format!("{:?}", $vk_struct.$field)));)*
+    }};
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum ReportFormat
+{
+    Text,
+    Json,
+}
+
+/// Options for the gpu-report command
+pub struct ReportOptions
+{
+    pub format:      ReportFormat,
+    /// Print to the console if None
+    pub output_path: Option<String>,
+}
+
+impl ReportOptions
+{
+    /// Parses the arguments following the gpu-report command, returns an error message describing any invalid argument
+    pub fn from_args(mut args: impl Iterator<Item = String>) -> std::result::Result<Self, String>
+    {
+        let mut options = ReportOptions { format: ReportFormat::Text, output_path: None };
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--json" => options.format = ReportFormat::Json,
+                "--output" => match args.next() {
+                    Some(path) => options.output_path = Some(path),
+                    None => return Err(format!("--output requires a path\n{}", USAGE)),
+                },
+                _ => return Err(format!("Unknown argument {}\n{}", arg, USAGE)),
+            }
+        }
+        Ok(options)
+    }
+}
+
+/// Describes the Vulkan loader, instance layers and extensions, and every physical device
+///
+/// A surface is created for the window when possible so surface formats and present modes can be reported, and each
+/// device is checked with the same checks used for device selection, reporting why it would be skipped
+pub fn create_report(
+    hwnd: &windows::Win32::Foundation::HWND, h_instance: &windows::Win32::Foundation::HINSTANCE,
+) -> Result<Value>
+{
+    let entry = device::create_entry()?;
+    let loader_version = unsafe { entry.try_enumerate_instance_version() }?.unwrap_or(vk::API_VERSION_1_0);
+    let api_version = device::get_instance_api_version(&entry)?;
+
+    let layers: Vec<Value> = unsafe { entry.enumerate_instance_layer_properties() }?
+        .iter()
+        .map(|layer| {
+            json!({
+                "name": c_str_to_string(layer.layer_name_as_c_str()),
+                "description": c_str_to_string(layer.description_as_c_str()),
+                "spec_version": device::version_string(layer.spec_version),
+                "implementation_version": layer.implementation_version,
+            })
+        })
+        .collect();
+
+    let extensions = extensions_to_json(&unsafe { entry.enumerate_instance_extension_properties(None) }?);
+
+    let (instance, surface_support) = device::create_inspection_instance(&entry, api_version)?;
+
+    let surface = if surface_support {
+        match presentation::create_surface(&entry, &instance, hwnd, h_instance) {
+            Ok(surface) => Some(surface),
+            Err(err) => {
+                crate::warn!("Failed to create a surface, surface support will not be reported: {}", err);
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    let devices = unsafe { instance.enumerate_physical_devices() }
+        .map_err(VkAppError::from)
+        .and_then(|physical_devices| {
+            physical_devices
+                .into_iter()
+                .map(|physical_device| physical_device_to_json(&instance, api_version, physical_device, surface.as_ref()))
+                .collect::<Result<Vec<Value>>>()
+        });
+
+    unsafe {
+        if let Some((surface_loader, vk_surface)) = &surface {
+            surface_loader.destroy_surface(*vk_surface, None);
+        }
+        instance.destroy_instance(None);
+    }
+
+    Ok(json!({
+        "loader_version": device::version_string(loader_version),
+        "instance_version": device::version_string(api_version),
+        "surface_extensions": surface_support,
+        "layers": layers,
+        "extensions": extensions,
+        "devices": devices?,
+    }))
+}
+
+/// Writes the report to the output path or the console in the requested format
+pub fn write_report(report: &Value, options: &ReportOptions) -> Result<()>
+{
+    let output = match options.format {
+        ReportFormat::Json => serde_json::to_string_pretty(report).expect("A JSON value can always be serialised"),
+        ReportFormat::Text => {
+            let mut output = String::new();
+            write_text(&mut output, report, 0);
+            output
+        }
+    };
+
+    match &options.output_path {
+        Some(path) => std::fs::write(path, output).to_result(path),
+        None => {
+            println!("{}", output);
+            Ok(())
+        }
+    }
+}
+
+fn physical_device_to_json(
+    instance: &ash::Instance, api_version: u32, physical_device: vk::PhysicalDevice,
+    surface: Option<&(khr::surface::Instance, vk::SurfaceKHR)>,
+) -> Result<Value>
+{
+    let properties = unsafe { instance.get_physical_device_properties(physical_device) };
+    let features = unsafe { instance.get_physical_device_features(physical_device) };
+    let memory_properties = unsafe { instance.get_physical_device_memory_properties(physical_device) };
+    let queue_families = unsafe { instance.get_physical_device_queue_family_properties(physical_device) };
+    let extensions = unsafe { instance.enumerate_device_extension_properties(physical_device) }?;
+
+    let selection = match surface {
+        Some((surface_loader, vk_surface)) => {
+            match device::check_physical_device(instance, api_version, physical_device, surface_loader, *vk_surface)? {
+                Ok((supported_device, _)) => json!({
+                    "supported": true,
+                    "api_version": device::version_string(supported_device.api_version),
+                    "rendering_support": format!("{:?}", supported_device.rendering_support),
+                    "descriptor_indexing": format!("{:?}", supported_device.descriptor_indexing),
+                }),
+                Err(reason) => json!({ "supported": false, "reason": reason }),
+            }
+        }
+        None => json!({ "supported": false, "reason": "Not checked as no surface could be created" }),
+    };
+
+    let queue_families: Vec<Value> = queue_families
+        .iter()
+        .zip(0u32..)
+        .map(|(queue_family, index)| {
+            let mut queue_family_json = json!({
+                "index": index,
+                "flags": format!("{:?}", queue_family.queue_flags),
+                "queue_count": queue_family.queue_count,
+                "timestamp_valid_bits": queue_family.timestamp_valid_bits,
+                "min_image_transfer_granularity": [
+                    queue_family.min_image_transfer_granularity.width,
+                    queue_family.min_image_transfer_granularity.height,
+                    queue_family.min_image_transfer_granularity.depth,
+                ],
+            });
+            if let Some((surface_loader, vk_surface)) = surface {
+                let present_support =
+                    unsafe { surface_loader.get_physical_device_surface_support(physical_device, index, *vk_surface) }
+                        .unwrap_or(false);
+                queue_family_json["present_support"] = json!(present_support);
+            }
+            queue_family_json
+        })
+        .collect();
+
+    let memory_heaps: Vec<Value> = memory_properties
+        .memory_heaps_as_slice()
+        .iter()
+        .map(|heap| json!({ "size": heap.size, "flags": format!("{:?}", heap.flags) }))
+        .collect();
+
+    let memory_types: Vec<Value> = memory_properties
+        .memory_types_as_slice()
+        .iter()
+        .map(|memory_type| {
+            json!({
+                "heap_index": memory_type.heap_index,
+                "property_flags": format!("{:?}", memory_type.property_flags),
+            })
+        })
+        .collect();
+
+    let mut device_json = json!({
+        "name": device::get_device_name(&properties),
+        "selection": selection,
+        "device_type": format!("{:?}", properties.device_type),
+        "api_version": device::version_string(properties.api_version),
+        "driver_version": properties.driver_version,
+        "vendor_id": format!("{:#06x}", properties.vendor_id),
+        "device_id": format!("{:#06x}", properties.device_id),
+        "limits": limits_to_json(&properties.limits),
+        "features": features_to_json(&features),
+        "memory_heaps": memory_heaps,
+        "memory_types": memory_types,
+        "queue_families": queue_families,
+        "extensions": extensions_to_json(&extensions),
+    });
+
+    if let Some((surface_loader, vk_surface)) = surface {
+        device_json["surface"] = surface_to_json(surface_loader, *vk_surface, physical_device)?;
+    }
+
+    Ok(device_json)
+}
+
+fn surface_to_json(
+    surface_loader: &khr::surface::Instance, vk_surface: vk::SurfaceKHR, physical_device: vk::PhysicalDevice,
+) -> Result<Value>
+{
+    let (capabilities, formats, present_modes) = unsafe {
+        (
+            surface_loader.get_physical_device_surface_capabilities(physical_device, vk_surface)?,
+            surface_loader.get_physical_device_surface_formats(physical_device, vk_surface)?,
+            surface_loader.get_physical_device_surface_present_modes(physical_device, vk_surface)?,
+        )
+    };
+
+    let formats: Vec<Value> = formats
+        .iter()
+        .map(|format| json!(format!("{:?} {:?}", format.format, format.color_space)))
+        .collect();
+
+    let present_modes: Vec<Value> = present_modes
+        .iter()
+        .map(|present_mode| json!(format!("{:?}", present_mode)))
+        .collect();
+
+    Ok(json!({
+        "min_image_count": capabilities.min_image_count,
+        "max_image_count": capabilities.max_image_count,
+        "current_extent": [capabilities.current_extent.width, capabilities.current_extent.height],
+        "supported_usage_flags": format!("{:?}", capabilities.supported_usage_flags),
+        "formats": formats,
+        "present_modes": present_modes,
+    }))
+}
+
+fn extensions_to_json(extensions: &[vk::ExtensionProperties]) -> Vec<Value>
+{
+    extensions
+        .iter()
+        .map(|extension| {
+            json!(format!(
+                "{} (version {})",
+                c_str_to_string(extension.extension_name_as_c_str()),
+                extension.spec_version
+            ))
+        })
+        .collect()
+}
+
+fn limits_to_json(limits: &vk::PhysicalDeviceLimits) -> Value
+{
+    let mut map = fields!(
+        limits,
+        max_image_dimension1_d,
+        max_image_dimension2_d,
+        max_image_dimension3_d,
+        max_image_dimension_cube,
+        max_image_array_layers,
+        max_texel_buffer_elements,
+        max_uniform_buffer_range,
+        max_storage_buffer_range,
+        max_push_constants_size,
+        max_memory_allocation_count,
+        max_sampler_allocation_count,
+        buffer_image_granularity,
+        sparse_address_space_size,
+        max_bound_descriptor_sets,
+        max_per_stage_descriptor_samplers,
+        max_per_stage_descriptor_uniform_buffers,
+        max_per_stage_descriptor_storage_buffers,
+        max_per_stage_descriptor_sampled_images,
+        max_per_stage_descriptor_storage_images,
+        max_per_stage_descriptor_input_attachments,
+        max_per_stage_resources,
+        max_descriptor_set_samplers,
+        max_descriptor_set_uniform_buffers,
+        max_descriptor_set_uniform_buffers_dynamic,
+        max_descriptor_set_storage_buffers,
+        max_descriptor_set_storage_buffers_dynamic,
+        max_descriptor_set_sampled_images,
+        max_descriptor_set_storage_images,
+        max_descriptor_set_input_attachments,
+        max_vertex_input_attributes,
+        max_vertex_input_bindings,
+        max_vertex_input_attribute_offset,
+        max_vertex_input_binding_stride,
+        max_vertex_output_components,
+        max_tessellation_generation_level,
+        max_tessellation_patch_size,
+        max_tessellation_control_per_vertex_input_components,
+        max_tessellation_control_per_vertex_output_components,
+        max_tessellation_control_per_patch_output_components,
+        max_tessellation_control_total_output_components,
+        max_tessellation_evaluation_input_components,
+        max_tessellation_evaluation_output_components,
+        max_geometry_shader_invocations,
+        max_geometry_input_components,
+        max_geometry_output_components,
+        max_geometry_output_vertices,
+        max_geometry_total_output_components,
+        max_fragment_input_components,
+        max_fragment_output_attachments,
+        max_fragment_dual_src_attachments,
+        max_fragment_combined_output_resources,
+        max_compute_shared_memory_size,
+        max_compute_work_group_count,
+        max_compute_work_group_invocations,
+        max_compute_work_group_size,
+        sub_pixel_precision_bits,
+        sub_texel_precision_bits,
+        mipmap_precision_bits,
+        max_draw_indexed_index_value,
+        max_draw_indirect_count,
+        max_sampler_lod_bias,
+        max_sampler_anisotropy,
+        max_viewports,
+        max_viewport_dimensions,
+        viewport_bounds_range,
+        viewport_sub_pixel_bits,
+        min_memory_map_alignment,
+        min_texel_buffer_offset_alignment,
+        min_uniform_buffer_offset_alignment,
+        min_storage_buffer_offset_alignment,
+        min_texel_offset,
+        max_texel_offset,
+        min_texel_gather_offset,
+        max_texel_gather_offset,
+        min_interpolation_offset,
+        max_interpolation_offset,
+        sub_pixel_interpolation_offset_bits,
+        max_framebuffer_width,
+        max_framebuffer_height,
+        max_framebuffer_layers,
+        max_color_attachments,
+        max_sample_mask_words,
+        timestamp_period,
+        max_clip_distances,
+        max_cull_distances,
+        max_combined_clip_and_cull_distances,
+        discrete_queue_priorities,
+        point_size_range,
+        line_width_range,
+        point_size_granularity,
+        line_width_granularity,
+        optimal_buffer_copy_offset_alignment,
+        optimal_buffer_copy_row_pitch_alignment,
+        non_coherent_atom_size,
+    );
+    map.extend(bool_fields!(
+        limits,
+        timestamp_compute_and_graphics,
+        strict_lines,
+        standard_sample_locations,
+    ));
+    flag_fields!(
+        map,
+        limits,
+        framebuffer_color_sample_counts,
+        framebuffer_depth_sample_counts,
+        framebuffer_stencil_sample_counts,
+        framebuffer_no_attachments_sample_counts,
+        sampled_image_color_sample_counts,
+        sampled_image_integer_sample_counts,
+        sampled_image_depth_sample_counts,
+        sampled_image_stencil_sample_counts,
+        storage_image_sample_counts,
+    );
+    Value::Object(map)
+}
+
+fn features_to_json(features: &vk::PhysicalDeviceFeatures) -> Value
+{
+    Value::Object(bool_fields!(
+        features,
+        robust_buffer_access,
+        full_draw_index_uint32,
+        image_cube_array,
+        independent_blend,
+        geometry_shader,
+        tessellation_shader,
+        sample_rate_shading,
+        dual_src_blend,
+        logic_op,
+        multi_draw_indirect,
+        draw_indirect_first_instance,
+        depth_clamp,
+        depth_bias_clamp,
+        fill_mode_non_solid,
+        depth_bounds,
+        wide_lines,
+        large_points,
+        alpha_to_one,
+        multi_viewport,
+        sampler_anisotropy,
+        texture_compression_etc2,
+        texture_compression_astc_ldr,
+        texture_compression_bc,
+        occlusion_query_precise,
+        pipeline_statistics_query,
+        vertex_pipeline_stores_and_atomics,
+        fragment_stores_and_atomics,
+        shader_tessellation_and_geometry_point_size,
+        shader_image_gather_extended,
+        shader_storage_image_extended_formats,
+        shader_storage_image_multisample,
+        shader_storage_image_read_without_format,
+        shader_storage_image_write_without_format,
+        shader_uniform_buffer_array_dynamic_indexing,
+        shader_sampled_image_array_dynamic_indexing,
+        shader_storage_buffer_array_dynamic_indexing,
+        shader_storage_image_array_dynamic_indexing,
+        shader_clip_distance,
+        shader_cull_distance,
+        shader_float64,
+        shader_int64,
+        shader_int16,
+        shader_resource_residency,
+        shader_resource_min_lod,
+        sparse_binding,
+        sparse_residency_buffer,
+        sparse_residency_image2_d,
+        sparse_residency_image3_d,
+        sparse_residency2_samples,
+        sparse_residency4_samples,
+        sparse_residency8_samples,
+        sparse_residency16_samples,
+        sparse_residency_aliased,
+        variable_multisample_rate,
+        inherited_queries,
+    ))
+}
+
+fn c_str_to_string(c_str: std::result::Result<&std::ffi::CStr, std::ffi::FromBytesUntilNulError>) -> String
+{
+    c_str.map_or_else(
+        |_| String::from("Invalid string"),
+        |c_str| c_str.to_string_lossy().into_owned(),
+    )
+}
+
+/// Writes the report as indented "key: value" lines, arrays of values are written on one line
+fn write_text(output: &mut String, value: &Value, indent: usize)
+{
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map {
+                if is_single_line(value) {
+                    let _ = writeln!(output, "{:indent$}{}: {}", "", key, text_value(value));
+                } else {
+                    let _ = writeln!(output, "{:indent$}{}:", "", key);
+                    write_text(output, value, indent + 2);
+                }
+            }
+        }
+        Value::Array(values) => {
+            for (index, value) in values.iter().enumerate() {
+                if is_single_line(value) {
+                    let _ = writeln!(output, "{:indent$}- {}", "", text_value(value));
+                } else {
+                    let _ = writeln!(output, "{:indent$}[{}]", "", index);
+                    write_text(output, value, indent + 2);
+                }
+            }
+        }
+        value => {
+            let _ = writeln!(output, "{:indent$}{}", "", text_value(value));
+        }
+    }
+}
+
+/// Scalars and short arrays of numbers are written on one line
+fn is_single_line(value: &Value) -> bool
+{
+    match value {
+        Value::Object(_) => false,
+        Value::Array(values) => values.len() <= 3 && values.iter().all(Value::is_number),
+        _ => true,
+    }
+}
+
+fn text_value(value: &Value) -> String
+{
+    match value {
+        Value::String(string) => string.clone(),
+        Value::Array(values) => values.iter().map(text_value).collect::<Vec<String>>().join(", "),
+        value => value.to_string(),
+    }
+}
@@ -0,0 +1,189 @@
+use crate::graphics::debug::DebugUtils;
+use crate::graphics::device::{LogicalDevice, SupportedPhysicalDevice};
+use crate::graphics::errors::VkAppError;
+use crate::graphics::vk_app::Result;
+use ash::vk;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::rc::Rc;
+
+/// How a texture is sampled: filtering, addressing, anisotropy, depth comparison and the levels sampled
+///
+/// The default samples with linear filtering between texels and mip levels, repeats in every direction and uses the
+/// most anisotropy the device supports
+#[derive(Copy, Clone, Debug)]
+pub struct SamplerDesc
+{
+    pub mag_filter:     vk::Filter,
+    pub min_filter:     vk::Filter,
+    pub mipmap_mode:    vk::SamplerMipmapMode,
+    /// Addressing outside the image along U, V and W
+    pub address_modes:  [vk::SamplerAddressMode; 3],
+    /// Filter anisotropically with up to this many samples, clamped to the device's limit, or None to not
+    pub max_anisotropy: Option<f32>,
+    /// Compare texels with a reference value using this op rather than returning them, e.g. for shadow maps
+    pub compare_op:     Option<vk::CompareOp>,
+    /// The colour outside the image with CLAMP_TO_BORDER addressing
+    pub border_colour:  vk::BorderColor,
+    pub mip_lod_bias:   f32,
+    /// The range of levels sampled, a max_lod of vk::LOD_CLAMP_NONE samples every level however many there are
+    pub min_lod:        f32,
+    pub max_lod:        f32,
+}
+
+impl Default for SamplerDesc
+{
+    fn default() -> Self
+    {
+        Self {
+            mag_filter:     vk::Filter::LINEAR,
+            min_filter:     vk::Filter::LINEAR,
+            mipmap_mode:    vk::SamplerMipmapMode::LINEAR,
+            address_modes:  [vk::SamplerAddressMode::REPEAT; 3],
+            max_anisotropy: Some(f32::MAX),
+            compare_op:     None,
+            border_colour:  vk::BorderColor::INT_OPAQUE_BLACK,
+            mip_lod_bias:   0.0,
+            min_lod:        0.0,
+            max_lod:        vk::LOD_CLAMP_NONE,
+        }
+    }
+}
+
+/// The floats are compared by their bits so descriptions can be hashed, which only differs from == for NaN and -0.0
+type SamplerKey = (
+    [vk::Filter; 2],
+    vk::SamplerMipmapMode,
+    [vk::SamplerAddressMode; 3],
+    Option<u32>,
+    Option<vk::CompareOp>,
+    vk::BorderColor,
+    [u32; 3],
+);
+
+impl SamplerDesc
+{
+    /// Nearest filtering without blending between mip levels or anisotropy, so pixel art keeps its hard edges
+    pub fn nearest() -> Self
+    {
+        Self {
+            mag_filter: vk::Filter::NEAREST,
+            min_filter: vk::Filter::NEAREST,
+            mipmap_mode: vk::SamplerMipmapMode::NEAREST,
+            max_anisotropy: None,
+            ..Default::default()
+        }
+    }
+
+    fn key(&self) -> SamplerKey
+    {
+        (
+            [self.mag_filter, self.min_filter],
+            self.mipmap_mode,
+            self.address_modes,
+            self.max_anisotropy.map(f32::to_bits),
+            self.compare_op,
+            self.border_colour,
+            [self.mip_lod_bias.to_bits(), self.min_lod.to_bits(), self.max_lod.to_bits()],
+        )
+    }
+}
+
+impl PartialEq for SamplerDesc
+{
+    fn eq(&self, other: &Self) -> bool { self.key() == other.key() }
+}
+
+impl Eq for SamplerDesc {}
+
+impl Hash for SamplerDesc
+{
+    fn hash<H: Hasher>(&self, state: &mut H) { self.key().hash(state) }
+}
+
+/// Creates a sampler for each distinct description once and shares it between every texture sampled the same way
+///
+/// The samplers are destroyed when the cache is dropped, so the cache must outlive the descriptor sets using them
+pub struct SamplerCache
+{
+    device:                Rc<LogicalDevice>,
+    debug_utils:           DebugUtils,
+    /// The device's limit, which anisotropy is clamped to
+    device_max_anisotropy: f32,
+    samplers:              HashMap<SamplerDesc, vk::Sampler>,
+}
+
+impl Drop for SamplerCache
+{
+    fn drop(&mut self)
+    {
+        for (_, sampler) in self.samplers.drain() {
+            unsafe { self.device.destroy_sampler(sampler, None) };
+        }
+    }
+}
+
+impl SamplerCache
+{
+    pub fn new(
+        instance: &ash::Instance, physical_device: &SupportedPhysicalDevice, device: &Rc<LogicalDevice>,
+        debug_utils: &DebugUtils,
+    ) -> Self
+    {
+        let properties = unsafe { instance.get_physical_device_properties(physical_device.vk_physical_device) };
+
+        Self {
+            device:                device.clone(),
+            debug_utils:           debug_utils.clone(),
+            device_max_anisotropy: properties.limits.max_sampler_anisotropy,
+            samplers:              HashMap::new(),
+        }
+    }
+
+    /// Get the sampler for the description, creating it if no sampler with the same description has been requested
+    /// before
+    pub fn get_or_create(&mut self, desc: &SamplerDesc) -> Result<vk::Sampler>
+    {
+        if let Some(&sampler) = self.samplers.get(desc) {
+            return Ok(sampler);
+        }
+
+        if desc.min_lod > desc.max_lod {
+            return Err(VkAppError::DeviceError(format!(
+                "Sampler min LOD {} is greater than its max LOD {}",
+                desc.min_lod, desc.max_lod
+            )));
+        }
+
+        // Anisotropy of 1 is the same as none, and the device may support less than was asked for
+        let max_anisotropy = desc
+            .max_anisotropy
+            .map(|max_anisotropy| max_anisotropy.min(self.device_max_anisotropy))
+            .filter(|&max_anisotropy| max_anisotropy > 1.0);
+
+        let [address_mode_u, address_mode_v, address_mode_w] = desc.address_modes;
+        let sampler_create_info = vk::SamplerCreateInfo::default()
+            .mag_filter(desc.mag_filter)
+            .min_filter(desc.min_filter)
+            .mipmap_mode(desc.mipmap_mode)
+            .address_mode_u(address_mode_u)
+            .address_mode_v(address_mode_v)
+            .address_mode_w(address_mode_w)
+            .anisotropy_enable(max_anisotropy.is_some())
+            .max_anisotropy(max_anisotropy.unwrap_or(1.0))
+            .compare_enable(desc.compare_op.is_some())
+            .compare_op(desc.compare_op.unwrap_or(vk::CompareOp::ALWAYS))
+            .border_color(desc.border_colour)
+            .unnormalized_coordinates(false)
+            .mip_lod_bias(desc.mip_lod_bias)
+            .min_lod(desc.min_lod)
+            .max_lod(desc.max_lod);
+
+        let sampler = unsafe { self.device.create_sampler(&sampler_create_info, None)? };
+        self.debug_utils
+            .set_name(sampler, format!("Sampler {}", self.samplers.len()).as_str());
+
+        self.samplers.insert(*desc, sampler);
+        Ok(sampler)
+    }
+}
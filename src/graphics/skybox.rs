@@ -0,0 +1,115 @@
+use crate::graphics::buffers;
+use crate::graphics::debug::DebugUtils;
+use crate::graphics::descriptors::{self, DescriptorAllocator};
+use crate::graphics::device::LogicalDevice;
+use crate::graphics::pipeline::Pipeline;
+use crate::graphics::textures::Texture;
+use crate::graphics::vk_app::Result;
+use ash::vk;
+use std::rc::Rc;
+
+/// Matches the push constant block of skybox.vert
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct SkyboxPushConstants
+{
+    /// How far the camera sees sideways and upwards for each unit it looks forwards, at the edges of the screen
+    pub view_scale: [f32; 2],
+}
+
+impl SkyboxPushConstants
+{
+    /// The push constant ranges for the skybox pipeline
+    pub fn ranges() -> [vk::PushConstantRange; 1]
+    {
+        [vk::PushConstantRange::default()
+            .stage_flags(vk::ShaderStageFlags::VERTEX)
+            .offset(0)
+            .size(size_of::<Self>() as u32)]
+    }
+}
+
+/// A cubemap drawn behind everything else, in the direction the camera looks through each pixel
+///
+/// The skybox destroys its pipeline when dropped, the pipeline's descriptor set layout and the sampler are owned by the
+/// caches they came from
+pub struct Skybox
+{
+    device:                Rc<LogicalDevice>,
+    _cubemap:              Texture, // Only sampled through the descriptor set
+    pipeline:              Pipeline,
+    // Owns the skybox's set, which is written once as the cubemap never changes
+    _descriptor_allocator: DescriptorAllocator,
+    descriptor_set:        vk::DescriptorSet,
+    push_constants:        SkyboxPushConstants,
+}
+
+impl Drop for Skybox
+{
+    fn drop(&mut self) { self.pipeline.cleanup(&self.device); }
+}
+
+impl Skybox
+{
+    /// Draw the cubemap with a pipeline from pipeline::create_skybox_pipeline, sampled with the sampler
+    ///
+    /// The cubemap may still be uploading, it must not be drawn until the upload has completed
+    pub fn new(
+        device: &Rc<LogicalDevice>, debug_utils: &DebugUtils, pipeline: Pipeline, cubemap: Texture, sampler: vk::Sampler,
+    ) -> Result<Self>
+    {
+        debug_utils.set_name(pipeline.pipeline_layout, "Skybox pipeline layout");
+        debug_utils.set_name(pipeline.graphics_pipeline, "Skybox pipeline");
+
+        let mut descriptor_allocator = DescriptorAllocator::new(device, debug_utils, "skybox");
+        let descriptor_set = match descriptor_allocator.allocate(pipeline.descriptor_set_layout) {
+            Ok(descriptor_set) => descriptor_set,
+            Err(err) => {
+                pipeline.cleanup(device);
+                return Err(err);
+            }
+        };
+        descriptors::write_sampled_image(device, descriptor_set, 0, cubemap.view());
+        descriptors::write_sampler(device, descriptor_set, 1, sampler);
+
+        // The projection divides a point's sideways and upwards distances by its distance forwards, so the edges of the
+        // screen are where they are the tangent of half the field of view
+        let view_scale = libm::tanf(buffers::FIELD_OF_VIEW.to_radians() * 0.5);
+
+        Ok(Self {
+            device: device.clone(),
+            _cubemap: cubemap,
+            pipeline,
+            _descriptor_allocator: descriptor_allocator,
+            descriptor_set,
+            push_constants: SkyboxPushConstants { view_scale: [view_scale; 2] },
+        })
+    }
+
+    /// Record the skybox's draw, which covers the whole render area so must come before the draws in front of it
+    ///
+    /// The viewport and scissor must already be set
+    pub fn cmd_draw(&self, device: &ash::Device, command_buffer: vk::CommandBuffer) -> Result<()>
+    {
+        unsafe {
+            device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline.graphics_pipeline,
+            );
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline.pipeline_layout,
+                0,
+                &[self.descriptor_set],
+                &[],
+            );
+        }
+        self.pipeline
+            .cmd_push_constants(device, command_buffer, vk::ShaderStageFlags::VERTEX, 0, &self.push_constants)?;
+        // The vertex shader makes the triangle from the vertex indices
+        unsafe { device.cmd_draw(command_buffer, 3, 1, 0, 0) };
+        Ok(())
+    }
+}
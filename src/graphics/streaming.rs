@@ -0,0 +1,138 @@
+use crate::graphics::errors::IOResultToResultExt;
+use crate::graphics::samplers::SamplerDesc;
+use crate::graphics::textures::{ColourSpace, ImageLoaders, TextureData};
+use crate::graphics::vk_app::Result;
+use crate::log;
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// The most worker threads decoding at once, decoding is rarely the bottleneck beyond this
+const MAX_WORKERS: usize = 4;
+
+/// A texture file to decode in the background and the id it was requested with
+struct StreamRequest
+{
+    id:           u64,
+    path:         String,
+    colour_space: ColourSpace,
+    sampler:      SamplerDesc,
+}
+
+/// A decoded texture, or why it couldn't be decoded, with the id it was requested with
+pub struct StreamedTexture
+{
+    pub id:   u64,
+    pub path: String,
+    pub data: Result<TextureData>,
+}
+
+/// Decodes texture files on worker threads so loading them doesn't stall drawing
+///
+/// Textures are drawn with a placeholder until their file is decoded, see placeholder. Decoded textures are collected
+/// with poll and are then uploaded and swapped in by the renderer, see TextureManager. The workers finish the requests
+/// already made and stop when the streamer is dropped
+pub struct TextureStreamer
+{
+    /// None once dropped, which tells the workers to stop
+    requests: Option<mpsc::Sender<StreamRequest>>,
+    decoded:  mpsc::Receiver<StreamedTexture>,
+    workers:  Vec<thread::JoinHandle<()>>,
+}
+
+impl Drop for TextureStreamer
+{
+    fn drop(&mut self)
+    {
+        // The workers' receives fail once the sender is gone and the queue is empty
+        self.requests = None;
+        for worker in self.workers.drain(..) {
+            if worker.join().is_err() {
+                log!("A texture streaming worker panicked");
+            }
+        }
+    }
+}
+
+impl TextureStreamer
+{
+    /// Start a worker for each core but the one drawing, up to MAX_WORKERS, decoding with the loaders
+    pub fn new(image_loaders: ImageLoaders) -> Result<Self>
+    {
+        let worker_count = thread::available_parallelism()
+            .map_or(1, |cores| cores.get().saturating_sub(1))
+            .clamp(1, MAX_WORKERS);
+
+        let image_loaders = Arc::new(image_loaders);
+        let (request_sender, request_receiver) = mpsc::channel::<StreamRequest>();
+        // The workers take requests from one queue so whichever is free decodes the next file
+        let request_receiver = Arc::new(Mutex::new(request_receiver));
+        let (decoded_sender, decoded_receiver) = mpsc::channel();
+
+        let mut workers = Vec::with_capacity(worker_count);
+        for worker in 0..worker_count {
+            let image_loaders = image_loaders.clone();
+            let request_receiver = request_receiver.clone();
+            let decoded_sender = decoded_sender.clone();
+            let spawned = thread::Builder::new()
+                .name(format!("Texture streaming {}", worker))
+                .spawn(move || loop {
+                    // The lock is only held while waiting for a request, not while decoding it
+                    let request = match request_receiver.lock() {
+                        Ok(receiver) => receiver.recv(),
+                        Err(_) => return,
+                    };
+                    let Ok(request) = request else {
+                        return;
+                    };
+
+                    let data = image_loaders
+                        .load_as(&request.path, request.colour_space)
+                        .map(|data| TextureData { sampler: request.sampler, ..data });
+                    let streamed = StreamedTexture { id: request.id, path: request.path, data };
+                    // The streamer has been dropped if nobody is receiving, so nothing needs the texture
+                    if decoded_sender.send(streamed).is_err() {
+                        return;
+                    }
+                })
+                .to_result("texture streaming worker")?;
+            workers.push(spawned);
+        }
+        log!("Streaming textures with {} workers", worker_count);
+
+        Ok(Self {
+            requests: Some(request_sender),
+            decoded: decoded_receiver,
+            workers,
+        })
+    }
+
+    /// Queue a texture file to be decoded in the colour space, to be sampled as described
+    ///
+    /// The id is chosen by the caller to match the decoded texture to what it was requested for
+    pub fn request(&self, id: u64, path: &str, colour_space: ColourSpace, sampler: SamplerDesc)
+    {
+        let request = StreamRequest { id, path: path.to_string(), colour_space, sampler };
+        // The workers only stop once the sender is dropped, so they are still receiving
+        if let Some(requests) = &self.requests {
+            let _ = requests.send(request);
+        }
+    }
+
+    /// Every texture decoded since the last poll, without blocking
+    pub fn poll(&self) -> Vec<StreamedTexture> { self.decoded.try_iter().collect() }
+}
+
+/// A small grey checkerboard drawn until a texture's file has been decoded and uploaded, sampled as the texture will be
+pub fn placeholder(path: &str, sampler: SamplerDesc) -> TextureData
+{
+    TextureData {
+        sampler,
+        ..TextureData::checkerboard(
+            format!("{} placeholder", path).as_str(),
+            16,
+            4,
+            [[160, 160, 160, 255], [96, 96, 96, 255]],
+        )
+    }
+}
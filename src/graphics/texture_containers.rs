@@ -0,0 +1,410 @@
+use crate::graphics::block_compression;
+use crate::graphics::samplers::SamplerDesc;
+use crate::graphics::textures::{self, invalid_data, ImageLoader, TextureData, TextureKind};
+use crate::graphics::vk_app::Result;
+use ash::vk;
+
+const DDS_MAGIC: &[u8; 4] = b"DDS ";
+/// The magic number, the header and its pixel format
+const DDS_HEADER_SIZE: usize = 128;
+/// The extended header for formats that have no FourCC code
+const DDS_DX10_HEADER_SIZE: usize = 20;
+const DDPF_FOURCC: u32 = 0x4;
+const DDSCAPS2_CUBEMAP: u32 = 0x200;
+const DDSCAPS2_VOLUME: u32 = 0x200000;
+
+const KTX2_IDENTIFIER: [u8; 12] = [0xab, 0x4b, 0x54, 0x58, 0x20, 0x32, 0x30, 0xbb, 0x0d, 0x0a, 0x1a, 0x0a];
+/// The identifier, the header and the index, which the level index follows
+const KTX2_HEADER_SIZE: usize = 80;
+/// Offset, length and uncompressed length of each level
+const KTX2_LEVEL_INDEX_ENTRY_SIZE: usize = 24;
+
+/// Loads DDS files of BC1, BC3, BC5 or BC7 blocks, along with the mip levels stored in them
+///
+/// Legacy files identify their format with a FourCC code, which doesn't say whether the colours are sRGB, so DXT1 and
+/// DXT5 are taken to be sRGB colour textures, load data textures with ColourSpace::Linear. Files with a DX10 header name
+/// their format exactly. Cubemaps, volumes and arrays are not supported
+pub struct DdsLoader;
+
+impl ImageLoader for DdsLoader
+{
+    fn extensions(&self) -> &[&str] { &["dds"] }
+
+    fn recognises(&self, bytes: &[u8]) -> bool { bytes.starts_with(DDS_MAGIC) }
+
+    fn load(&self, path: &str, bytes: &[u8]) -> Result<TextureData> { load_dds(path, bytes) }
+}
+
+/// Loads KTX2 files of BC1, BC3, BC5, BC7 or RGBA8 texels, along with the mip levels stored in them
+///
+/// The format is the Vulkan format the file names. Files can hold a single image, an array or a cubemap, but not an
+/// array of cubemaps. Supercompressed files and volumes are not supported. RGBA8 files without mip levels have their
+/// mip chain generated when uploaded
+pub struct Ktx2Loader;
+
+impl ImageLoader for Ktx2Loader
+{
+    fn extensions(&self) -> &[&str] { &["ktx2"] }
+
+    fn recognises(&self, bytes: &[u8]) -> bool { bytes.starts_with(&KTX2_IDENTIFIER) }
+
+    fn load(&self, path: &str, bytes: &[u8]) -> Result<TextureData> { load_ktx2(path, bytes) }
+}
+
+fn load_dds(path: &str, bytes: &[u8]) -> Result<TextureData>
+{
+    if bytes.len() < DDS_HEADER_SIZE || !bytes.starts_with(DDS_MAGIC) {
+        return Err(invalid_data(path, "Not a DDS file"));
+    }
+
+    let height = read_u32(bytes, 12);
+    let width = read_u32(bytes, 16);
+    let level_count = read_u32(bytes, 28).max(1);
+    let pixel_format_flags = read_u32(bytes, 80);
+    let caps2 = read_u32(bytes, 112);
+
+    if caps2 & (DDSCAPS2_CUBEMAP | DDSCAPS2_VOLUME) != 0 {
+        return Err(invalid_data(path, "DDS cubemaps and volume textures are not supported"));
+    }
+    if pixel_format_flags & DDPF_FOURCC == 0 {
+        return Err(invalid_data(path, "Uncompressed DDS files are not supported"));
+    }
+
+    let (format, data_offset) = match &bytes[84..88] {
+        b"DXT1" => (vk::Format::BC1_RGBA_SRGB_BLOCK, DDS_HEADER_SIZE),
+        b"DXT5" => (vk::Format::BC3_SRGB_BLOCK, DDS_HEADER_SIZE),
+        b"ATI2" | b"BC5U" => (vk::Format::BC5_UNORM_BLOCK, DDS_HEADER_SIZE),
+        b"DX10" => {
+            if bytes.len() < DDS_HEADER_SIZE + DDS_DX10_HEADER_SIZE {
+                return Err(invalid_data(path, "DDS file ends in its DX10 header"));
+            }
+            if read_u32(bytes, DDS_HEADER_SIZE + 12) > 1 {
+                return Err(invalid_data(path, "DDS texture arrays are not supported"));
+            }
+            let format = match read_u32(bytes, DDS_HEADER_SIZE) {
+                71 => vk::Format::BC1_RGBA_UNORM_BLOCK,
+                72 => vk::Format::BC1_RGBA_SRGB_BLOCK,
+                77 => vk::Format::BC3_UNORM_BLOCK,
+                78 => vk::Format::BC3_SRGB_BLOCK,
+                83 => vk::Format::BC5_UNORM_BLOCK,
+                98 => vk::Format::BC7_UNORM_BLOCK,
+                99 => vk::Format::BC7_SRGB_BLOCK,
+                dxgi_format => return Err(invalid_data(path, format!("DXGI format {} is not supported", dxgi_format))),
+            };
+            (format, DDS_HEADER_SIZE + DDS_DX10_HEADER_SIZE)
+        }
+        four_cc => {
+            return Err(invalid_data(
+                path,
+                format!("DDS format {} is not supported", String::from_utf8_lossy(four_cc)),
+            ))
+        }
+    };
+
+    check_extent(path, width, height, level_count)?;
+
+    // The levels follow the header one after another, largest first
+    let mut levels = Vec::new();
+    let mut offset = data_offset;
+    for level in 0..level_count {
+        let (level_width, level_height) = textures::mip_extent(width, height, level);
+        // The size is checked against the file before it is used, as a malformed extent could overflow it
+        let level_bytes = block_compression::level_size(format, level_width, level_height)
+            .and_then(|size| bytes.get(offset..offset.checked_add(size)?))
+            .ok_or_else(|| invalid_data(path, "DDS file ends before its last level"))?;
+        offset += level_bytes.len();
+        levels.push(level_bytes.to_vec());
+    }
+
+    Ok(texture_data(path, TextureKind::Single, format, width, height, levels))
+}
+
+fn load_ktx2(path: &str, bytes: &[u8]) -> Result<TextureData>
+{
+    if bytes.len() < KTX2_HEADER_SIZE || !bytes.starts_with(&KTX2_IDENTIFIER) {
+        return Err(invalid_data(path, "Not a KTX2 file"));
+    }
+
+    let format = vk::Format::from_raw(read_u32(bytes, 12) as i32);
+    let width = read_u32(bytes, 20);
+    let height = read_u32(bytes, 24);
+    let depth = read_u32(bytes, 28);
+    let layer_count = read_u32(bytes, 32);
+    let face_count = read_u32(bytes, 36);
+    // 0 asks the loader to generate the mip chain, which we do anyway for uncompressed textures with one level
+    let level_count = read_u32(bytes, 40).max(1);
+    let supercompression_scheme = read_u32(bytes, 44);
+
+    if block_compression::block_size(format).is_none()
+        && format != vk::Format::R8G8B8A8_UNORM
+        && format != vk::Format::R8G8B8A8_SRGB
+    {
+        return Err(invalid_data(path, format!("KTX2 format {:?} is not supported", format)));
+    }
+    if supercompression_scheme != 0 {
+        return Err(invalid_data(path, "Supercompressed KTX2 files are not supported"));
+    }
+    if depth > 0 {
+        return Err(invalid_data(path, "KTX2 volume textures are not supported"));
+    }
+    // A layer count of 0 means the texture isn't an array, rather than an array of one layer
+    let kind = match (layer_count, face_count) {
+        (0, 1) => TextureKind::Single,
+        (_, 1) => TextureKind::Array(layer_count),
+        (0, 6) => TextureKind::Cube,
+        (_, 6) => return Err(invalid_data(path, "KTX2 arrays of cubemaps are not supported")),
+        _ => return Err(invalid_data(path, format!("KTX2 face count {} is invalid", face_count))),
+    };
+    if kind == TextureKind::Cube && width != height {
+        return Err(invalid_data(path, "KTX2 cubemap faces are not square"));
+    }
+
+    check_extent(path, width, height, level_count)?;
+
+    // Each level's position is given by the level index, largest level first
+    let mut levels = Vec::new();
+    for level in 0..level_count {
+        let entry = KTX2_HEADER_SIZE + level as usize * KTX2_LEVEL_INDEX_ENTRY_SIZE;
+        let (offset, length) = match (read_u64(bytes, entry), read_u64(bytes, entry + 8)) {
+            (Some(offset), Some(length)) => (offset as usize, length as usize),
+            _ => return Err(invalid_data(path, "KTX2 file ends in its level index")),
+        };
+
+        // Each level holds every layer, or every face, one after another
+        let (level_width, level_height) = textures::mip_extent(width, height, level);
+        // A malformed extent or layer count could overflow the size, or make it larger than the file
+        let expected_length = block_compression::level_size(format, level_width, level_height)
+            .and_then(|size| size.checked_mul(kind.layer_count() as usize))
+            .filter(|&size| size <= bytes.len())
+            .ok_or_else(|| invalid_data(path, format!("KTX2 level {} is larger than the file", level)))?;
+        if length != expected_length {
+            return Err(invalid_data(path, format!("KTX2 level {} has the wrong size", level)));
+        }
+        let level_bytes = bytes
+            .get(offset..offset.saturating_add(length))
+            .ok_or_else(|| invalid_data(path, format!("KTX2 level {} is outside the file", level)))?;
+        levels.push(level_bytes.to_vec());
+    }
+
+    Ok(texture_data(path, kind, format, width, height, levels))
+}
+
+/// Check the texture has texels and no more levels than a full mip chain, before its levels are read
+fn check_extent(path: &str, width: u32, height: u32, level_count: u32) -> Result<()>
+{
+    if width == 0 || height == 0 {
+        return Err(invalid_data(path, "Texture has no texels"));
+    }
+    if level_count > textures::mip_level_count(width, height) {
+        return Err(invalid_data(path, "Texture has more mip levels than its size allows"));
+    }
+    Ok(())
+}
+
+/// Split the levels into the base level and the levels below it
+fn texture_data(
+    path: &str, kind: TextureKind, format: vk::Format, width: u32, height: u32, mut levels: Vec<Vec<u8>>,
+) -> TextureData
+{
+    let pixels = levels.remove(0);
+    TextureData {
+        path: path.to_string(),
+        width,
+        height,
+        kind,
+        format,
+        pixels,
+        mip_pixels: levels,
+        sampler: SamplerDesc::default(),
+    }
+}
+
+/// Headers are checked to be long enough before being read
+fn read_u32(bytes: &[u8], offset: usize) -> u32
+{
+    u32::from_le_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]])
+}
+
+/// The level index's length depends on the level count so it is checked as it is read
+fn read_u64(bytes: &[u8], offset: usize) -> Option<u64>
+{
+    Some(u64::from_le_bytes(bytes.get(offset..offset + 8)?.try_into().ok()?))
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn write_u32(bytes: &mut [u8], offset: usize, value: u32)
+    {
+        bytes[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    /// A DDS file of the FourCC format followed by level_bytes of blocks
+    fn dds(four_cc: &[u8; 4], width: u32, height: u32, level_count: u32, level_bytes: usize) -> Vec<u8>
+    {
+        let mut bytes = vec![0; DDS_HEADER_SIZE];
+        bytes[..4].copy_from_slice(DDS_MAGIC);
+        write_u32(&mut bytes, 12, height);
+        write_u32(&mut bytes, 16, width);
+        write_u32(&mut bytes, 28, level_count);
+        write_u32(&mut bytes, 80, DDPF_FOURCC);
+        bytes[84..88].copy_from_slice(four_cc);
+        bytes.resize(DDS_HEADER_SIZE + level_bytes, 0);
+        bytes
+    }
+
+    /// A DDS file with a DX10 header naming the DXGI format and array size, followed by level_bytes of blocks
+    fn dds_dx10(dxgi_format: u32, array_size: u32, width: u32, height: u32, level_bytes: usize) -> Vec<u8>
+    {
+        let mut bytes = dds(b"DX10", width, height, 1, DDS_DX10_HEADER_SIZE + level_bytes);
+        write_u32(&mut bytes, DDS_HEADER_SIZE, dxgi_format);
+        write_u32(&mut bytes, DDS_HEADER_SIZE + 12, array_size);
+        bytes
+    }
+
+    #[test]
+    fn dds_files_load_every_level()
+    {
+        // 4x4, 2x2 and 1x1 levels of one BC1 block each
+        let texture = load_dds("test.dds", &dds(b"DXT1", 4, 4, 3, 3 * 8)).unwrap();
+        assert_eq!(texture.format, vk::Format::BC1_RGBA_SRGB_BLOCK);
+        assert_eq!((texture.width, texture.height, texture.kind), (4, 4, TextureKind::Single));
+        assert_eq!(texture.pixels.len(), 8);
+        assert_eq!(texture.mip_pixels, vec![vec![0; 8], vec![0; 8]]);
+
+        let texture = load_dds("test.dds", &dds_dx10(98, 1, 8, 4, 2 * 16)).unwrap();
+        assert_eq!(texture.format, vk::Format::BC7_UNORM_BLOCK);
+        assert_eq!(texture.pixels.len(), 2 * 16);
+    }
+
+    #[test]
+    fn truncated_dds_files_are_rejected()
+    {
+        assert!(load_dds("test.dds", &dds(b"DXT1", 4, 4, 3, 3 * 8 - 1)).is_err());
+        assert!(load_dds("test.dds", &dds(b"DXT5", 8, 8, 1, 0)).is_err());
+        assert!(load_dds("test.dds", &dds(b"DXT1", 4, 4, 1, 8)[..DDS_HEADER_SIZE - 1]).is_err());
+        // Ends in the DX10 header
+        assert!(load_dds("test.dds", &dds(b"DX10", 4, 4, 1, DDS_DX10_HEADER_SIZE - 1)).is_err());
+    }
+
+    #[test]
+    fn mismatched_dds_headers_are_rejected()
+    {
+        let mut not_dds = dds(b"DXT1", 4, 4, 1, 8);
+        not_dds[..4].copy_from_slice(b"PNG ");
+        assert!(load_dds("test.dds", &not_dds).is_err());
+
+        // More levels than a 4x4 texture has
+        assert!(load_dds("test.dds", &dds(b"DXT1", 4, 4, 4, 4 * 8)).is_err());
+        assert!(load_dds("test.dds", &dds(b"DXT1", 0, 4, 1, 8)).is_err());
+        assert!(load_dds("test.dds", &dds(b"DXT3", 4, 4, 1, 16)).is_err());
+
+        let mut cubemap = dds(b"DXT1", 4, 4, 1, 6 * 8);
+        write_u32(&mut cubemap, 112, DDSCAPS2_CUBEMAP);
+        assert!(load_dds("test.dds", &cubemap).is_err());
+
+        let mut uncompressed = dds(b"DXT1", 4, 4, 1, 4 * 4 * 4);
+        write_u32(&mut uncompressed, 80, 0);
+        assert!(load_dds("test.dds", &uncompressed).is_err());
+
+        assert!(load_dds("test.dds", &dds_dx10(98, 2, 4, 4, 2 * 16)).is_err());
+        assert!(load_dds("test.dds", &dds_dx10(28, 1, 4, 4, 4 * 4 * 4)).is_err());
+    }
+
+    /// A KTX2 file with a level index of the levels' lengths, each level following the last after the index
+    fn ktx2(format: vk::Format, width: u32, height: u32, layer_count: u32, face_count: u32, levels: &[usize]) -> Vec<u8>
+    {
+        let mut bytes = vec![0; KTX2_HEADER_SIZE + levels.len() * KTX2_LEVEL_INDEX_ENTRY_SIZE];
+        bytes[..12].copy_from_slice(&KTX2_IDENTIFIER);
+        write_u32(&mut bytes, 12, format.as_raw() as u32);
+        write_u32(&mut bytes, 20, width);
+        write_u32(&mut bytes, 24, height);
+        write_u32(&mut bytes, 32, layer_count);
+        write_u32(&mut bytes, 36, face_count);
+        write_u32(&mut bytes, 40, levels.len() as u32);
+
+        for (level, &length) in levels.iter().enumerate() {
+            let entry = KTX2_HEADER_SIZE + level * KTX2_LEVEL_INDEX_ENTRY_SIZE;
+            let offset = bytes.len() as u64;
+            bytes[entry..entry + 8].copy_from_slice(&offset.to_le_bytes());
+            bytes[entry + 8..entry + 16].copy_from_slice(&(length as u64).to_le_bytes());
+            bytes[entry + 16..entry + 24].copy_from_slice(&(length as u64).to_le_bytes());
+            bytes.resize(bytes.len() + length, level as u8);
+        }
+        bytes
+    }
+
+    #[test]
+    fn ktx2_files_load_every_level()
+    {
+        let texture = load_ktx2("test.ktx2", &ktx2(vk::Format::BC7_SRGB_BLOCK, 8, 8, 0, 1, &[4 * 16, 16])).unwrap();
+        assert_eq!(texture.format, vk::Format::BC7_SRGB_BLOCK);
+        assert_eq!(texture.kind, TextureKind::Single);
+        assert_eq!(texture.pixels, vec![0; 4 * 16]);
+        assert_eq!(texture.mip_pixels, vec![vec![1; 16]]);
+    }
+
+    #[test]
+    fn ktx2_arrays_and_cubemaps_hold_every_layer_in_each_level()
+    {
+        let array = load_ktx2("test.ktx2", &ktx2(vk::Format::R8G8B8A8_UNORM, 2, 2, 3, 1, &[3 * 16])).unwrap();
+        assert_eq!(array.kind, TextureKind::Array(3));
+        assert_eq!(array.pixels.len(), 3 * 16);
+
+        let cube = load_ktx2("test.ktx2", &ktx2(vk::Format::BC1_RGBA_UNORM_BLOCK, 4, 4, 0, 6, &[6 * 8])).unwrap();
+        assert_eq!(cube.kind, TextureKind::Cube);
+        assert_eq!(cube.pixels.len(), 6 * 8);
+    }
+
+    #[test]
+    fn truncated_ktx2_files_are_rejected()
+    {
+        let file = ktx2(vk::Format::BC7_UNORM_BLOCK, 8, 8, 0, 1, &[4 * 16, 16]);
+        assert!(load_ktx2("test.ktx2", &file[..file.len() - 1]).is_err());
+        // Ends in the level index
+        assert!(load_ktx2("test.ktx2", &file[..KTX2_HEADER_SIZE + KTX2_LEVEL_INDEX_ENTRY_SIZE + 4]).is_err());
+        assert!(load_ktx2("test.ktx2", &file[..KTX2_HEADER_SIZE - 1]).is_err());
+    }
+
+    #[test]
+    fn mismatched_ktx2_headers_are_rejected()
+    {
+        let mut not_ktx2 = ktx2(vk::Format::BC7_UNORM_BLOCK, 4, 4, 0, 1, &[16]);
+        not_ktx2[5] = b'1';
+        assert!(load_ktx2("test.ktx2", &not_ktx2).is_err());
+
+        // Levels of the wrong size for their extent
+        assert!(load_ktx2("test.ktx2", &ktx2(vk::Format::BC7_UNORM_BLOCK, 8, 8, 0, 1, &[16])).is_err());
+        assert!(load_ktx2("test.ktx2", &ktx2(vk::Format::BC1_RGBA_UNORM_BLOCK, 4, 4, 0, 6, &[8])).is_err());
+
+        assert!(load_ktx2("test.ktx2", &ktx2(vk::Format::R16G16B16A16_SFLOAT, 4, 4, 0, 1, &[128])).is_err());
+        assert!(load_ktx2("test.ktx2", &ktx2(vk::Format::BC7_UNORM_BLOCK, 4, 4, 0, 3, &[3 * 16])).is_err());
+        assert!(load_ktx2("test.ktx2", &ktx2(vk::Format::BC7_UNORM_BLOCK, 8, 4, 0, 6, &[6 * 32])).is_err());
+        assert!(load_ktx2("test.ktx2", &ktx2(vk::Format::BC7_UNORM_BLOCK, 4, 4, 2, 6, &[12 * 16])).is_err());
+        assert!(load_ktx2("test.ktx2", &ktx2(vk::Format::BC7_UNORM_BLOCK, 4, 4, 0, 1, &[16, 16, 16, 16])).is_err());
+
+        let mut supercompressed = ktx2(vk::Format::BC7_UNORM_BLOCK, 4, 4, 0, 1, &[16]);
+        write_u32(&mut supercompressed, 44, 1);
+        assert!(load_ktx2("test.ktx2", &supercompressed).is_err());
+
+        let mut volume = ktx2(vk::Format::BC7_UNORM_BLOCK, 4, 4, 0, 1, &[16]);
+        write_u32(&mut volume, 28, 4);
+        assert!(load_ktx2("test.ktx2", &volume).is_err());
+    }
+
+    #[test]
+    fn extents_too_large_for_the_file_are_rejected_without_overflowing()
+    {
+        assert!(load_dds("test.dds", &dds_dx10(98, 1, u32::MAX, u32::MAX, 16)).is_err());
+        assert!(load_dds("test.dds", &dds(b"DXT1", u32::MAX, u32::MAX, 1, 8)).is_err());
+
+        let huge = ktx2(vk::Format::BC1_RGBA_UNORM_BLOCK, u32::MAX, u32::MAX, 0, 1, &[8]);
+        assert!(load_ktx2("test.ktx2", &huge).is_err());
+        let many_layers = ktx2(vk::Format::BC1_RGBA_UNORM_BLOCK, u32::MAX, u32::MAX, 2, 1, &[16]);
+        assert!(load_ktx2("test.ktx2", &many_layers).is_err());
+        let many_layers = ktx2(vk::Format::R8G8B8A8_UNORM, 4, 4, u32::MAX, 1, &[64]);
+        assert!(load_ktx2("test.ktx2", &many_layers).is_err());
+    }
+}
@@ -0,0 +1,209 @@
+use crate::graphics::errors::IOResultToResultExt;
+use crate::graphics::renderer::{self, Renderer};
+use crate::graphics::samplers::SamplerDesc;
+use crate::graphics::streaming::{self, TextureStreamer};
+use crate::graphics::textures::{ColourSpace, ImageLoaders, TextureData};
+use crate::graphics::vk_app::Result;
+use crate::{log, warn};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::rc::{Rc, Weak};
+
+/// How a texture file is loaded, loading the same file the same way shares one texture
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct TextureKey
+{
+    /// Canonical so different paths to the same file share a texture
+    path:         PathBuf,
+    colour_space: ColourSpace,
+    sampler:      SamplerDesc,
+}
+
+/// A counted reference to a texture held by a TextureManager, the texture is released once every handle to it has been
+/// dropped
+#[derive(Clone, Debug)]
+pub struct TextureHandle(Rc<usize>);
+
+impl TextureHandle
+{
+    /// The renderer's index for the texture, which objects draw it with
+    pub fn index(&self) -> usize { *self.0 }
+}
+
+/// A texture held by the manager, its data is a placeholder until its file has been decoded
+struct ManagedTexture
+{
+    data:      TextureData,
+    /// None for textures that aren't loaded from a file, which aren't shared
+    key:       Option<TextureKey>,
+    handle:    Weak<usize>,
+    /// The id the file was requested from the streamer with, until it has been decoded
+    stream_id: Option<u64>,
+}
+
+/// Loads textures once however many times they are asked for, handing out counted handles to them
+///
+/// Files loaded the same way are decoded and uploaded once and share their handles. Files are streamed, so are drawn with
+/// a placeholder until they have been decoded. Textures whose handles have all been dropped are released by the next
+/// update or load, the renderer frees them once no frame in flight can be drawing them and their indices are reused. The
+/// manager keeps the CPU copy of every texture, so the renderer can be rebuilt from it when the device is lost
+pub struct TextureManager
+{
+    streamer:       TextureStreamer,
+    /// Indexed from renderer::FIRST_LOADED_TEXTURE, None where a texture has been released
+    textures:       Vec<Option<ManagedTexture>>,
+    /// The index into textures of each texture loaded from a file
+    by_key:         HashMap<TextureKey, usize>,
+    next_stream_id: u64,
+}
+
+impl TextureManager
+{
+    pub fn new(image_loaders: ImageLoaders) -> Result<Self>
+    {
+        Ok(Self {
+            streamer:       TextureStreamer::new(image_loaders)?,
+            textures:       Vec::new(),
+            by_key:         HashMap::new(),
+            next_stream_id: 0,
+        })
+    }
+
+    /// A handle to the texture loaded from a file in the colour space and sampled as described, loading it if it isn't
+    /// already loaded that way
+    ///
+    /// The renderer is None until it is created, it is then created from the textures added so far
+    pub fn load(
+        &mut self, mut renderer: Option<&mut Renderer>, path: &str, colour_space: ColourSpace, sampler: SamplerDesc,
+    ) -> Result<TextureHandle>
+    {
+        let key = TextureKey {
+            path: std::fs::canonicalize(path).to_result(path)?,
+            colour_space,
+            sampler,
+        };
+        if let Some(&slot) = self.by_key.get(&key) {
+            if let Some(handle) = self.textures[slot].as_ref().and_then(|texture| texture.handle.upgrade()) {
+                return Ok(TextureHandle(handle));
+            }
+        }
+        // A texture loaded the same way whose handles have all been dropped is released first, to be loaded again
+        self.release_unused(renderer.as_deref_mut())?;
+
+        let stream_id = self.next_stream_id;
+        self.next_stream_id += 1;
+        self.streamer.request(stream_id, path, colour_space, sampler);
+
+        let (slot, handle) = self.add(
+            renderer,
+            ManagedTexture {
+                data:      streaming::placeholder(path, sampler),
+                key:       Some(key.clone()),
+                handle:    Weak::new(),
+                stream_id: Some(stream_id),
+            },
+        )?;
+        self.by_key.insert(key, slot);
+        Ok(handle)
+    }
+
+    /// A handle to a texture that isn't loaded from a file, such as a generated one, which is uploaded straight away
+    /// and isn't shared
+    pub fn insert(&mut self, renderer: Option<&mut Renderer>, data: TextureData) -> Result<TextureHandle>
+    {
+        let texture = ManagedTexture { data, key: None, handle: Weak::new(), stream_id: None };
+        let (_, handle) = self.add(renderer, texture)?;
+        Ok(handle)
+    }
+
+    /// Release the textures whose handles have all been dropped and replace the placeholders of the files decoded since
+    /// the last update, called before each frame
+    pub fn update(&mut self, renderer: &mut Renderer) -> Result<()>
+    {
+        self.release_unused(Some(renderer))?;
+
+        let mut decoded = Vec::new();
+        for streamed in self.streamer.poll() {
+            // Textures released before their file was decoded are no longer needed
+            let Some(slot) = self
+                .textures
+                .iter()
+                .position(|texture| texture.as_ref().is_some_and(|texture| texture.stream_id == Some(streamed.id)))
+            else {
+                continue;
+            };
+            let Some(texture) = self.textures[slot].as_mut() else {
+                continue;
+            };
+            texture.stream_id = None;
+
+            match streamed.data {
+                Ok(data) => {
+                    log!("Streamed {}", streamed.path);
+                    texture.data = data;
+                    decoded.push(slot);
+                }
+                // The placeholder is left in place of a texture that can't be loaded
+                Err(err) => warn!("Failed to stream {}: {}", streamed.path, err),
+            }
+        }
+
+        // Every decoded texture is kept before any are uploaded, as the streamer won't decode them again, so a renderer
+        // rebuilt after a failed upload starts with all of them
+        for slot in decoded {
+            if let Some(texture) = self.textures[slot].as_ref() {
+                renderer.replace_texture(renderer::FIRST_LOADED_TEXTURE + slot, &texture.data)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Every texture held, in the renderer's order from renderer::FIRST_LOADED_TEXTURE, for creating the renderer
+    pub fn textures(&self) -> Vec<Option<&TextureData>>
+    {
+        self.textures
+            .iter()
+            .map(|texture| texture.as_ref().map(|texture| &texture.data))
+            .collect()
+    }
+
+    /// Hold a texture in the first released index, or after the last texture, and upload it if there is a renderer
+    fn add(&mut self, renderer: Option<&mut Renderer>, mut texture: ManagedTexture) -> Result<(usize, TextureHandle)>
+    {
+        let slot = self.textures.iter().position(Option::is_none).unwrap_or(self.textures.len());
+        let index = renderer::FIRST_LOADED_TEXTURE + slot;
+        if let Some(renderer) = renderer {
+            renderer.insert_texture(index, &texture.data)?;
+        }
+
+        let handle = Rc::new(index);
+        texture.handle = Rc::downgrade(&handle);
+        if slot == self.textures.len() {
+            self.textures.push(Some(texture));
+        } else {
+            self.textures[slot] = Some(texture);
+        }
+        Ok((slot, TextureHandle(handle)))
+    }
+
+    fn release_unused(&mut self, mut renderer: Option<&mut Renderer>) -> Result<()>
+    {
+        for slot in 0..self.textures.len() {
+            let unused = self.textures[slot]
+                .as_ref()
+                .is_some_and(|texture| texture.handle.strong_count() == 0);
+            if !unused {
+                continue;
+            }
+
+            if let Some(key) = self.textures[slot].take().and_then(|texture| texture.key) {
+                self.by_key.remove(&key);
+            }
+            if let Some(renderer) = renderer.as_deref_mut() {
+                renderer.release_texture(renderer::FIRST_LOADED_TEXTURE + slot)?;
+            }
+        }
+        Ok(())
+    }
+}
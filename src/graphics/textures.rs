@@ -1,279 +1,1064 @@
-use crate::graphics::buffers;
+use crate::graphics::allocator::{Allocation, MemoryPurpose};
+use crate::graphics::barriers::{self, ImageTransition};
+use crate::graphics::block_compression;
+use crate::graphics::debug::DebugUtils;
+use crate::graphics::device::{LogicalDevice, SupportedPhysicalDevice};
 use crate::graphics::errors::{IOResultToResultExt, VkAppError};
+use crate::graphics::samplers::SamplerDesc;
+use crate::graphics::upload::UploadContext;
 use crate::graphics::vk_app::Result;
+use crate::graphics::{image_loaders, texture_containers};
+use crate::log;
 use ash::vk;
-use std::fs::File;
 use std::io;
+use std::rc::Rc;
 
-/// Loads a PNG and creates a Vulkan image from it
+/// Whether a texture is a single image, an array of layers or a cubemap, which decides how shaders sample it
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TextureKind
+{
+    /// A single 2D image, sampled with a sampler2D
+    Single,
+    /// This many 2D images of the same size, sampled with a sampler2DArray and the layer as the third coordinate
+    Array(u32),
+    /// Six square faces in the order +X, -X, +Y, -Y, +Z, -Z, sampled with a samplerCube and a direction
+    Cube,
+}
+
+impl TextureKind
+{
+    pub fn layer_count(self) -> u32
+    {
+        match self {
+            TextureKind::Single => 1,
+            TextureKind::Array(layers) => layers,
+            TextureKind::Cube => 6,
+        }
+    }
+
+    fn view_type(self) -> vk::ImageViewType
+    {
+        match self {
+            TextureKind::Single => vk::ImageViewType::TYPE_2D,
+            TextureKind::Array(_) => vk::ImageViewType::TYPE_2D_ARRAY,
+            TextureKind::Cube => vk::ImageViewType::CUBE,
+        }
+    }
+
+    /// A cubemap's image must be created cube compatible to have a cube view
+    fn image_create_flags(self) -> vk::ImageCreateFlags
+    {
+        match self {
+            TextureKind::Cube => vk::ImageCreateFlags::CUBE_COMPATIBLE,
+            _ => vk::ImageCreateFlags::empty(),
+        }
+    }
+}
+
+/// Whether a texture's colour channels hold sRGB encoded colours, which are converted to linear when sampled, or linear
+/// data such as normals, roughness or masks, which are sampled as stored
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ColourSpace
+{
+    Srgb,
+    Linear,
+}
+
+impl ColourSpace
+{
+    /// The variant of the format in this colour space, the bytes of a texel mean the same in either variant. Formats
+    /// with no sRGB variant, such as BC5 and floats, are always linear and are returned unchanged
+    pub fn format(self, format: vk::Format) -> vk::Format
+    {
+        let (srgb, unorm) = match format {
+            vk::Format::R8G8B8A8_SRGB | vk::Format::R8G8B8A8_UNORM => {
+                (vk::Format::R8G8B8A8_SRGB, vk::Format::R8G8B8A8_UNORM)
+            }
+            vk::Format::BC1_RGB_SRGB_BLOCK | vk::Format::BC1_RGB_UNORM_BLOCK => {
+                (vk::Format::BC1_RGB_SRGB_BLOCK, vk::Format::BC1_RGB_UNORM_BLOCK)
+            }
+            vk::Format::BC1_RGBA_SRGB_BLOCK | vk::Format::BC1_RGBA_UNORM_BLOCK => {
+                (vk::Format::BC1_RGBA_SRGB_BLOCK, vk::Format::BC1_RGBA_UNORM_BLOCK)
+            }
+            vk::Format::BC3_SRGB_BLOCK | vk::Format::BC3_UNORM_BLOCK => {
+                (vk::Format::BC3_SRGB_BLOCK, vk::Format::BC3_UNORM_BLOCK)
+            }
+            vk::Format::BC7_SRGB_BLOCK | vk::Format::BC7_UNORM_BLOCK => {
+                (vk::Format::BC7_SRGB_BLOCK, vk::Format::BC7_UNORM_BLOCK)
+            }
+            _ => return format,
+        };
+
+        match self {
+            ColourSpace::Srgb => srgb,
+            ColourSpace::Linear => unorm,
+        }
+    }
+}
+
+/// Decoded image pixels kept on the CPU, so the texture can be created again if the device is lost
+pub struct TextureData
+{
+    pub path:       String,
+    pub width:      u32,
+    pub height:     u32,
+    /// Image files are loaded as single images, arrays and cubemaps are built from them or stored in a texture file
+    pub kind:       TextureKind,
+    /// R8G8B8A8_SRGB for decoded images, R32G32B32A32_SFLOAT for HDR images, or the format a texture file was stored in,
+    /// switched between sRGB and UNORM variants by with_colour_space
+    pub format:     vk::Format,
+    /// Tightly packed RGBA pixels, or BC blocks in row order, of the base level of each layer one after another
+    pub pixels:     Vec<u8>,
+    /// The levels below the base level stored in a texture file, in order, each holding every layer in the same way as
+    /// pixels. When empty the mip chain is generated on upload, unless the format is block compressed
+    pub mip_pixels: Vec<Vec<u8>>,
+    /// How the texture is sampled when drawn, loaded images have the default
+    pub sampler:    SamplerDesc,
+}
+
+impl TextureData
+{
+    /// Generates a checkerboard of two colours with square cells, for a texture that doesn't need an image file
+    pub fn checkerboard(name: &str, size: u32, cell_size: u32, colours: [[u8; 4]; 2]) -> Self
+    {
+        let pixels = (0..size)
+            .flat_map(|y| (0..size).map(move |x| colours[((x / cell_size + y / cell_size) % 2) as usize]))
+            .flatten()
+            .collect();
+
+        Self {
+            path: name.to_string(),
+            width: size,
+            height: size,
+            kind: TextureKind::Single,
+            format: vk::Format::R8G8B8A8_SRGB,
+            pixels,
+            mip_pixels: Vec::new(),
+            sampler: SamplerDesc::default(),
+        }
+    }
+
+    /// Reinterpret the texels as sRGB colours or linear data, e.g. a normal map loaded from a PNG is linear. The
+    /// texels are unchanged, only the format, so mip levels generated on upload are averaged in the right space
+    pub fn with_colour_space(self, colour_space: ColourSpace) -> Self
+    {
+        Self { format: colour_space.format(self.format), ..self }
+    }
+
+    /// Combine single images of the same size, format and number of stored mip levels into an array texture, sampled the
+    /// same way as the first image
+    pub fn array(name: &str, layers: &[TextureData]) -> Result<Self>
+    {
+        Self::stack_layers(name, TextureKind::Array(layers.len() as u32), layers)
+    }
+
+    /// Combine six square single images of the same size, format and number of stored mip levels into a cubemap, the
+    /// faces are in the order +X, -X, +Y, -Y, +Z, -Z and the cubemap is sampled the same way as the first face
+    pub fn cubemap(name: &str, faces: &[TextureData; 6]) -> Result<Self>
+    {
+        if faces[0].width != faces[0].height {
+            return Err(invalid_data(name, "Cubemap faces must be square"));
+        }
+        Self::stack_layers(name, TextureKind::Cube, faces)
+    }
+
+    fn stack_layers(name: &str, kind: TextureKind, layers: &[TextureData]) -> Result<Self>
+    {
+        let Some(first) = layers.first() else {
+            return Err(invalid_data(name, "Texture has no layers"));
+        };
+        if let Some(layer) = layers.iter().find(|layer| {
+            layer.kind != TextureKind::Single
+                || layer.width != first.width
+                || layer.height != first.height
+                || layer.format != first.format
+                || layer.mip_pixels.len() != first.mip_pixels.len()
+        }) {
+            return Err(invalid_data(
+                name,
+                format!(
+                    "Layer {} is not a single image of the same size, format and mip levels as {}",
+                    layer.path, first.path
+                ),
+            ));
+        }
+
+        Ok(Self {
+            path: name.to_string(),
+            width: first.width,
+            height: first.height,
+            kind,
+            format: first.format,
+            pixels: layers.iter().flat_map(|layer| layer.pixels.iter().copied()).collect(),
+            mip_pixels: (0..first.mip_pixels.len())
+                .map(|level| layers.iter().flat_map(|layer| layer.mip_pixels[level].iter().copied()).collect())
+                .collect(),
+            sampler: first.sampler,
+        })
+    }
+
+    /// Decompress every level of BC blocks into RGBA pixels, for devices that can't sample the format
+    fn decompressed(&self) -> Self
+    {
+        let layer_count = self.kind.layer_count() as usize;
+        // Each layer is decompressed separately as a layer's last row of blocks can be partly outside it
+        let decompress = |level: &[u8], mip_level: u32| -> Vec<u8> {
+            let (width, height) = mip_extent(self.width, self.height, mip_level);
+            level
+                .chunks_exact(level.len() / layer_count)
+                .flat_map(|layer| block_compression::decompress(self.format, layer, width, height))
+                .collect()
+        };
+
+        Self {
+            path: self.path.clone(),
+            width: self.width,
+            height: self.height,
+            kind: self.kind,
+            format: block_compression::decompressed_format(self.format),
+            pixels: decompress(&self.pixels, 0),
+            mip_pixels: self.mip_pixels.iter().zip(1..).map(|(level, mip_level)| decompress(level, mip_level)).collect(),
+            sampler: self.sampler,
+        }
+    }
+
+    /// Convert R32G32B32A32_SFLOAT pixels to R16G16B16A16_SFLOAT, for devices that can't filter 32-bit floats
+    fn to_half_float(&self) -> Self
+    {
+        let convert = |pixels: &Vec<u8>| -> Vec<u8> {
+            pixels
+                .chunks_exact(4)
+                .flat_map(|value| f32_to_f16(f32::from_ne_bytes([value[0], value[1], value[2], value[3]])).to_ne_bytes())
+                .collect()
+        };
+
+        Self {
+            path: self.path.clone(),
+            width: self.width,
+            height: self.height,
+            kind: self.kind,
+            format: vk::Format::R16G16B16A16_SFLOAT,
+            pixels: convert(&self.pixels),
+            mip_pixels: self.mip_pixels.iter().map(convert).collect(),
+            sampler: self.sampler,
+        }
+    }
+}
+
+/// A sampled image and its view uploaded from TextureData, viewed as a 2D image, a 2D array or a cube to match its kind
 ///
-/// Uses a staging buffer instead of a staging image as this can be more performant on (at least) NVidia hardware
-pub fn create_texture_image(
-    instance: &ash::Instance, physical_device: vk::PhysicalDevice, device: &ash::Device, command_pool: vk::CommandPool,
-    graphics_queue: vk::Queue, path: &str,
-) -> Result<(vk::Image, vk::DeviceMemory)>
+/// The texture destroys its image and view and frees its memory when dropped. It holds a reference to the device so the
+/// device is not destroyed while the texture is alive
+pub struct Texture
+{
+    device:     Rc<LogicalDevice>,
+    image:      vk::Image,
+    allocation: Allocation,
+    view:       vk::ImageView,
+}
+
+impl Texture
 {
-    let decoder = png::Decoder::new(File::open(path).to_result(path)?);
-    let mut reader = decoder.read_info().unwrap();
-    let mut buf = vec![0; reader.output_buffer_size()];
-    let info = reader.next_frame(&mut buf).unwrap();
-    let bytes = &buf[..info.buffer_size()];
+    /// Create the texture's image and view, and record the upload of its pixels and the filling of its mip levels in
+    /// the upload context's current batch
+    ///
+    /// Mip levels stored in a texture file are uploaded as they are. Otherwise uncompressed textures get a full mip chain,
+    /// blitted on the GPU if the format supports linear filtered blits, see supports_linear_blit, or downsampled on the
+    /// CPU. Formats the device can't sample with linear filtering are converted on the CPU first, block compressed
+    /// formats are decompressed and 32-bit floats become 16-bit floats
+    ///
+    /// The texture must not be drawn until the upload context is flushed and the upload has completed
+    pub fn new(
+        upload_context: &mut UploadContext, instance: &ash::Instance, physical_device: &SupportedPhysicalDevice,
+        device: &Rc<LogicalDevice>, debug_utils: &DebugUtils, texture: &TextureData,
+    ) -> Result<Self>
+    {
+        let converted;
+        let texture = if supports_filtering(instance, physical_device, texture.format) {
+            texture
+        } else if block_compression::block_size(texture.format).is_some() {
+            log!("{:?} is not supported, decompressing {} on the CPU", texture.format, texture.path);
+            converted = texture.decompressed();
+            &converted
+        } else if texture.format == vk::Format::R32G32B32A32_SFLOAT {
+            log!("{:?} can't be filtered, converting {} to half floats", texture.format, texture.path);
+            converted = texture.to_half_float();
+            &converted
+        } else {
+            texture
+        };
 
-    // TODO: Allow more than just RGBA
-    if info.color_type != png::ColorType::Rgba {
-        return Err(VkAppError::IoError(
-            io::Error::new(io::ErrorKind::InvalidData, "Must be RGBA image"),
-            path.to_string(),
-        ));
+        let linear_blit = supports_linear_blit(instance, physical_device.vk_physical_device, texture.format);
+        let (image, mip_levels) = create_texture_image(upload_context, device, debug_utils, texture, linear_blit)?;
+        Self::with_view(device, debug_utils, texture.path.as_str(), image, texture.format, mip_levels, texture.kind)
     }
 
-    let image_size = (info.width * info.height * 4) as vk::DeviceSize; // TODO: 4 is currently temporary number of channels for RGBA, change
+    /// Create a texture that compute shaders write to and fragment shaders sample
+    ///
+    /// The image starts with undefined contents, it must be written and transitioned to SHADER_READ_ONLY_OPTIMAL before
+    /// it is drawn with
+    pub fn new_storage(
+        device: &Rc<LogicalDevice>, debug_utils: &DebugUtils, name: &str, width: u32, height: u32,
+    ) -> Result<Self>
+    {
+        // Every implementation supports storage images of this format, unlike the sRGB formats
+        let format = vk::Format::R8G8B8A8_UNORM;
+        let usage = vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED;
+        let image = create_image(device, width, height, 1, TextureKind::Single, format, usage)?;
+        debug_utils.set_name(image.0, format!("Texture {}", name).as_str());
+        Self::with_view(device, debug_utils, name, image, format, 1, TextureKind::Single)
+    }
 
-    let usage = vk::BufferUsageFlags::TRANSFER_SRC;
-    let properties = vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT;
-    let staging_buffer = buffers::create_buffer(instance, physical_device, device, image_size, usage, properties)?;
+    /// Take ownership of the image and create its view, destroying the image if the view can't be created
+    pub(crate) fn with_view(
+        device: &Rc<LogicalDevice>, debug_utils: &DebugUtils, name: &str, (image, allocation): (vk::Image, Allocation),
+        format: vk::Format, mip_levels: u32, kind: TextureKind,
+    ) -> Result<Self>
+    {
+        let view = match create_texture_image_view(device, debug_utils, name, image, format, mip_levels, kind) {
+            Ok(view) => view,
+            Err(err) => {
+                unsafe { device.destroy_image(image, None) };
+                device.allocator.borrow_mut().free(&allocation);
+                return Err(err);
+            }
+        };
 
-    // TODO: buffer memcpy
-    unsafe {
-        let data_ptr = device.map_memory(staging_buffer.buffer_memory, 0, image_size, vk::MemoryMapFlags::empty())?;
-        std::ptr::copy(bytes.as_ptr() as *mut std::ffi::c_void, data_ptr, image_size as usize);
-        device.unmap_memory(staging_buffer.buffer_memory);
+        Ok(Self { device: device.clone(), image, allocation, view })
     }
 
-    let (texture_image, texture_image_memory) = create_image(instance, physical_device, device, info.width, info.height)?;
+    pub fn image(&self) -> vk::Image { self.image }
 
-    // Transition the image to be able to copy the staging buffer to it
-    transition_image_layout(
-        device,
-        command_pool,
-        graphics_queue,
-        texture_image,
-        vk::Format::R8G8B8A8_SRGB,
-        vk::ImageLayout::UNDEFINED,
-        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
-    )?;
+    pub fn view(&self) -> vk::ImageView { self.view }
+}
+
+impl Drop for Texture
+{
+    fn drop(&mut self)
+    {
+        unsafe {
+            self.device.destroy_image_view(self.view, None);
+            self.device.destroy_image(self.image, None);
+        }
+        self.device.allocator.borrow_mut().free(&self.allocation);
+    }
+}
+
+/// Decodes one kind of image file into texture data, see ImageLoaders
+///
+/// Loaders are shared with the texture streaming workers so must be usable from any thread
+pub trait ImageLoader: Send + Sync
+{
+    /// Lower case extensions, without the dot, of the files the loader decodes
+    fn extensions(&self) -> &[&str];
+
+    /// Whether the file's contents identify it as the loader's kind, usually by the magic bytes it starts with
+    fn recognises(&self, bytes: &[u8]) -> bool;
+
+    fn load(&self, path: &str, bytes: &[u8]) -> Result<TextureData>;
+}
+
+/// The loaders texture files can be loaded with
+///
+/// A file is loaded by the first loader that recognises its contents, or failing that the first loader for its
+/// extension, as some formats such as TGA have no magic bytes
+pub struct ImageLoaders
+{
+    loaders: Vec<Box<dyn ImageLoader>>,
+}
+
+impl Default for ImageLoaders
+{
+    /// PNG, JPEG, Radiance HDR, TGA, DDS and KTX2
+    fn default() -> Self
+    {
+        Self::new(vec![
+            Box::new(image_loaders::PngLoader),
+            Box::new(image_loaders::JpegLoader),
+            Box::new(image_loaders::HdrLoader),
+            Box::new(image_loaders::TgaLoader),
+            Box::new(texture_containers::DdsLoader),
+            Box::new(texture_containers::Ktx2Loader),
+        ])
+    }
+}
+
+impl ImageLoaders
+{
+    pub fn new(loaders: Vec<Box<dyn ImageLoader>>) -> Self { Self { loaders } }
+
+    /// Read a texture file and decode it with the loader for its kind
+    pub fn load(&self, path: &str) -> Result<TextureData>
+    {
+        let bytes = std::fs::read(path).to_result(path)?;
+        let extension = std::path::Path::new(path)
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_lowercase());
+
+        let loader = self
+            .loaders
+            .iter()
+            .find(|loader| loader.recognises(&bytes))
+            .or_else(|| {
+                let extension = extension.as_deref()?;
+                self.loaders.iter().find(|loader| loader.extensions().contains(&extension))
+            })
+            .ok_or_else(|| {
+                VkAppError::IoError(
+                    io::Error::new(io::ErrorKind::Unsupported, "Unknown texture file type"),
+                    path.to_string(),
+                )
+            })?;
+        loader.load(path, &bytes)
+    }
+
+    /// Read a texture file as load does and give it the colour space, see TextureData::with_colour_space
+    ///
+    /// Files are loaded as sRGB unless their format says otherwise, so data textures such as normal and roughness maps
+    /// should be loaded as ColourSpace::Linear
+    pub fn load_as(&self, path: &str, colour_space: ColourSpace) -> Result<TextureData>
+    {
+        Ok(self.load(path)?.with_colour_space(colour_space))
+    }
+
+    /// Load an image file for each layer of an array texture, see TextureData::array
+    pub fn load_array(&self, name: &str, paths: &[&str]) -> Result<TextureData>
+    {
+        let layers = paths.iter().map(|path| self.load(path)).collect::<Result<Vec<_>>>()?;
+        TextureData::array(name, &layers)
+    }
+
+    /// Load an image file for each face of a cubemap, in the order +X, -X, +Y, -Y, +Z, -Z, see TextureData::cubemap
+    pub fn load_cubemap(&self, name: &str, face_paths: &[&str; 6]) -> Result<TextureData>
+    {
+        let faces = face_paths
+            .iter()
+            .map(|path| self.load(path))
+            .collect::<Result<Vec<_>>>()?;
+        let faces: &[TextureData; 6] = faces.as_slice().try_into().unwrap();
+        TextureData::cubemap(name, faces)
+    }
+}
+
+/// An error for a texture file that is malformed or in a variant the loader doesn't support
+pub(crate) fn invalid_data(path: &str, message: impl Into<String>) -> VkAppError
+{
+    VkAppError::IoError(io::Error::new(io::ErrorKind::InvalidData, message.into()), path.to_string())
+}
 
-    // Execute the copy
-    copy_buffer_to_image(
+/// Creates a Vulkan image with a layer for each layer of the texture data, returning it with its number of mip levels
+///
+/// The pixels are copied in the upload context's current batch, so the image must not be used until it is flushed and
+/// complete. Levels stored with the texture are copied along with it. Otherwise the smaller levels of uncompressed
+/// textures are blitted from the base level when linear_blit is set, or downsampled on the CPU and copied, and block
+/// compressed textures have only their base level
+fn create_texture_image(
+    upload_context: &mut UploadContext, device: &LogicalDevice, debug_utils: &DebugUtils, texture: &TextureData,
+    linear_blit: bool,
+) -> Result<((vk::Image, Allocation), u32)>
+{
+    let layer_count = texture.kind.layer_count();
+    let cpu_levels: Vec<Vec<u8>>;
+    let mip_chain = if !texture.mip_pixels.is_empty() {
+        MipChain::Levels(&texture.mip_pixels)
+    } else if block_compression::block_size(texture.format).is_some() {
+        // Blocks can't be blitted, and downsampling them would mean compressing them again
+        MipChain::Blit(1)
+    } else if linear_blit {
+        MipChain::Blit(mip_level_count(texture.width, texture.height))
+    } else {
+        log!("Linear blits are unsupported, downsampling the mip levels of {} on the CPU", texture.path);
+        let mip_levels = mip_level_count(texture.width, texture.height);
+        // Each layer is downsampled on its own, then each level's layers are put back together
+        let layer_levels: Vec<Vec<Vec<u8>>> = texture
+            .pixels
+            .chunks_exact(texture.pixels.len() / layer_count as usize)
+            .map(|layer| downsample_mip_chain(layer, texture.width, texture.height, mip_levels, texture.format))
+            .collect();
+        cpu_levels = (0..mip_levels as usize - 1)
+            .map(|level| layer_levels.iter().flat_map(|levels| levels[level].iter().copied()).collect())
+            .collect();
+        MipChain::Levels(&cpu_levels)
+    };
+    let mip_levels = mip_chain.level_count();
+
+    log!(
+        "Creating texture image for {} as {:?} {:?} with {} mip levels",
+        texture.path,
+        texture.kind,
+        texture.format,
+        mip_levels
+    );
+
+    // Blitting reads the larger level so the image is a transfer source as well
+    let (texture_image, texture_image_allocation) = create_image(
         device,
-        command_pool,
-        graphics_queue,
-        info.width,
-        info.height,
-        staging_buffer.buffer,
-        texture_image,
+        texture.width,
+        texture.height,
+        mip_levels,
+        texture.kind,
+        texture.format,
+        vk::ImageUsageFlags::TRANSFER_SRC | vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED,
     )?;
+    debug_utils.set_name(texture_image, format!("Texture {}", texture.path).as_str());
 
-    // Transition the image from being a transfer destination to being readable from a shader
-    transition_image_layout(
-        device,
-        command_pool,
-        graphics_queue,
+    if let Err(err) = upload_context.upload_image(
+        texture.pixels.as_slice(),
         texture_image,
-        vk::Format::R8G8B8A8_SRGB,
-        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
-        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
-    )?;
+        texture.width,
+        texture.height,
+        layer_count,
+        mip_chain,
+    ) {
+        unsafe { device.destroy_image(texture_image, None) };
+        device.allocator.borrow_mut().free(&texture_image_allocation);
+        return Err(err);
+    }
 
-    staging_buffer.cleanup(device);
+    Ok(((texture_image, texture_image_allocation), mip_levels))
+}
 
-    Ok((texture_image, texture_image_memory))
+/// How the levels below the base level of an uploaded image are filled
+pub enum MipChain<'a>
+{
+    /// The image has this many levels, each blitted from the one above it on the GPU
+    Blit(u32),
+    /// Tightly packed pixels or BC blocks of each level below the base level, in order, with every layer of a level one
+    /// after another
+    Levels(&'a [Vec<u8>]),
 }
 
-/// Images are accessed through image views rather than directly, texutre images are no different
-pub fn create_texture_image_view(device: &ash::Device, texture_image: vk::Image) -> Result<vk::ImageView>
+impl MipChain<'_>
 {
-    let image_view_create_info = vk::ImageViewCreateInfo::default()
-        .image(texture_image)
-        .view_type(vk::ImageViewType::TYPE_2D)
-        .format(vk::Format::R8G8B8A8_SRGB)
-        .subresource_range(
-            vk::ImageSubresourceRange::default()
-                .aspect_mask(vk::ImageAspectFlags::COLOR)
-                .base_mip_level(0)
-                .level_count(1)
-                .base_array_layer(0)
-                .layer_count(1),
-        );
+    /// Number of levels including the base level
+    pub fn level_count(&self) -> u32
+    {
+        match self {
+            MipChain::Blit(mip_levels) => *mip_levels,
+            MipChain::Levels(levels) => levels.len() as u32 + 1,
+        }
+    }
+}
+
+/// Number of levels in a full mip chain, halving the largest dimension down to 1
+pub fn mip_level_count(width: u32, height: u32) -> u32 { u32::BITS - width.max(height).max(1).leading_zeros() }
+
+/// Size of a mip level, each dimension is halved and rounded down, but never below 1
+pub fn mip_extent(width: u32, height: u32, level: u32) -> (u32, u32) { ((width >> level).max(1), (height >> level).max(1)) }
 
-    unsafe { Ok(device.create_image_view(&image_view_create_info, None)?) }
+/// Whether the device can sample the format with linear filtering, block compressed formats also need their feature
+fn supports_filtering(instance: &ash::Instance, physical_device: &SupportedPhysicalDevice, format: vk::Format) -> bool
+{
+    let properties = unsafe { instance.get_physical_device_format_properties(physical_device.vk_physical_device, format) };
+    (block_compression::block_size(format).is_none() || physical_device.texture_compression_bc)
+        && properties
+            .optimal_tiling_features
+            .contains(vk::FormatFeatureFlags::SAMPLED_IMAGE | vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR)
 }
 
-/// A combined image sampler is a descriptor that makes it possible for shaders to access an image resource through a sampler object
-pub fn create_texture_sampler(
-    instance: &ash::Instance, device: &ash::Device, physical_device: vk::PhysicalDevice,
-) -> Result<vk::Sampler>
+/// Whether images of the format with optimal tiling can be blitted with linear filtering, which generating the mip
+/// chain on the GPU needs
+pub fn supports_linear_blit(instance: &ash::Instance, physical_device: vk::PhysicalDevice, format: vk::Format) -> bool
 {
-    let properties = unsafe { instance.get_physical_device_properties(physical_device) };
-
-    let sampler_create_info = vk::SamplerCreateInfo::default()
-        .mag_filter(vk::Filter::LINEAR)
-        .min_filter(vk::Filter::LINEAR)
-        .address_mode_u(vk::SamplerAddressMode::REPEAT)
-        .address_mode_v(vk::SamplerAddressMode::REPEAT)
-        .address_mode_w(vk::SamplerAddressMode::REPEAT)
-        .anisotropy_enable(true)
-        .max_anisotropy(properties.limits.max_sampler_anisotropy)
-        .border_color(vk::BorderColor::INT_OPAQUE_BLACK)
-        .unnormalized_coordinates(false)
-        .compare_enable(false)
-        .compare_op(vk::CompareOp::ALWAYS)
-        .mipmap_mode(vk::SamplerMipmapMode::LINEAR)
-        .mip_lod_bias(0.0)
-        .min_lod(0.0)
-        .max_lod(0.0);
-
-    Ok(unsafe { device.create_sampler(&sampler_create_info, None)? })
+    let properties = unsafe { instance.get_physical_device_format_properties(physical_device, format) };
+    properties.optimal_tiling_features.contains(
+        vk::FormatFeatureFlags::BLIT_SRC
+            | vk::FormatFeatureFlags::BLIT_DST
+            | vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR,
+    )
 }
 
-/// Creates a Vulkan image buffer from an image's width and height
-fn create_image(
-    instance: &ash::Instance, physical_device: vk::PhysicalDevice, device: &ash::Device, width: u32, height: u32,
-) -> Result<(vk::Image, vk::DeviceMemory)>
+/// Downsample RGBA pixels into every level below the base level, each level averaging 2x2 texels of the one above
+///
+/// The pixels are 8-bit UNORM or sRGB, or 16 or 32-bit floats. The colour channels of sRGB pixels are averaged in
+/// linear space to match what a blit does with an sRGB format, alpha is always linear. Odd dimensions drop their last
+/// row or column, the same as rounding the extent down
+fn downsample_mip_chain(pixels: &[u8], width: u32, height: u32, mip_levels: u32, format: vk::Format) -> Vec<Vec<u8>>
 {
-    let image_create_info = vk::ImageCreateInfo::default()
-        .image_type(vk::ImageType::TYPE_2D)
-        .extent(vk::Extent3D { width, height, depth: 1 }) // Number of texels on each axis
-        .mip_levels(1)
-        .array_layers(1)
-        .format(vk::Format::R8G8B8A8_SRGB) // Must use same format for texels as the pixels in the image buffer TODO: More options
-        .tiling(vk::ImageTiling::OPTIMAL) // Texels laid out in implementation defined order for optimal access (cannot directly access texels in memory of image)
-        // Discard texels in first transition, we can do this because we first transition image to be a transfer destination so don't need to preserve texels
-        .initial_layout(vk::ImageLayout::UNDEFINED)
-        // Image is destination for a buffer copy so use TRANSFER_DST
-        // Image must be accessable from shader so also use SAMPLED
-        .usage(vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED)
-        .sharing_mode(vk::SharingMode::EXCLUSIVE) // Only used by graphics queue
-        .samples(vk::SampleCountFlags::TYPE_1)
-        .flags(vk::ImageCreateFlags::empty());
+    let to_linear: [f32; 256] = std::array::from_fn(|value| srgb_to_linear(value as f32 / 255.0));
+    let texel_size = match format {
+        vk::Format::R32G32B32A32_SFLOAT => 16,
+        vk::Format::R16G16B16A16_SFLOAT => 8,
+        _ => 4,
+    };
+    let read = |texel: &[u8]| -> [f32; 4] {
+        std::array::from_fn(|channel| match format {
+            vk::Format::R32G32B32A32_SFLOAT => f32::from_ne_bytes([
+                texel[channel * 4],
+                texel[channel * 4 + 1],
+                texel[channel * 4 + 2],
+                texel[channel * 4 + 3],
+            ]),
+            vk::Format::R16G16B16A16_SFLOAT => f16_to_f32(u16::from_ne_bytes([texel[channel * 2], texel[channel * 2 + 1]])),
+            vk::Format::R8G8B8A8_SRGB if channel < 3 => to_linear[texel[channel] as usize],
+            _ => texel[channel] as f32 / 255.0,
+        })
+    };
+    let write = |texel: [f32; 4], dst: &mut Vec<u8>| {
+        for (channel, value) in texel.into_iter().enumerate() {
+            match format {
+                vk::Format::R32G32B32A32_SFLOAT => dst.extend(value.to_ne_bytes()),
+                vk::Format::R16G16B16A16_SFLOAT => dst.extend(f32_to_f16(value).to_ne_bytes()),
+                vk::Format::R8G8B8A8_SRGB if channel < 3 => dst.push((linear_to_srgb(value) * 255.0 + 0.5) as u8),
+                _ => dst.push((value * 255.0 + 0.5) as u8),
+            }
+        }
+    };
 
-    let image = unsafe { device.create_image(&image_create_info, None)? };
+    let mut levels: Vec<Vec<u8>> = Vec::with_capacity(mip_levels.saturating_sub(1) as usize);
+    for level in 1..mip_levels {
+        let (src_width, src_height) = mip_extent(width, height, level - 1);
+        let (dst_width, dst_height) = mip_extent(width, height, level);
+        let src = levels.last().map_or(pixels, Vec::as_slice);
 
-    let memory_requirements = unsafe { device.get_image_memory_requirements(image) };
+        let mut dst = Vec::with_capacity(dst_width as usize * dst_height as usize * texel_size);
+        for y in 0..dst_height {
+            for x in 0..dst_width {
+                // A dimension that is already 1 has nothing to average along it
+                let xs = [(x * 2).min(src_width - 1), (x * 2 + 1).min(src_width - 1)];
+                let ys = [(y * 2).min(src_height - 1), (y * 2 + 1).min(src_height - 1)];
+                let mut sum = [0.0f32; 4];
+                for sy in ys {
+                    for sx in xs {
+                        let offset = (sy * src_width + sx) as usize * texel_size;
+                        for (sum, value) in sum.iter_mut().zip(read(&src[offset..offset + texel_size])) {
+                            *sum += value;
+                        }
+                    }
+                }
+                write(sum.map(|sum| sum / 4.0), &mut dst);
+            }
+        }
+        levels.push(dst);
+    }
+    levels
+}
 
-    let memory_type = buffers::find_memory_type(
-        instance,
-        physical_device,
-        memory_requirements.memory_type_bits,
-        vk::MemoryPropertyFlags::DEVICE_LOCAL,
-    )?;
+fn srgb_to_linear(value: f32) -> f32
+{
+    if value <= 0.04045 { value / 12.92 } else { libm::powf((value + 0.055) / 1.055, 2.4) }
+}
+
+fn linear_to_srgb(value: f32) -> f32
+{
+    if value <= 0.0031308 { value * 12.92 } else { 1.055 * libm::powf(value, 1.0 / 2.4) - 0.055 }
+}
+
+/// Round a float to the nearest half float, ties to even, values too large become infinity and values too small become
+/// zero
+fn f32_to_f16(value: f32) -> u16
+{
+    let bits = value.to_bits();
+    let sign = (bits >> 16) as u16 & 0x8000;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x7f_ffff;
+
+    if exponent == 0xff {
+        // Infinity stays infinity and NaN stays NaN
+        return sign | 0x7c00 | if mantissa != 0 { 0x200 } else { 0 };
+    }
+    let exponent = exponent - 127 + 15;
+    if exponent >= 0x1f {
+        return sign | 0x7c00;
+    }
+    if exponent <= 0 {
+        // Subnormal, with the implicit leading bit shifted into the mantissa
+        if exponent < -10 {
+            return sign;
+        }
+        return sign | shift_right_rounded(mantissa | 0x80_0000, (14 - exponent) as u32) as u16;
+    }
 
-    let memory_allocate_info = vk::MemoryAllocateInfo::default()
-        .allocation_size(memory_requirements.size)
-        .memory_type_index(memory_type as u32);
+    // Rounding can carry into the exponent, which is still the correctly rounded value
+    sign | shift_right_rounded(((exponent as u32) << 23) | mantissa, 13) as u16
+}
 
-    unsafe {
-        let image_memory = device.allocate_memory(&memory_allocate_info, None)?;
-        device.bind_image_memory(image, image_memory, 0)?;
+/// Shift value right, rounding to the nearest result and ties to the even result
+fn shift_right_rounded(value: u32, shift: u32) -> u32
+{
+    let truncated = value >> shift;
+    let remainder = value & ((1 << shift) - 1);
+    let halfway = 1 << (shift - 1);
+    if remainder > halfway || (remainder == halfway && truncated & 1 == 1) {
+        truncated + 1
+    } else {
+        truncated
+    }
+}
 
-        Ok((image, image_memory))
+fn f16_to_f32(half: u16) -> f32
+{
+    let sign = if half & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = ((half >> 10) & 0x1f) as i32;
+    let mantissa = (half & 0x3ff) as f32;
+    match exponent {
+        0 => sign * mantissa * libm::exp2f(-24.0),
+        0x1f if mantissa == 0.0 => sign * f32::INFINITY,
+        0x1f => f32::NAN,
+        _ => sign * (1.0 + mantissa / 1024.0) * libm::exp2f((exponent - 15) as f32),
     }
 }
 
-/// We need to copy the staging buffer to the device-local image
+/// Record blits filling each level of the first array_layers layers of the image below the base level from the level
+/// above it, every layer is blitted at once
 ///
-/// But first we need to transition the image to the right layout to do this
-fn transition_image_layout(
-    device: &ash::Device, command_pool: vk::CommandPool, graphics_queue: vk::Queue, image: vk::Image, format: vk::Format,
-    old_layout: vk::ImageLayout, new_layout: vk::ImageLayout,
+/// Every level must be in TRANSFER_DST_OPTIMAL with the base level written, afterwards every level is in
+/// SHADER_READ_ONLY_OPTIMAL
+pub(crate) fn cmd_generate_mipmaps(
+    device: &ash::Device, command_buffer: vk::CommandBuffer, image: vk::Image, width: u32, height: u32, mip_levels: u32,
+    array_layers: u32,
 ) -> Result<()>
 {
-    let command_buffer = buffers::begin_single_time_commands(device, command_pool)?;
-
-    // Synchronisation object for acces to images
-    let mut barrier = vk::ImageMemoryBarrier::default()
-        .old_layout(old_layout)
-        .new_layout(new_layout)
-        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
-        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
-        .image(image)
+    let level_transition = |level: u32, old_layout: vk::ImageLayout, new_layout: vk::ImageLayout| {
+        ImageTransition::colour(image, old_layout, new_layout)
+            .mips(level..level + 1)
+            .layers(0..array_layers)
+    };
+
+    for level in 1..mip_levels {
+        // The level above has been written, by the copy or the previous blit, and is now read by this blit
+        let to_src = level_transition(
+            level - 1,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+        );
+        barriers::cmd_transition_images(device, command_buffer, &[to_src])?;
+
+        let (src_width, src_height) = mip_extent(width, height, level - 1);
+        let (dst_width, dst_height) = mip_extent(width, height, level);
+        let blit = vk::ImageBlit::default()
+            .src_subresource(colour_layers(level - 1, array_layers))
+            .src_offsets([vk::Offset3D::default(), vk::Offset3D { x: src_width as i32, y: src_height as i32, z: 1 }])
+            .dst_subresource(colour_layers(level, array_layers))
+            .dst_offsets([vk::Offset3D::default(), vk::Offset3D { x: dst_width as i32, y: dst_height as i32, z: 1 }]);
+        unsafe {
+            device.cmd_blit_image(
+                command_buffer,
+                image,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &[blit],
+                vk::Filter::LINEAR,
+            )
+        };
+    }
+
+    // Every level but the last has been blitted from, the last was only written
+    let mut transitions: Vec<ImageTransition> = (0..mip_levels - 1)
+        .map(|level| {
+            level_transition(level, vk::ImageLayout::TRANSFER_SRC_OPTIMAL, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+        })
+        .collect();
+    transitions.push(level_transition(
+        mip_levels - 1,
+        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+    ));
+    barriers::cmd_transition_images(device, command_buffer, &transitions)
+}
+
+/// Images are accessed through image views rather than directly, texutre images are no different
+///
+/// The view covers every layer of the image, as a 2D image, a 2D array or a cube depending on the kind
+fn create_texture_image_view(
+    device: &ash::Device, debug_utils: &DebugUtils, name: &str, texture_image: vk::Image, format: vk::Format,
+    mip_levels: u32, kind: TextureKind,
+) -> Result<vk::ImageView>
+{
+    let image_view_create_info = vk::ImageViewCreateInfo::default()
+        .image(texture_image)
+        .view_type(kind.view_type())
+        .format(format)
         .subresource_range(
             vk::ImageSubresourceRange::default()
                 .aspect_mask(vk::ImageAspectFlags::COLOR)
                 .base_mip_level(0)
-                .level_count(1)
+                .level_count(mip_levels)
                 .base_array_layer(0)
-                .layer_count(1),
+                .layer_count(kind.layer_count()),
         );
 
-    /*  We use a pipeline barrier to ensure that commands are using the image transition they expect
-        The source stage specifies in which pipeline stage the operations before transition complete
-        The destination stage is where subsequent operations on the image begin
-     */
-    let (source_stage, destination_stage) = if old_layout == vk::ImageLayout::UNDEFINED
-        && new_layout == vk::ImageLayout::TRANSFER_DST_OPTIMAL
-    {
-        // Transition to being able to transfer write to image
-        // We don't need to limit the memory operations before the barrier because we don't care about the image layout
-        // We want to make the sure the image memory is ready to be written to by a transfer operation after the transition
-        barrier = barrier
-            .src_access_mask(vk::AccessFlags::empty())
-            .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE);
-        // Pre-barrier operations can start as early as possible
-        // Any transfer operations should wait on the barrier to complete because our transition will be complete
-        (vk::PipelineStageFlags::TOP_OF_PIPE, vk::PipelineStageFlags::TRANSFER)
-    } else if old_layout == vk::ImageLayout::TRANSFER_DST_OPTIMAL && new_layout == vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL
-    {
-
-        // Transition to being able to read image from shader
-        // We must wait for all transfer writes to be complete before transitioning
-        // We want to make sure the image memory is ready to read by a shader after the transition
-        barrier = barrier
-            .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
-            .dst_access_mask(vk::AccessFlags::SHADER_READ);
-        // We must wait for the transfer stage of the pipeline to complete before we can transition the image
-        // Fragment shader operations should wait on the barrier to complete because this is where we read the texture
-        (vk::PipelineStageFlags::TRANSFER, vk::PipelineStageFlags::FRAGMENT_SHADER)
-    } else {
-        // TODO: probably shouldn't be a device error
-        return Err(VkAppError::DeviceError(String::from("Unsupported layout transition")));
-    };
+    let image_view = unsafe { device.create_image_view(&image_view_create_info, None)? };
+    debug_utils.set_name(image_view, format!("Texture view {}", name).as_str());
 
-    // TODO: Investigate VK_DEPENDENCY_BY_REGION_BIT
-    unsafe {
-        device.cmd_pipeline_barrier(
-            command_buffer,
-            source_stage,
-            destination_stage,
-            vk::DependencyFlags::empty(),
-            &[],
-            &[],
-            &[barrier],
-        )
-    };
+    Ok(image_view)
+}
+
+/// Creates a Vulkan image from an image's width, height, number of mip levels and kind, bound to memory sub-allocated
+/// from the allocator
+pub(crate) fn create_image(
+    device: &LogicalDevice, width: u32, height: u32, mip_levels: u32, kind: TextureKind, format: vk::Format,
+    usage: vk::ImageUsageFlags,
+) -> Result<(vk::Image, Allocation)>
+{
+    let image_create_info = vk::ImageCreateInfo::default()
+        .image_type(vk::ImageType::TYPE_2D)
+        .extent(vk::Extent3D { width, height, depth: 1 }) // Number of texels on each axis
+        .mip_levels(mip_levels)
+        .array_layers(kind.layer_count()) // Arrays and cubemaps are 2D images with a layer per image or face
+        .format(format) // Must use same format for texels as the pixels in the image buffer
+        .tiling(vk::ImageTiling::OPTIMAL) // Texels laid out in implementation defined order for optimal access (cannot directly access texels in memory of image)
+        // Discard texels in first transition, we can do this because we first transition image to be a transfer destination so don't need to preserve texels
+        .initial_layout(vk::ImageLayout::UNDEFINED)
+        // A loaded image is destination for a buffer copy so uses TRANSFER_DST
+        // Image must be accessable from shader so also use SAMPLED
+        .usage(usage)
+        .sharing_mode(vk::SharingMode::EXCLUSIVE) // Only used by graphics queue
+        .samples(vk::SampleCountFlags::TYPE_1)
+        .flags(kind.image_create_flags());
 
-    buffers::end_single_time_commands(device, command_pool, command_buffer, graphics_queue)?;
+    let image = unsafe { device.create_image(&image_create_info, None)? };
 
-    Ok(())
+    match device.allocator.borrow_mut().allocate_image(image, vk::MemoryPropertyFlags::DEVICE_LOCAL, MemoryPurpose::Texture) {
+        Ok(allocation) => Ok((image, allocation)),
+        Err(err) => {
+            unsafe { device.destroy_image(image, None) };
+            Err(err)
+        }
+    }
 }
 
-/// Copy a staging buffer to a device-local image
-fn copy_buffer_to_image(
-    device: &ash::Device, command_pool: vk::CommandPool, graphics_queue: vk::Queue, width: u32, height: u32,
-    buffer: vk::Buffer, image: vk::Image,
-) -> Result<()>
+/// Record a copy of a staging buffer, starting at buffer_offset, to a mip level and layers of a device-local image
+///
+/// The extent is the image's, the copy covers the level's extent. The layers are tightly packed one after another in
+/// the buffer
+pub(crate) fn copy_buffer_to_image(
+    device: &ash::Device, command_buffer: vk::CommandBuffer, buffer: vk::Buffer, buffer_offset: vk::DeviceSize,
+    image: vk::Image, extent: vk::Extent2D, subresource: vk::ImageSubresourceLayers,
+)
 {
-    let command_buffer = buffers::begin_single_time_commands(device, command_pool)?;
+    let (level_width, level_height) = mip_extent(extent.width, extent.height, subresource.mip_level);
 
     // Specify which part of the buffer is going to be copied to which part of the image
     let region = vk::BufferImageCopy::default()
-        .buffer_offset(0)
+        .buffer_offset(buffer_offset)
         .buffer_row_length(0)
         .buffer_image_height(0)
-        .image_subresource(
-            vk::ImageSubresourceLayers::default()
-                .aspect_mask(vk::ImageAspectFlags::COLOR)
-                .mip_level(0)
-                .base_array_layer(0)
-                .layer_count(1),
-        )
+        .image_subresource(subresource)
         .image_offset(vk::Offset3D { x: 0, y: 0, z: 0 })
-        .image_extent(vk::Extent3D { width, height, depth: 1 });
+        .image_extent(vk::Extent3D { width: level_width, height: level_height, depth: 1 });
 
     let regions = [region];
-    unsafe {
-        device.cmd_copy_buffer_to_image(command_buffer, buffer, image, vk::ImageLayout::TRANSFER_DST_OPTIMAL, &regions)
-    };
+    unsafe { device.cmd_copy_buffer_to_image(command_buffer, buffer, image, vk::ImageLayout::TRANSFER_DST_OPTIMAL, &regions) };
+}
+
+/// The colour aspect of a mip level of the first array_layers layers of an image, for copies and blits
+pub(crate) fn colour_layers(mip_level: u32, array_layers: u32) -> vk::ImageSubresourceLayers
+{
+    vk::ImageSubresourceLayers::default()
+        .aspect_mask(vk::ImageAspectFlags::COLOR)
+        .mip_level(mip_level)
+        .base_array_layer(0)
+        .layer_count(array_layers)
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn every_half_float_round_trips()
+    {
+        for half in 0..=u16::MAX {
+            let value = f16_to_f32(half);
+            if value.is_nan() {
+                continue;
+            }
+            assert_eq!(f32_to_f16(value), half, "{:#06x} converted to {}", half, value);
+        }
+    }
+
+    #[test]
+    fn subnormal_half_floats_convert_exactly()
+    {
+        assert_eq!(f16_to_f32(0x0001), libm::exp2f(-24.0));
+        assert_eq!(f16_to_f32(0x03ff), 1023.0 * libm::exp2f(-24.0));
+        assert_eq!(f16_to_f32(0x8001), -libm::exp2f(-24.0));
+        // The smallest normal
+        assert_eq!(f32_to_f16(libm::exp2f(-14.0)), 0x0400);
+        // Just below the smallest normal rounds up to it
+        assert_eq!(f32_to_f16(libm::exp2f(-14.0) - libm::exp2f(-26.0)), 0x0400);
+    }
+
+    #[test]
+    fn tiny_floats_round_to_the_nearest_subnormal_or_zero()
+    {
+        // Half of the smallest subnormal is a tie, which goes to the even zero
+        assert_eq!(f32_to_f16(libm::exp2f(-25.0)), 0x0000);
+        assert_eq!(f32_to_f16(1.5 * libm::exp2f(-25.0)), 0x0001);
+        assert_eq!(f32_to_f16(3.0 * libm::exp2f(-25.0)), 0x0002);
+        assert_eq!(f32_to_f16(libm::exp2f(-30.0)), 0x0000);
+        assert_eq!(f32_to_f16(-libm::exp2f(-30.0)), 0x8000);
+        assert_eq!(f32_to_f16(f32::from_bits(1)), 0x0000);
+        assert_eq!(f32_to_f16(-0.0), 0x8000);
+    }
+
+    #[test]
+    fn floats_round_to_the_nearest_half_float_with_ties_to_even()
+    {
+        let ulp = libm::exp2f(-10.0);
+        assert_eq!(f32_to_f16(1.0), 0x3c00);
+        assert_eq!(f32_to_f16(1.0 + ulp * 0.25), 0x3c00);
+        assert_eq!(f32_to_f16(1.0 + ulp * 0.75), 0x3c01);
+        assert_eq!(f32_to_f16(1.0 + ulp * 0.5), 0x3c00);
+        assert_eq!(f32_to_f16(1.0 + ulp * 1.5), 0x3c02);
+        assert_eq!(f32_to_f16(1.0 + ulp * 0.5 + libm::exp2f(-20.0)), 0x3c01);
+        // Rounding up the largest mantissa carries into the exponent
+        assert_eq!(f32_to_f16(2.0 - ulp * 0.25), 0x4000);
+        assert_eq!(f32_to_f16(-1.0 - ulp * 0.75), 0xbc01);
+    }
+
+    #[test]
+    fn infinities_and_nans_are_kept()
+    {
+        assert_eq!(f32_to_f16(f32::INFINITY), 0x7c00);
+        assert_eq!(f32_to_f16(f32::NEG_INFINITY), 0xfc00);
+        assert_eq!(f16_to_f32(0x7c00), f32::INFINITY);
+        assert_eq!(f16_to_f32(0xfc00), f32::NEG_INFINITY);
 
-    buffers::end_single_time_commands(device, command_pool, command_buffer, graphics_queue)
+        let nan = f32_to_f16(f32::NAN);
+        assert_eq!(nan & 0x7c00, 0x7c00);
+        assert_ne!(nan & 0x03ff, 0);
+        assert!(f16_to_f32(0x7e00).is_nan());
+        assert!(f16_to_f32(0x7c01).is_nan());
+    }
+
+    #[test]
+    fn floats_too_large_become_infinity()
+    {
+        assert_eq!(f32_to_f16(65504.0), 0x7bff);
+        assert_eq!(f32_to_f16(65519.0), 0x7bff);
+        // Halfway between the largest half float and the next power of two rounds to the even infinity
+        assert_eq!(f32_to_f16(65520.0), 0x7c00);
+        assert_eq!(f32_to_f16(1.0e10), 0x7c00);
+        assert_eq!(f32_to_f16(-1.0e10), 0xfc00);
+        assert_eq!(f32_to_f16(f32::MAX), 0x7c00);
+    }
+
+    /// Each format with sRGB and UNORM variants, as (sRGB, UNORM)
+    const SRGB_AND_UNORM_FORMATS: [(vk::Format, vk::Format); 5] = [
+        (vk::Format::R8G8B8A8_SRGB, vk::Format::R8G8B8A8_UNORM),
+        (vk::Format::BC1_RGB_SRGB_BLOCK, vk::Format::BC1_RGB_UNORM_BLOCK),
+        (vk::Format::BC1_RGBA_SRGB_BLOCK, vk::Format::BC1_RGBA_UNORM_BLOCK),
+        (vk::Format::BC3_SRGB_BLOCK, vk::Format::BC3_UNORM_BLOCK),
+        (vk::Format::BC7_SRGB_BLOCK, vk::Format::BC7_UNORM_BLOCK),
+    ];
+
+    #[test]
+    fn linear_data_uses_the_unorm_variant_of_either_format()
+    {
+        for (srgb, unorm) in SRGB_AND_UNORM_FORMATS {
+            assert_eq!(ColourSpace::Linear.format(srgb), unorm);
+            assert_eq!(ColourSpace::Linear.format(unorm), unorm);
+        }
+    }
+
+    #[test]
+    fn srgb_colours_use_the_srgb_variant_of_either_format()
+    {
+        for (srgb, unorm) in SRGB_AND_UNORM_FORMATS {
+            assert_eq!(ColourSpace::Srgb.format(srgb), srgb);
+            assert_eq!(ColourSpace::Srgb.format(unorm), srgb);
+        }
+    }
+
+    #[test]
+    fn formats_without_an_srgb_variant_are_unchanged()
+    {
+        for format in [
+            vk::Format::BC5_UNORM_BLOCK,
+            vk::Format::R16G16B16A16_SFLOAT,
+            vk::Format::R32G32B32A32_SFLOAT,
+        ] {
+            assert_eq!(ColourSpace::Srgb.format(format), format);
+            assert_eq!(ColourSpace::Linear.format(format), format);
+        }
+    }
+
+    #[test]
+    fn changing_the_colour_space_keeps_the_texels()
+    {
+        let colours = TextureData::checkerboard("Checkerboard", 4, 2, [[255, 128, 0, 255], [0, 64, 255, 255]]);
+        let pixels = colours.pixels.clone();
+
+        let data = colours.with_colour_space(ColourSpace::Linear);
+        assert_eq!(data.format, vk::Format::R8G8B8A8_UNORM);
+        assert_eq!(data.pixels, pixels);
+
+        let colours = data.with_colour_space(ColourSpace::Srgb);
+        assert_eq!(colours.format, vk::Format::R8G8B8A8_SRGB);
+        assert_eq!(colours.pixels, pixels);
+    }
+
+    #[test]
+    fn image_files_load_as_srgb_unless_loaded_as_linear()
+    {
+        // A 2x1 greyscale PNG, such as a mask
+        let mut png = Vec::new();
+        let mut encoder = png::Encoder::new(&mut png, 2, 1);
+        encoder.set_color(png::ColorType::Grayscale);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.write_header().unwrap().write_image_data(&[0, 200]).unwrap();
+        let path = std::env::temp_dir().join(format!("colour_space_test_{}.png", std::process::id()));
+        std::fs::write(&path, png).unwrap();
+        let path = path.to_str().unwrap();
+
+        let loaders = ImageLoaders::default();
+        let colours = loaders.load(path);
+        let data = loaders.load_as(path, ColourSpace::Linear);
+        let layers = loaders.load_array("Layers", &[path, path]);
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(colours.unwrap().format, vk::Format::R8G8B8A8_SRGB);
+        let data = data.unwrap();
+        assert_eq!(data.format, vk::Format::R8G8B8A8_UNORM);
+        assert_eq!(data.pixels, [0, 0, 0, 255, 200, 200, 200, 255]);
+        let layers = layers.unwrap().with_colour_space(ColourSpace::Linear);
+        assert_eq!(layers.kind, TextureKind::Array(2));
+        assert_eq!(layers.format, vk::Format::R8G8B8A8_UNORM);
+    }
+
+    #[test]
+    fn cubemap_faces_load_in_layer_order()
+    {
+        // A 1x1 greyscale PNG of a different shade for each face
+        let paths: Vec<_> = (0..6u8)
+            .map(|face| {
+                let mut png = Vec::new();
+                let mut encoder = png::Encoder::new(&mut png, 1, 1);
+                encoder.set_color(png::ColorType::Grayscale);
+                encoder.set_depth(png::BitDepth::Eight);
+                encoder.write_header().unwrap().write_image_data(&[face * 40]).unwrap();
+                let path = std::env::temp_dir().join(format!("cubemap_test_{}_{}.png", std::process::id(), face));
+                std::fs::write(&path, png).unwrap();
+                path.to_str().unwrap().to_string()
+            })
+            .collect();
+        let face_paths: [&str; 6] = std::array::from_fn(|face| paths[face].as_str());
+
+        let cubemap = ImageLoaders::default().load_cubemap("Cubemap", &face_paths);
+        for path in &paths {
+            std::fs::remove_file(path).unwrap();
+        }
+
+        let cubemap = cubemap.unwrap();
+        assert_eq!(cubemap.kind, TextureKind::Cube);
+        assert_eq!((cubemap.width, cubemap.height), (1, 1));
+        let faces: Vec<_> = cubemap.pixels.chunks(4).collect();
+        let expected: Vec<_> = (0..6u8).map(|face| [face * 40, face * 40, face * 40, 255]).collect();
+        assert_eq!(faces, expected);
+    }
+
+    #[test]
+    fn cubemap_faces_must_be_square()
+    {
+        let faces: [TextureData; 6] = std::array::from_fn(|_| TextureData {
+            height: 1,
+            pixels: vec![0; 2 * 4],
+            ..TextureData::checkerboard("Face", 2, 1, [[0, 0, 0, 255]; 2])
+        });
+        assert!(TextureData::cubemap("Cubemap", &faces).is_err());
+    }
 }
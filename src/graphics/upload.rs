@@ -0,0 +1,530 @@
+use crate::graphics::allocator::MemoryPurpose;
+use crate::graphics::barriers::{self, ImageTransition};
+use crate::graphics::buffers::{self, GpuBuffer};
+use crate::graphics::debug::DebugUtils;
+use crate::graphics::device::{LogicalDevice, SupportedPhysicalDevice};
+use crate::graphics::errors::VkAppError;
+use crate::graphics::textures;
+use crate::graphics::vk_app::Result;
+use crate::log;
+use ash::vk;
+use std::any::Any;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+/// Size of the persistently mapped staging ring, uploads larger than this get their own temporary staging buffer
+const STAGING_RING_SIZE: usize = 32 * 1024 * 1024;
+
+/// Identifies a submitted batch of uploads, to wait until its copies have finished
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct UploadTicket(u64);
+
+/// A command buffer of copies recorded together and submitted together
+struct Batch
+{
+    id:                u64,
+    command_buffer:    vk::CommandBuffer,
+    fence:             vk::Fence,
+    /// Where the batch's last staging allocation ends in the ring, the ring space before it is free once the batch completes
+    ring_end:          Option<usize>,
+    /// Staging buffers for uploads too large for the ring, destroyed once the batch completes
+    temporary_buffers: Vec<GpuBuffer<u8>>,
+    /// Other resources the batch's commands use, dropped once the batch completes
+    retained:          Vec<Box<dyn Any>>,
+    copy_count:        usize,
+    staged_bytes:      usize,
+}
+
+/// Records buffer and image uploads into batches which are submitted in a single submission
+///
+/// Data is written to a persistently mapped staging ring buffer and copied to its destination by the GPU. Each submitted
+/// batch signals a fence, the ring space it used is reclaimed once the fence is signalled. Uploading only waits when
+/// the ring is full or when a ticket is explicitly waited on, rather than after every resource
+pub struct UploadContext
+{
+    device:         Rc<LogicalDevice>,
+    debug_utils:    DebugUtils,
+    queue:          vk::Queue,
+    command_pool:   vk::CommandPool,
+    ring:           GpuBuffer<u8>,
+    /// Offsets into the ring satisfy optimalBufferCopyOffsetAlignment and the texel size of any format we copy
+    ring_alignment: usize,
+    /// Offset of the oldest staging data still in use
+    ring_tail:      usize,
+    /// Offset after the newest staging data
+    ring_head:      usize,
+    recording:      Option<Batch>,
+    /// Submitted batches in submission order
+    in_flight:      VecDeque<Batch>,
+    /// Command buffers and fences of completed batches, reused for new batches
+    free_batches:   Vec<(vk::CommandBuffer, vk::Fence)>,
+    next_batch_id:  u64,
+}
+
+impl Drop for UploadContext
+{
+    fn drop(&mut self)
+    {
+        // Uploads still in flight must finish before their command buffers and staging memory are destroyed
+        if let Err(err) = self.wait_idle() {
+            log!("Failed to wait for uploads to finish: {}", err);
+        }
+
+        unsafe {
+            // Batches are only left in flight or recording if waiting failed, e.g the device was lost
+            let batches = self.in_flight.drain(..).chain(self.recording.take());
+            for fence in batches
+                .map(|batch| batch.fence)
+                .chain(self.free_batches.drain(..).map(|(_, fence)| fence))
+            {
+                self.device.destroy_fence(fence, None);
+            }
+            // Also frees every command buffer allocated from the pool
+            self.device.destroy_command_pool(self.command_pool, None);
+        }
+        // The ring buffer is destroyed when the fields are dropped
+    }
+}
+
+impl UploadContext
+{
+    pub fn new(
+        instance: &ash::Instance, device: &Rc<LogicalDevice>, debug_utils: &DebugUtils,
+        physical_device: &SupportedPhysicalDevice, queue: vk::Queue,
+    ) -> Result<Self>
+    {
+        let properties = unsafe { instance.get_physical_device_properties(physical_device.vk_physical_device) };
+        // 16 bytes covers the largest texel we upload (R32G32B32A32), and is a multiple of every smaller texel size
+        let ring_alignment = (properties.limits.optimal_buffer_copy_offset_alignment as usize).max(16);
+
+        // TRANSIENT hints that the command buffers are short lived, RESET_COMMAND_BUFFER lets us reuse them individually
+        let command_pool_create_info = vk::CommandPoolCreateInfo::default()
+            .flags(vk::CommandPoolCreateFlags::TRANSIENT | vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER)
+            .queue_family_index(physical_device.graphics_family_index);
+        let command_pool = unsafe { device.create_command_pool(&command_pool_create_info, None) }?;
+        debug_utils.set_name(command_pool, "Upload command pool");
+
+        let ring = match create_staging_buffer(device, STAGING_RING_SIZE) {
+            Ok(ring) => ring,
+            Err(err) => {
+                unsafe { device.destroy_command_pool(command_pool, None) };
+                return Err(err);
+            }
+        };
+        ring.set_name(debug_utils, "Staging ring buffer");
+
+        Ok(Self {
+            device: device.clone(),
+            debug_utils: debug_utils.clone(),
+            queue,
+            command_pool,
+            ring,
+            ring_alignment,
+            ring_tail: 0,
+            ring_head: 0,
+            recording: None,
+            in_flight: VecDeque::new(),
+            free_batches: Vec::new(),
+            next_batch_id: 1,
+        })
+    }
+
+    /// Record a copy of data to the start of a device local buffer
+    ///
+    /// The buffer must not be used until the ticket returned by the next flush has completed
+    pub fn upload_buffer<T: Copy>(&mut self, data: &[T], dst_buffer: &GpuBuffer<T>) -> Result<()>
+    {
+        if !dst_buffer.usage().contains(vk::BufferUsageFlags::TRANSFER_DST) {
+            return Err(VkAppError::DeviceError(String::from(
+                "Upload destination buffer must have TRANSFER_DST usage",
+            )));
+        }
+        if data.len() > dst_buffer.count() {
+            return Err(VkAppError::DeviceError(format!(
+                "Cannot upload {} elements to a buffer of {} elements",
+                data.len(),
+                dst_buffer.count()
+            )));
+        }
+
+        let (staging_buffer, staging_offset) = self.stage(buffers::as_bytes(data))?;
+        let command_buffer = self.recording_command_buffer()?;
+
+        let copy_region = vk::BufferCopy::default()
+            .src_offset(staging_offset as vk::DeviceSize)
+            .dst_offset(0)
+            .size(size_of_val(data) as vk::DeviceSize);
+
+        // Make the copy visible to anything reading the buffer after the batch, e.g vertex input or shaders
+        let barrier = vk::BufferMemoryBarrier::default()
+            .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .dst_access_mask(vk::AccessFlags::MEMORY_READ)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .buffer(dst_buffer.handle())
+            .offset(0)
+            .size(vk::WHOLE_SIZE);
+
+        unsafe {
+            self.device
+                .cmd_copy_buffer(command_buffer, staging_buffer, dst_buffer.handle(), &[copy_region]);
+            self.device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::ALL_COMMANDS,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[barrier],
+                &[],
+            );
+        }
+
+        self.record_copy(size_of_val(data));
+        Ok(())
+    }
+
+    /// Record a copy of tightly packed pixels to the base level of an image's first array_layers layers and the filling
+    /// of their other mip levels, leaving them ready to be sampled from shaders
+    ///
+    /// The pixels and each level of the mip chain hold every layer one after another. The image's previous contents are
+    /// discarded. The image must not be used until the ticket returned by the next flush has completed
+    pub fn upload_image(
+        &mut self, pixels: &[u8], image: vk::Image, width: u32, height: u32, array_layers: u32,
+        mip_chain: textures::MipChain,
+    ) -> Result<()>
+    {
+        let mip_levels = mip_chain.level_count();
+
+        // Every level is staged together so the copies share one staging allocation, each level's pixels are a
+        // multiple of the texel size so the offsets after the first stay aligned
+        let (staging_buffer, staging_offset, staged_bytes) = match mip_chain {
+            textures::MipChain::Blit(_) => {
+                let (staging_buffer, staging_offset) = self.stage(pixels)?;
+                (staging_buffer, staging_offset, pixels.len())
+            }
+            textures::MipChain::Levels(levels) => {
+                let bytes: Vec<u8> = std::iter::once(pixels)
+                    .chain(levels.iter().map(Vec::as_slice))
+                    .flatten()
+                    .copied()
+                    .collect();
+                let (staging_buffer, staging_offset) = self.stage(&bytes)?;
+                (staging_buffer, staging_offset, bytes.len())
+            }
+        };
+        let command_buffer = self.recording_command_buffer()?;
+
+        // Transition the image to be able to copy the staging buffer to it
+        let to_transfer_dst =
+            ImageTransition::colour(image, vk::ImageLayout::UNDEFINED, vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+                .mips(0..mip_levels)
+                .layers(0..array_layers);
+        barriers::cmd_transition_images(&self.device, command_buffer, &[to_transfer_dst])?;
+
+        let extent = vk::Extent2D { width, height };
+        textures::copy_buffer_to_image(
+            &self.device,
+            command_buffer,
+            staging_buffer,
+            staging_offset as vk::DeviceSize,
+            image,
+            extent,
+            textures::colour_layers(0, array_layers),
+        );
+
+        match mip_chain {
+            // The blits leave every level readable from a shader
+            textures::MipChain::Blit(_) => {
+                textures::cmd_generate_mipmaps(&self.device, command_buffer, image, width, height, mip_levels, array_layers)?
+            }
+            textures::MipChain::Levels(levels) => {
+                let mut level_offset = staging_offset + pixels.len();
+                for (level, level_pixels) in (1..).zip(levels) {
+                    textures::copy_buffer_to_image(
+                        &self.device,
+                        command_buffer,
+                        staging_buffer,
+                        level_offset as vk::DeviceSize,
+                        image,
+                        extent,
+                        textures::colour_layers(level, array_layers),
+                    );
+                    level_offset += level_pixels.len();
+                }
+
+                // Transition the image from being a transfer destination to being readable from a shader
+                let to_shader_read = ImageTransition::colour(
+                    image,
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                )
+                .mips(0..mip_levels)
+                .layers(0..array_layers);
+                barriers::cmd_transition_images(&self.device, command_buffer, &[to_shader_read])?;
+            }
+        }
+
+        self.record_copy(staged_bytes);
+        Ok(())
+    }
+
+    /// The command buffer of the batch being recorded, for recording work that goes with the uploads such as a dispatch
+    /// converting an uploaded image
+    ///
+    /// Anything the commands use must live until the batch completes, see retain_until_complete
+    pub fn command_buffer(&mut self) -> Result<vk::CommandBuffer> { self.recording_command_buffer() }
+
+    /// Keep a resource used by the commands recorded so far alive until the batch being recorded completes, then drop it
+    pub fn retain_until_complete(&mut self, resource: impl Any) -> Result<()>
+    {
+        self.recording_batch()?.retained.push(Box::new(resource));
+        Ok(())
+    }
+
+    /// Submit every upload recorded since the last flush in a single submission
+    ///
+    /// The returned ticket completes once those uploads, and every upload submitted before them, have finished
+    pub fn flush(&mut self) -> Result<UploadTicket>
+    {
+        let Some(batch) = self.recording.take() else {
+            // Nothing new was recorded so the latest submitted batch covers every upload
+            return Ok(UploadTicket(self.next_batch_id - 1));
+        };
+
+        self.debug_utils.end_label(batch.command_buffer);
+        let command_buffers = [batch.command_buffer];
+        let submit_info = vk::SubmitInfo::default().command_buffers(&command_buffers);
+
+        let submitted = unsafe {
+            self.device
+                .end_command_buffer(batch.command_buffer)
+                .and_then(|_| self.device.queue_submit(self.queue, &[submit_info], batch.fence))
+        };
+        if let Err(err) = submitted {
+            // The batch never reached the queue so its resources can be reused straight away
+            self.release(batch);
+            return Err(err.into());
+        }
+
+        log!(
+            "Submitted upload batch {} with {} copies, {:.2} MiB staged",
+            batch.id,
+            batch.copy_count,
+            batch.staged_bytes as f64 / (1024.0 * 1024.0)
+        );
+
+        let ticket = UploadTicket(batch.id);
+        self.in_flight.push_back(batch);
+        Ok(ticket)
+    }
+
+    /// Block until the uploads covered by the ticket have finished
+    pub fn wait(&mut self, ticket: UploadTicket) -> Result<()>
+    {
+        while self.in_flight.front().is_some_and(|batch| batch.id <= ticket.0) {
+            self.retire_oldest()?;
+        }
+        Ok(())
+    }
+
+    /// Whether the uploads covered by the ticket have finished, without blocking
+    pub fn is_complete(&mut self, ticket: UploadTicket) -> Result<bool>
+    {
+        self.retire_completed()?;
+        Ok(self.in_flight.front().is_none_or(|batch| batch.id > ticket.0))
+    }
+
+    /// Submit any recorded uploads and block until every upload has finished
+    pub fn wait_idle(&mut self) -> Result<()>
+    {
+        let ticket = self.flush()?;
+        self.wait(ticket)
+    }
+
+    /// Copy bytes to staging memory, returning the staging buffer and the offset of the bytes within it
+    fn stage(&mut self, bytes: &[u8]) -> Result<(vk::Buffer, usize)>
+    {
+        if bytes.is_empty() {
+            return Err(VkAppError::DeviceError(String::from("Cannot upload empty data")));
+        }
+
+        if bytes.len() > STAGING_RING_SIZE {
+            // Too large to ever fit in the ring, so give it a staging buffer of its own which lives as long as the batch
+            let staging_buffer = create_staging_buffer(&self.device, bytes.len())?;
+            staging_buffer.set_name(&self.debug_utils, "Temporary staging buffer");
+            staging_buffer.write(bytes)?;
+
+            let handle = staging_buffer.handle();
+            self.recording_batch()?.temporary_buffers.push(staging_buffer);
+            return Ok((handle, 0));
+        }
+
+        self.retire_completed()?;
+        let offset = loop {
+            if let Some(offset) = self.ring_allocate(bytes.len()) {
+                break offset;
+            }
+            // The ring is full, so make room by waiting for the oldest batch using it
+            if self.in_flight.is_empty() {
+                // Only the batch being recorded is using the ring, so it has to be submitted before anything is freed
+                self.flush()?;
+            }
+            self.retire_oldest()?;
+        };
+
+        // The ring memory is host coherent, so the write is visible to the GPU once the batch is submitted
+        self.ring.write_at(offset, bytes)?;
+        self.ring_head = offset + bytes.len();
+        self.recording_batch()?.ring_end = Some(self.ring_head);
+
+        Ok((self.ring.handle(), offset))
+    }
+
+    /// Find space for size bytes in the ring, the space is only claimed once ring_head is moved past it
+    fn ring_allocate(&mut self, size: usize) -> Option<usize>
+    {
+        if self.ring_is_empty() {
+            self.ring_tail = 0;
+            self.ring_head = 0;
+        }
+
+        let offset = self.ring_head.next_multiple_of(self.ring_alignment);
+        if self.ring_is_empty() || self.ring_head > self.ring_tail {
+            // The used space is [tail, head) so there is free space after the head, and before the tail if we wrap around
+            if offset + size <= STAGING_RING_SIZE {
+                Some(offset)
+            } else if size <= self.ring_tail {
+                Some(0)
+            } else {
+                None
+            }
+        } else if offset + size <= self.ring_tail {
+            // The used space has wrapped around so the only free space is between the head and the tail
+            Some(offset)
+        } else {
+            None
+        }
+    }
+
+    fn ring_is_empty(&self) -> bool
+    {
+        self.recording.as_ref().is_none_or(|batch| batch.ring_end.is_none())
+            && self.in_flight.iter().all(|batch| batch.ring_end.is_none())
+    }
+
+    /// The batch currently being recorded, starting a new one if needed
+    fn recording_batch(&mut self) -> Result<&mut Batch>
+    {
+        if self.recording.is_none() {
+            let batch = self.begin_batch()?;
+            self.recording = Some(batch);
+        }
+        Ok(self.recording.as_mut().unwrap())
+    }
+
+    fn recording_command_buffer(&mut self) -> Result<vk::CommandBuffer> { Ok(self.recording_batch()?.command_buffer) }
+
+    fn record_copy(&mut self, size: usize)
+    {
+        if let Some(batch) = self.recording.as_mut() {
+            batch.copy_count += 1;
+            batch.staged_bytes += size;
+        }
+    }
+
+    fn begin_batch(&mut self) -> Result<Batch>
+    {
+        let id = self.next_batch_id;
+
+        let (command_buffer, fence) = match self.free_batches.pop() {
+            Some((command_buffer, fence)) => {
+                unsafe { self.device.reset_fences(&[fence]) }?;
+                (command_buffer, fence)
+            }
+            None => {
+                let allocate_info = vk::CommandBufferAllocateInfo::default()
+                    .level(vk::CommandBufferLevel::PRIMARY)
+                    .command_pool(self.command_pool)
+                    .command_buffer_count(1);
+                let command_buffer = unsafe { self.device.allocate_command_buffers(&allocate_info) }?[0];
+                let fence = unsafe { self.device.create_fence(&vk::FenceCreateInfo::default(), None) }?;
+                (command_buffer, fence)
+            }
+        };
+        self.debug_utils
+            .set_name(command_buffer, format!("Upload batch {}", id).as_str());
+
+        // Beginning a command buffer from a pool with RESET_COMMAND_BUFFER implicitly resets it
+        let command_buffer_begin_info =
+            vk::CommandBufferBeginInfo::default().flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+        if let Err(err) = unsafe { self.device.begin_command_buffer(command_buffer, &command_buffer_begin_info) } {
+            self.free_batches.push((command_buffer, fence));
+            return Err(err.into());
+        }
+        self.debug_utils
+            .begin_label(command_buffer, format!("Upload batch {}", id).as_str());
+
+        self.next_batch_id += 1;
+        Ok(Batch {
+            id,
+            command_buffer,
+            fence,
+            ring_end: None,
+            temporary_buffers: Vec::new(),
+            retained: Vec::new(),
+            copy_count: 0,
+            staged_bytes: 0,
+        })
+    }
+
+    /// Free the staging space of every batch that has already finished, without blocking
+    fn retire_completed(&mut self) -> Result<()>
+    {
+        while let Some(batch) = self.in_flight.front() {
+            if !unsafe { self.device.get_fence_status(batch.fence) }? {
+                break;
+            }
+            self.retire_oldest()?;
+        }
+        Ok(())
+    }
+
+    /// Wait for the oldest submitted batch to finish and free its staging space
+    fn retire_oldest(&mut self) -> Result<()>
+    {
+        let Some(batch) = self.in_flight.front() else {
+            return Ok(());
+        };
+        unsafe { self.device.wait_for_fences(&[batch.fence], true, u64::MAX) }?;
+
+        let batch = self.in_flight.pop_front().unwrap();
+        // Batches finish in submission order so everything before this batch's staging data is free
+        if let Some(ring_end) = batch.ring_end {
+            self.ring_tail = ring_end;
+        }
+        self.release(batch);
+        Ok(())
+    }
+
+    fn release(&mut self, batch: Batch)
+    {
+        // Dropping the batch destroys its temporary staging buffers and the resources it retained
+        self.free_batches.push((batch.command_buffer, batch.fence));
+    }
+}
+
+/// Create a host visible buffer to copy data from to a device local buffer or image
+fn create_staging_buffer(device: &Rc<LogicalDevice>, size: usize) -> Result<GpuBuffer<u8>>
+{
+    GpuBuffer::new(
+        device,
+        size,
+        vk::BufferUsageFlags::TRANSFER_SRC,
+        /*  HOST_VISIBLE lets us map the memory so we can write to it from the CPU
+           HOST_COHERENT ensures the mapped memory always matches the contents of the allocated memory
+           Useful because driver may not immediately copy data into buffer memory
+        */
+        vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+        MemoryPurpose::Staging,
+    )
+}
@@ -1,8 +1,10 @@
 use crate::graphics::*;
-use crate::{log, project};
+use crate::maths::{matrix, vector};
+use crate::{log, warn};
 use ash::vk;
 
 #[repr(C)]
+#[derive(Copy, Clone)]
 pub struct Vertex
 {
     pub position:  [f32; 3],
@@ -35,36 +37,63 @@ pub const VERTICES: [Vertex; 4] = [
 
 pub const INDICES: [u16; 6] = [0, 1, 2, 2, 3, 0];
 
+pub const TRIANGLE_VERTICES: [Vertex; 3] = [
+    Vertex {
+        position:  [0.0, -0.5, 0.0],
+        colour:    [1.0, 0.0, 0.0],
+        tex_coord: [0.5, 0.0],
+    },
+    Vertex {
+        position:  [0.5, 0.5, 0.0],
+        colour:    [0.0, 1.0, 0.0],
+        tex_coord: [1.0, 1.0],
+    },
+    Vertex {
+        position:  [-0.5, 0.5, 0.0],
+        colour:    [0.0, 0.0, 1.0],
+        tex_coord: [0.0, 1.0],
+    },
+];
+
+pub const TRIANGLE_INDICES: [u32; 3] = [0, 1, 2];
+
+/// Images of the faces of a cubemap, in the order +X, -X, +Y, -Y, +Z, -Z, drawn as the sky in place of sky.hdr when
+/// they are all present
+const SKY_FACES: [&str; 6] = [
+    "sky_px.png",
+    "sky_nx.png",
+    "sky_py.png",
+    "sky_ny.png",
+    "sky_pz.png",
+    "sky_nz.png",
+];
+
 pub type Result<T> = std::result::Result<T, errors::VkAppError>;
 
 pub struct VkApp
 {
-    _entry:                 ash::Entry, // For loading vulkan, must have same lifetime as struct
-    instance:               ash::Instance,
-    debug_utils_loader:     ash::ext::debug_utils::Instance,
-    debug_callback:         vk::DebugUtilsMessengerEXT,
-    physical_device:        device::SupportedPhysicalDevice,
-    surface:                presentation::Surface,
-    device:                 ash::Device,
-    graphics_queue:         vk::Queue,
-    present_queue:          vk::Queue,
-    swapchain:              presentation::Swapchain,
-    pipeline:               pipeline::Pipeline,
-    command_pool:           vk::CommandPool,
-    texture_image:          vk::Image,
-    texture_image_memory:   vk::DeviceMemory,
-    texture_image_view:     vk::ImageView,
-    texture_sampler:        vk::Sampler,
-    vertex_buffer:          buffers::Buffer,
-    index_buffer:           buffers::Buffer,
-    uniform_buffers:        Vec<buffers::Buffer>,
-    uniform_buffers_mapped: Vec<*mut std::ffi::c_void>,
-    descriptor_pool:        vk::DescriptorPool,
-    descriptor_sets:        Vec<vk::DescriptorSet>,
-    command_buffers:        Vec<vk::CommandBuffer>,
-    sync_objects:           commands::SyncObjects,
-    // current_frame keeps track of the index to use the right objects (command buffers, semaphores)
-    current_frame:          usize,
+    _entry:               ash::Entry, // For loading vulkan, must have same lifetime as struct
+    instance:             ash::Instance,
+    api_version:          u32,
+    debug_utils_loader:   ash::ext::debug_utils::Instance,
+    debug_callback:       vk::DebugUtilsMessengerEXT,
+    surface_loader:       ash::khr::surface::Instance,
+    vk_surface:           vk::SurfaceKHR,
+    // Holds CPU-side copies of the textures, like the meshes, so the renderer can be rebuilt after the device is lost
+    texture_manager:      texture_manager::TextureManager,
+    _textures:            Vec<texture_manager::TextureHandle>, // The textures the objects draw stay loaded while their handles are held
+    // An equirectangular HDR image drawn behind the objects as a skybox
+    sky:                  textures::TextureData,
+    // An array texture with a sprite's coverage mask in each layer, drawn at the particles
+    particle_sprites:     textures::TextureData,
+    // CPU-side copies of GPU resources so the renderer can be rebuilt after the device is lost
+    meshes:               Vec<mesh::MeshData<Vertex>>,
+    // Each object draws one of the meshes
+    objects:              Vec<mesh::DrawObject>,
+    // None only if rebuilding the renderer failed
+    renderer:             Option<renderer::Renderer>,
+    // Report the device as lost on the next frame, for testing recovery
+    simulate_device_loss: bool,
 }
 
 impl Drop for VkApp
@@ -72,32 +101,13 @@ impl Drop for VkApp
     fn drop(&mut self)
     {
         log!("Cleaning up VkApp");
+        if let Some(snapshot) = self.memory_snapshot() {
+            log!("GPU memory at shutdown:\n{}", snapshot);
+        }
+        // The renderer owns the device and everything created from it so must be destroyed before the instance
+        self.renderer = None;
         unsafe {
-            if self.device.handle() != vk::Device::null() {
-                self.device.device_wait_idle().unwrap(); // TODO should be unwrap?
-            }
-
-            self.swapchain.cleanup(&self.device);
-
-            self.device.destroy_sampler(self.texture_sampler, None);
-            self.device.destroy_image_view(self.texture_image_view, None);
-            self.device.destroy_image(self.texture_image, None);
-            self.device.free_memory(self.texture_image_memory, None);
-
-            for uniform_buffer in &self.uniform_buffers {
-                uniform_buffer.cleanup(&self.device);
-            }
-
-            self.device.destroy_descriptor_pool(self.descriptor_pool, None);
-
-            self.vertex_buffer.cleanup(&self.device);
-            self.index_buffer.cleanup(&self.device);
-
-            self.pipeline.cleanup(&self.device);
-            self.sync_objects.cleanup(&self.device);
-            self.device.destroy_command_pool(self.command_pool, None);
-            self.device.destroy_device(None);
-            self.surface.loader.destroy_surface(self.surface.vk_surface, None);
+            self.surface_loader.destroy_surface(self.vk_surface, None);
             self.debug_utils_loader
                 .destroy_debug_utils_messenger(self.debug_callback, None);
             self.instance.destroy_instance(None);
@@ -110,235 +120,180 @@ impl VkApp
 {
     pub fn new(hwnd: &windows::Win32::Foundation::HWND, h_instance: &windows::Win32::Foundation::HINSTANCE) -> Result<Self>
     {
-        //let entry = unsafe { ash::Entry::load().unwrap() };
-        let entry = ash::Entry::linked(); // Dev only
-        let instance = device::create_instance(&entry)?;
+        let entry = device::create_entry()?;
+        let api_version = device::get_instance_api_version(&entry)?;
+        let instance = device::create_instance(&entry, api_version)?;
         let (debug_utils_loader, debug_callback) = device::create_debug_messenger(&entry, &instance)?;
         let (surface_loader, vk_surface) = presentation::create_surface(&entry, &instance, hwnd, h_instance)?;
 
-        // Just get the first device
-        let (physical_device, surface_details) =
-            match device::get_physical_devices(&instance, &surface_loader, vk_surface)?.get(0) {
-                Some((physical_device, surface_details)) => {
-                    log!("Selected device {}", physical_device.device_name);
-                    (physical_device.to_owned(), surface_details.to_owned())
-                }
-                None => return Err(errors::VkAppError::DeviceError(String::from("No supported devices"))),
-            };
-
-        let device = device::create_logical_device(&instance, &physical_device)?;
-
-        let surface = presentation::Surface { loader: surface_loader, vk_surface, details: surface_details };
-
-        let (graphics_queue, present_queue) = unsafe {
-            (
-                device.get_device_queue(physical_device.graphics_family_index, 0),
-                device.get_device_queue(physical_device.present_family_index, 0),
+        // The sky and the particle sprites are needed before the first frame so are loaded straight away, they are only
+        // decoration so placeholders are drawn in place of files that can't be loaded
+        let image_loaders = textures::ImageLoaders::default();
+        let sky = if SKY_FACES.iter().all(|face| std::path::Path::new(face).exists()) {
+            image_loaders.load_cubemap("Sky", &SKY_FACES)
+        } else {
+            image_loaders.load("sky.hdr")
+        }
+        .unwrap_or_else(|err| {
+            warn!("Failed to load the sky: {}", err);
+            streaming::placeholder("Sky", samplers::SamplerDesc::default())
+        });
+        // The sprites are masks of how much of each texel they cover, which is data rather than colour
+        let particle_sprites = image_loaders
+            .load_array(
+                "Particle sprites",
+                &["particle_spark.png", "particle_ring.png", "particle_cross.png"],
             )
-        };
-
-        let mut swapchain = presentation::create_swapchain(&instance, &device, &physical_device, &surface)?;
-        let pipeline = pipeline::create_pipeline(&device, swapchain.settings)?;
-        swapchain.create_framebuffers(&device, &pipeline)?;
-
-        let command_pool = commands::create_command_pool(&device, physical_device.graphics_family_index)?;
-
-        let (texture_image, texture_image_memory) = textures::create_texture_image(
-            &instance,
-            physical_device.vk_physical_device,
-            &device,
-            command_pool,
-            graphics_queue,
+            .or_else(|err| {
+                warn!("Failed to load the particle sprites: {}", err);
+                let placeholder = streaming::placeholder("Particle sprites", samplers::SamplerDesc::default());
+                textures::TextureData::array("Particle sprites", &[placeholder])
+            })?
+            .with_colour_space(textures::ColourSpace::Linear);
+
+        // The cobblestones are decoded in the background and drawn with a placeholder until they are ready
+        let mut texture_manager = texture_manager::TextureManager::new(image_loaders)?;
+        let cobblestones = texture_manager.load(
+            None,
             "cobble1.png",
+            textures::ColourSpace::Srgb,
+            samplers::SamplerDesc::default(),
         )?;
-
-        let texture_image_view = textures::create_texture_image_view(&device, texture_image)?;
-
-        let texture_sampler = textures::create_texture_sampler(&instance, &device, physical_device.vk_physical_device)?;
-
-        let vertex_buffer = buffers::create_vertex_buffer(
-            &instance,
-            physical_device.vk_physical_device,
-            &device,
-            command_pool,
-            graphics_queue,
+        // Nearest filtering keeps the cells' edges sharp
+        let checkerboard = texture_manager.insert(
+            None,
+            textures::TextureData {
+                sampler: samplers::SamplerDesc::nearest(),
+                ..textures::TextureData::checkerboard("Checkerboard", 64, 8, [[255, 255, 255, 255], [64, 64, 64, 255]])
+            },
         )?;
 
-        let index_buffer = buffers::create_index_buffer(
-            &instance,
-            physical_device.vk_physical_device,
-            &device,
-            command_pool,
-            graphics_queue,
-        )?;
-
-        let (uniform_buffers, uniform_buffers_mapped) =
-            buffers::create_uniform_buffers(&instance, physical_device.vk_physical_device, &device)?;
-
-        let descriptor_pool = buffers::create_descriptor_pool(&device)?;
-
-        let descriptor_sets = buffers::create_descriptor_sets(
-            &device,
-            descriptor_pool,
-            &uniform_buffers,
-            pipeline.descriptor_set_layout,
-            texture_image_view,
-            texture_sampler,
-        )?;
-
-        let command_buffers = commands::create_command_buffers(&device, command_pool)?;
-
-        let sync_objects = commands::create_sync_objects(&device)?;
+        // Differently textured quads either side of a triangle showing the particles
+        let objects = [
+            (0, cobblestones.index(), -1.5),
+            (1, renderer::PARTICLE_TEXTURE, 0.0),
+            (0, checkerboard.index(), 1.5),
+        ]
+        .into_iter()
+        .map(|(mesh, texture, x)| mesh::DrawObject {
+            mesh,
+            texture,
+            transform: matrix::Matrix4f::translation_matrix(vector::Vector3f::new([x, 0.0, 5.0])),
+        })
+        .collect();
 
-        Ok(Self {
+        let mut vk_app = Self {
             _entry: entry,
             instance,
+            api_version,
             debug_utils_loader,
             debug_callback,
-            surface,
-            physical_device,
-            device,
-            graphics_queue,
-            present_queue,
-            swapchain,
-            pipeline,
-            command_pool,
-            texture_image,
-            texture_image_memory,
-            texture_image_view,
-            texture_sampler,
-            vertex_buffer,
-            index_buffer,
-            uniform_buffers,
-            uniform_buffers_mapped,
-            descriptor_pool,
-            descriptor_sets,
-            command_buffers,
-            sync_objects,
-            current_frame: 0,
-        })
+            surface_loader,
+            vk_surface,
+            texture_manager,
+            _textures: vec![cobblestones, checkerboard],
+            sky,
+            particle_sprites,
+            meshes: vec![
+                mesh::MeshData {
+                    name:     String::from("Quad"),
+                    vertices: VERTICES.to_vec(),
+                    indices:  mesh::IndexData::U16(INDICES.to_vec()),
+                },
+                mesh::MeshData {
+                    name:     String::from("Triangle"),
+                    vertices: TRIANGLE_VERTICES.to_vec(),
+                    indices:  mesh::IndexData::U32(TRIANGLE_INDICES.to_vec()),
+                },
+            ],
+            objects,
+            renderer: None,
+            simulate_device_loss: false,
+        };
+        vk_app.renderer = Some(vk_app.create_renderer()?);
+
+        Ok(vk_app)
     }
 
-    pub fn draw_frame(&mut self) -> Result<()>
+    /// Select a physical device and create the renderer for it
+    fn create_renderer(&self) -> Result<renderer::Renderer>
     {
-        unsafe {
-            // Wait until the current previous frame has finished
-            self.device
-                .wait_for_fences(&[self.sync_objects.in_flight_fences[self.current_frame]], true, u64::MAX)?;
-
-            // Acquire an image from the swapchain
-            let (image_index, suboptimal_surface) = match self.swapchain.swapchain_device.acquire_next_image(
-                self.swapchain.vk_swapchain,
-                u64::MAX, // Disable timeout for images to become available
-                self.sync_objects.image_available_semaphores[self.current_frame], // Synchronization object for when presentation execution has finished using the image
-                vk::Fence::null(),
-            ) {
-                Ok((image_index, suboptimal_surface)) => (image_index, suboptimal_surface),
-                Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => {
-                    // Swapchain has become incompatible with surface and can no longer be used for rendering, must be recreated and try again in next draw
-                    self.recreate_swapchain()?;
-                    return Ok(());
+        // Just get the first device
+        let (physical_device, surface_details) =
+            match device::get_physical_devices(&self.instance, self.api_version, &self.surface_loader, self.vk_surface)?
+                .into_iter()
+                .next()
+            {
+                Some((physical_device, surface_details)) => {
+                    log!(
+                        "Selected device {} using Vulkan {}",
+                        physical_device.device_name,
+                        device::version_string(physical_device.api_version)
+                    );
+                    (physical_device, surface_details)
                 }
-                Err(err) => return Err(err.into()),
+                None => return Err(errors::VkAppError::DeviceError(String::from("No supported devices"))),
             };
 
-            buffers::update_uniform_buffer(&self.uniform_buffers_mapped, self.current_frame);
-
-            // Only reset the fence if we are sure we are submitting work to prevent deadlock
-            self.device
-                .reset_fences(&[self.sync_objects.in_flight_fences[self.current_frame]])?;
-
-            self.device
-                .reset_command_buffer(self.command_buffers[self.current_frame], vk::CommandBufferResetFlags::empty())?;
-
-            commands::record_command_buffer(
-                &self.device,
-                self.command_buffers[self.current_frame],
-                image_index,
-                &self.pipeline,
-                &self.swapchain,
-                self.vertex_buffer.buffer,
-                self.index_buffer.buffer,
-                vec![self.descriptor_sets[self.current_frame]],
-            )?;
-
-            // Semaphores to wait on before execution begins
-            let wait_semaphores: [vk::Semaphore; 1] = [self.sync_objects.image_available_semaphores[self.current_frame]];
-            // Which stage of the pipeline to wait on. We wait at the point of writing colours to the image until its available
-            let wait_stages: [vk::PipelineStageFlags; 1] = [vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT];
-            // Which semaphores to signal once the command buffer has finished execution
-            let signal_semaphores: [vk::Semaphore; 1] = [self.sync_objects.render_finished_semaphores[self.current_frame]];
-
-            let command_buffers = [self.command_buffers[self.current_frame]];
-
-            let submit_info = vk::SubmitInfo::default()
-                .wait_semaphores(&wait_semaphores)
-                .wait_dst_stage_mask(&wait_stages)
-                .command_buffers(&command_buffers)
-                .signal_semaphores(&signal_semaphores);
-
-            self.device.queue_submit(
-                self.graphics_queue,
-                [submit_info].as_slice(),
-                self.sync_objects.in_flight_fences[self.current_frame],
-            )?;
+        let surface = presentation::Surface {
+            loader:     self.surface_loader.clone(),
+            vk_surface: self.vk_surface,
+            details:    surface_details,
+        };
 
-            // Finally, submit the result of the render pass back to the swapchain for presentation
-            let image_indices = [image_index];
-            let swapchains = [self.swapchain.vk_swapchain];
-            let present_info = vk::PresentInfoKHR::default()
-                .wait_semaphores(&signal_semaphores)
-                .image_indices(&image_indices)
-                .swapchains(&swapchains);
+        renderer::Renderer::new(
+            &self.instance,
+            physical_device,
+            surface,
+            &self.texture_manager.textures(),
+            &self.meshes,
+            &self.sky,
+            &self.particle_sprites,
+        )
+    }
 
-            self.swapchain
-                .swapchain_device
-                .queue_present(self.present_queue, &present_info)?;
+    pub fn draw_frame(&mut self) -> Result<()>
+    {
+        let renderer = self
+            .renderer
+            .as_mut()
+            .ok_or_else(|| errors::VkAppError::DeviceError(String::from("No renderer to draw with")))?;
+
+        let result = if std::mem::take(&mut self.simulate_device_loss) {
+            Err(errors::VkAppError::VkError(vk::Result::ERROR_DEVICE_LOST))
+        } else {
+            self.texture_manager
+                .update(renderer)
+                .and_then(|_| renderer.draw_frame(&self.objects))
+        };
 
-            // A suboptimal surface is considered a success code and we have acquired an image successfully
-            // So recreate it after presenting the image
-            if suboptimal_surface {
-                log!("Suboptimal surface");
-                self.recreate_swapchain()?;
-            }
+        match result {
+            Err(err) if err.is_device_lost() => self.recover_from_device_loss(),
+            result => result,
         }
-
-        // Advance the frame, looping back round after every MAX_FRAMES_IN_FLIGHT frames
-        self.current_frame = (self.current_frame + 1) % commands::MAX_FRAMES_IN_FLIGHT as usize;
-
-        Ok(())
     }
 
-    /// The window surface can change such that the swapchain is no longer compatible with it (e.g a window resize)
-    ///
-    /// When these events occur, we should recreate the swapchain so it is compatible with the surface
-    pub fn recreate_swapchain(&mut self) -> Result<()>
+    /// Memory use of the renderer's device, None if there is no renderer
+    pub fn memory_snapshot(&self) -> Option<allocator::MemorySnapshot>
     {
-        log!("Recreating swapchain");
-
-        log!(
-            "Window Dimensions: Width {}, Height {}",
-            project::WINDOW_WIDTH.get(),
-            project::WINDOW_HEIGHT.get()
-        );
-
-        // Wait for in process execution to finish first
-        unsafe { self.device.device_wait_idle()? };
-
-        // Delete the previous swapchain
-        self.swapchain.cleanup(&self.device);
-
-        // Update the surface details with the new surface
-        self.surface.details = presentation::get_surface_details(
-            self.physical_device.vk_physical_device,
-            self.surface.vk_surface,
-            &self.surface.loader,
-        )?;
+        self.renderer.as_ref().map(|renderer| renderer.memory_snapshot())
+    }
 
-        // Create the new swapchain
-        self.swapchain = presentation::create_swapchain(&self.instance, &self.device, &self.physical_device, &self.surface)?;
-        self.swapchain.create_framebuffers(&self.device, &self.pipeline)?;
+    /// Report the device as lost on the next call to draw_frame so recovery can be tested without a real device loss
+    pub fn simulate_device_loss(&mut self)
+    {
+        warn!("Simulating device loss");
+        self.simulate_device_loss = true;
+    }
 
+    /// A lost device can't be used again, so destroy every object created from it and the device itself then run device
+    /// selection again, as the lost device may no longer be available, and recreate everything from the retained data
+    fn recover_from_device_loss(&mut self) -> Result<()>
+    {
+        warn!("Device lost, recreating the device and its resources");
+        self.renderer = None;
+        self.renderer = Some(self.create_renderer()?);
+        log!("Recovered from device loss");
         Ok(())
     }
 }
@@ -1,11 +1,15 @@
-#![no_main]
+// Tests are run by the test harness's main rather than wWinMain
+#![cfg_attr(not(test), no_main)]
 mod graphics;
 mod log;
 mod maths;
 
 use crate::log::ProjectError;
 use std::io::Read;
-use windows::{core::*, Win32::Foundation::*, Win32::System::Console::*, Win32::UI::WindowsAndMessaging::*};
+use windows::{
+    core::*, Win32::Foundation::*, Win32::System::Console::*, Win32::UI::Input::KeyboardAndMouse::*,
+    Win32::UI::WindowsAndMessaging::*,
+};
 
 mod project
 {
@@ -15,6 +19,8 @@ mod project
     thread_local! {
         pub static WINDOW_WIDTH: std::cell::Cell<i32> = std::cell::Cell::new(640);
         pub static WINDOW_HEIGHT: std::cell::Cell<i32> = std::cell::Cell::new(480);
+        // Set by pressing F9 to test recovering from a lost device
+        pub static SIMULATE_DEVICE_LOSS: std::cell::Cell<bool> = std::cell::Cell::new(false);
     }
 }
 
@@ -60,11 +66,38 @@ unsafe extern "system" fn window_proc(hwnd: HWND, u_msg: u32, w_param: WPARAM, l
             project::WINDOW_WIDTH.set(loword(&l_param) as i32);
             project::WINDOW_HEIGHT.set(hiword(&l_param) as i32);
         }
+        WM_KEYDOWN if w_param.0 == VK_F9.0 as usize => {
+            project::SIMULATE_DEVICE_LOSS.set(true);
+        }
         _ => (),
     }
     DefWindowProcW(hwnd, u_msg, w_param, l_param)
 }
 
+/// Keep the console open until a key is pressed so the output can be read
+fn wait_for_exit()
+{
+    println!("Press any key to exit");
+    std::io::stdin().read(&mut [0]).unwrap();
+}
+
+/// Print or save the GPU capability report instead of running the app
+///
+/// The window is created but not shown so that surface formats and present modes can be reported
+fn run_gpu_report(options: &graphics::report::ReportOptions, hwnd: &HWND, h_instance: &HINSTANCE) -> i32
+{
+    let result = graphics::report::create_report(hwnd, h_instance)
+        .and_then(|report| graphics::report::write_report(&report, options));
+    if let Err(err) = result {
+        err.handle();
+        return -1;
+    }
+    if let Some(path) = &options.output_path {
+        log!("GPU report written to {}", path);
+    }
+    0
+}
+
 #[no_mangle]
 extern "system" fn wWinMain(h_instance: HINSTANCE, _h_prev_instance: HINSTANCE, _p_cmd_line: PWSTR, n_cmd_show: i32) -> i32
 {
@@ -84,6 +117,20 @@ extern "system" fn wWinMain(h_instance: HINSTANCE, _h_prev_instance: HINSTANCE,
 
         log!("Console Initialized");
 
+        // Running with the gpu-report command prints the GPU capability report instead of running the app
+        let mut args = std::env::args().skip(1);
+        let report_options = match args.next().as_deref() {
+            Some("gpu-report") => match graphics::report::ReportOptions::from_args(args) {
+                Ok(report_options) => Some(report_options),
+                Err(err) => {
+                    eprintln!("{}", err);
+                    wait_for_exit();
+                    return -1;
+                }
+            },
+            _ => None,
+        };
+
         RegisterClassW(&wc);
         let hwnd = match CreateWindowExW(
             WINDOW_EX_STYLE(0),
@@ -106,6 +153,13 @@ extern "system" fn wWinMain(h_instance: HINSTANCE, _h_prev_instance: HINSTANCE,
             }
         };
 
+        if let Some(report_options) = report_options {
+            let exit_code = run_gpu_report(&report_options, &hwnd, &h_instance);
+            let _ = DestroyWindow(hwnd);
+            wait_for_exit();
+            return exit_code;
+        }
+
         let _ = ShowWindow(hwnd, SHOW_WINDOW_CMD(n_cmd_show));
 
         let mut vk_app: graphics::vk_app::VkApp = match graphics::vk_app::VkApp::new(&hwnd, &h_instance) {
@@ -121,6 +175,9 @@ extern "system" fn wWinMain(h_instance: HINSTANCE, _h_prev_instance: HINSTANCE,
             let _ = TranslateMessage(&mut msg);
             DispatchMessageW(&mut msg);
             if GetMessageW(&mut msg, hwnd, 0, 0).0 > 0 {
+                if project::SIMULATE_DEVICE_LOSS.take() {
+                    vk_app.simulate_device_loss();
+                }
                 if let Err(err) = vk_app.draw_frame() {
                     err.handle();
                     return -1;
@@ -130,7 +187,6 @@ extern "system" fn wWinMain(h_instance: HINSTANCE, _h_prev_instance: HINSTANCE,
             }
         }
     }
-    println!("Press any key to exit");
-    std::io::stdin().read(&mut [0]).unwrap();
+    wait_for_exit();
     0
 }